@@ -6,17 +6,21 @@ use std::{
     env,
     error::Error,
     fmt,
+    fs::File,
     path::{Path, PathBuf},
     process::{self, Command, Stdio},
-    time::Instant,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 
 use probe_rs::{
     config::registry::{Registry, SelectionStrategy},
     coresight::access_ports::AccessPortError,
-    flash::download::{download_file_with_progress_reporting, Format},
-    flash::{FlashProgress, ProgressEvent},
+    flash::download::{
+        download_file_with_progress_reporting, elf_to_bin, parse_u32, run_from_ram, BinOptions,
+        DownloadOptions, Format,
+    },
+    flash::{FlashProgress, FlashReport, ProgressEvent},
     probe::{
         daplink, stlink, DebugProbe, DebugProbeError, DebugProbeType, MasterProbe, WireProtocol,
     },
@@ -34,10 +38,38 @@ struct Opt {
         long = "chip-description-path"
     )]
     chip_description_path: Option<String>,
-    #[structopt(name = "nrf-recover", long = "nrf-recover")]
-    nrf_recover: bool,
+    #[structopt(name = "recover", long = "recover", alias = "nrf-recover")]
+    recover: bool,
     #[structopt(name = "list-chips", long = "list-chips")]
     list_chips: bool,
+    #[structopt(name = "chip-erase", long = "chip-erase")]
+    chip_erase: bool,
+    #[structopt(name = "keep-unwritten", long = "keep-unwritten")]
+    keep_unwritten: bool,
+    #[structopt(name = "verify", long = "verify")]
+    verify: bool,
+    #[structopt(name = "dry-run", long = "dry-run")]
+    dry_run: bool,
+    #[structopt(name = "skip-unchanged", long = "skip-unchanged")]
+    skip_unchanged: bool,
+    #[structopt(name = "ram", long = "ram")]
+    ram: bool,
+    #[structopt(name = "format", long = "format", default_value = "elf")]
+    format: Format,
+    #[structopt(name = "base-address", long = "base-address", parse(try_from_str = parse_u32))]
+    base_address: Option<u32>,
+    #[structopt(name = "skip", long = "skip", default_value = "0", parse(try_from_str = parse_u32))]
+    skip: u32,
+    #[structopt(name = "report", long = "report")]
+    report: bool,
+    #[structopt(name = "report-json", long = "report-json", parse(from_os_str))]
+    report_json: Option<PathBuf>,
+    #[structopt(
+        name = "message-format",
+        long = "message-format",
+        default_value = "human"
+    )]
+    message_format: MessageFormat,
 
     // `cargo build` arguments
     #[structopt(name = "binary", long = "bin")]
@@ -60,6 +92,30 @@ struct Opt {
     features: Vec<String>,
 }
 
+/// The format of the messages which report the flash progress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageFormat {
+    /// Progress bars and colored messages.
+    Human,
+    /// One JSON object per progress event on stdout.
+    Json,
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!(
+                "'{}' is not a valid message format. Choose from [human, json].",
+                s
+            )),
+        }
+    }
+}
+
 fn main() {
     pretty_env_logger::init();
     match main_try() {
@@ -90,6 +146,16 @@ fn main_try() -> Result<(), failure::Error> {
         std::process::exit(0);
     }
 
+    // The build artifact is an ELF file, which can only be converted into a binary.
+    match opt.format {
+        Format::Elf | Format::Auto | Format::Bin(_) => (),
+        _ => {
+            return Err(format_err!(
+                "cargo flash builds an ELF file, which can only be flashed as elf or bin"
+            ))
+        }
+    }
+
     args.remove(0); // Remove executable name
 
     // Remove possible `--chip <chip>` arguments as cargo build does not understand it.
@@ -128,11 +194,47 @@ fn main_try() -> Result<(), failure::Error> {
         args.remove(index);
     }
 
-    // Remove possible `--nrf-recover` argument as cargo build does not understand it.
-    if let Some(index) = args.iter().position(|x| x.starts_with("--nrf-recover")) {
+    // Remove possible `--recover` argument as cargo build does not understand it.
+    if let Some(index) = args
+        .iter()
+        .position(|x| x == "--recover" || x == "--nrf-recover")
+    {
         args.remove(index);
     }
 
+    // Remove possible download flags as cargo build does not understand them.
+    for flag in &[
+        "--chip-erase",
+        "--keep-unwritten",
+        "--verify",
+        "--dry-run",
+        "--skip-unchanged",
+        "--report",
+        "--ram",
+    ] {
+        if let Some(index) = args.iter().position(|x| x == flag) {
+            args.remove(index);
+        }
+    }
+
+    // Remove possible download options with a value as cargo build does not understand them.
+    for option in &[
+        "--format",
+        "--base-address",
+        "--skip",
+        "--report-json",
+        "--message-format",
+    ] {
+        if let Some(index) = args.iter().position(|x| x == option) {
+            args.remove(index);
+            args.remove(index);
+        }
+        let prefix = format!("{}=", option);
+        if let Some(index) = args.iter().position(|x| x.starts_with(&prefix)) {
+            args.remove(index);
+        }
+    }
+
     let status = Command::new("cargo")
         .arg("build")
         .args(args)
@@ -178,7 +280,10 @@ fn main_try() -> Result<(), failure::Error> {
         None => panic!(),
     };
 
-    println!("    {} {}", "Flashing".green().bold(), path_str);
+    if opt.message_format == MessageFormat::Human {
+        let action = if opt.ram { "Loading" } else { "Flashing" };
+        println!("    {} {}", action.green().bold(), path_str);
+    }
 
     let mut list = daplink::tools::list_daplink_devices();
     list.extend(stlink::tools::list_stlink_devices());
@@ -193,20 +298,13 @@ fn main_try() -> Result<(), failure::Error> {
 
             link.attach(Some(WireProtocol::Swd))?;
 
-            let mut probe = MasterProbe::from_specific_probe(link);
-            if opt.nrf_recover {
-                probe.nrf_recover()?;
-            }
-            probe
+            MasterProbe::from_specific_probe(link)
         }
         DebugProbeType::STLink => {
             let mut link = stlink::STLink::new_from_probe_info(&device)?;
 
             link.attach(Some(WireProtocol::Swd))?;
 
-            if opt.nrf_recover {
-                return Err(format_err!("It isn't possible to recover with a ST-Link"));
-            }
             MasterProbe::from_specific_probe(link)
         }
     };
@@ -225,12 +323,118 @@ fn main_try() -> Result<(), failure::Error> {
     let target = registry.get_target(strategy)?;
 
     let mut session = Session::new(target, probe);
+    if opt.recover {
+        session.unlock()?;
+    }
+    session.attach()?;
 
     // Start timer.
     let instant = Instant::now();
 
     let mm = session.target.memory_map.clone();
 
+    // An image linked for RAM is written and started directly, without touching the flash.
+    if opt.ram {
+        run_from_ram(&mut session, &path, &mm)
+            .map_err(|e| format_err!("failed to run {} from RAM: {}", path_str, e))?;
+
+        if opt.message_format == MessageFormat::Human {
+            println!(
+                "    {} in {}s",
+                "Running".green().bold(),
+                instant.elapsed().as_millis() as f32 / 1000.0
+            );
+        }
+        return Ok(());
+    }
+
+    let (progress, progress_thread_handle) = match opt.message_format {
+        // Every event is printed as one JSON object per line.
+        MessageFormat::Json => {
+            let progress = FlashProgress::new(|event| match serde_json::to_string(&event) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Failed to serialize the progress event: {}", e),
+            });
+            (progress, None)
+        }
+        MessageFormat::Human => {
+            let (progress, multi_progress) = progress_bars(opt.verify);
+
+            // Make the multi progresses print.
+            // indicatif requires this in a separate thread as this join is a blocking op,
+            // but is required for printing multiprogress.
+            let handle = std::thread::spawn(move || {
+                multi_progress.join().unwrap();
+            });
+            (progress, Some(handle))
+        }
+    };
+
+    // A binary is converted from the ELF file and put next to it, like `objcopy -O binary` does.
+    // It is flashed at the address of the ELF file, unless another base address is given.
+    let (file, format) = match opt.format {
+        Format::Bin(_) => {
+            let (address, image) = elf_to_bin(&path)?;
+            let bin_path = path.with_extension("bin");
+            std::fs::write(&bin_path, image)?;
+            let base_address = opt.base_address.unwrap_or(address);
+            (
+                bin_path,
+                Format::Bin(BinOptions::new(Some(base_address), opt.skip)),
+            )
+        }
+        _ => (path.clone(), Format::Elf),
+    };
+
+    let options = DownloadOptions {
+        do_chip_erase: opt.chip_erase,
+        keep_unwritten: opt.keep_unwritten,
+        verify: opt.verify,
+        dry_run: opt.dry_run,
+        skip_unchanged: opt.skip_unchanged,
+    };
+
+    let report = download_file_with_progress_reporting(
+        &mut session,
+        &file,
+        format,
+        &mm,
+        &options,
+        &progress,
+    )
+    .map_err(|e| format_err!("failed to flash {}: {}", path_str, e))?;
+
+    // We don't care if we cannot join this thread.
+    if let Some(handle) = progress_thread_handle {
+        let _ = handle.join();
+    }
+
+    // Stop timer.
+    let elapsed = instant.elapsed();
+    if opt.message_format == MessageFormat::Human {
+        println!(
+            "    {} in {}s",
+            "Finished".green().bold(),
+            elapsed.as_millis() as f32 / 1000.0
+        );
+    }
+
+    if opt.report {
+        print_report(&report);
+    }
+    if let Some(path) = &opt.report_json {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &report)?;
+    }
+
+    session.core().reset(&mut session.probe)?;
+
+    Ok(())
+}
+
+/// Creates the progress bars for erasing, programming and, if requested, verifying,
+/// and returns the progress handler which updates them.
+fn progress_bars(verify: bool) -> (FlashProgress, indicatif::MultiProgress) {
     // Create progress bars.
     let multi_progress = indicatif::MultiProgress::new(); //with_draw_target(indicatif::ProgressDrawTarget::stdout_nohz());
     let style = indicatif::ProgressStyle::default_bar()
@@ -245,21 +449,34 @@ fn main_try() -> Result<(), failure::Error> {
 
     // Create a new progress bar for the program progress.
     let program_progress = multi_progress.add(indicatif::ProgressBar::new(0));
-    program_progress.set_style(style);
+    program_progress.set_style(style.clone());
     program_progress.set_message("Programming pages");
 
+    // Create a new progress bar for the verify progress, if verification is requested.
+    let verify_progress = if verify {
+        let verify_progress = multi_progress.add(indicatif::ProgressBar::new(0));
+        verify_progress.set_style(style);
+        verify_progress.set_message("Verifying pages  ");
+        Some(verify_progress)
+    } else {
+        None
+    };
+
     // Register callback to update the progress.
     let progress = FlashProgress::new(move |event| {
         use ProgressEvent::*;
         match event {
             Initialized {
                 total_pages,
-                total_sectors,
-                sector_size,
+                total_sector_size,
                 page_size,
+                ..
             } => {
-                erase_progress.set_length(total_sectors as u64 * sector_size as u64);
+                erase_progress.set_length(total_sector_size as u64);
                 program_progress.set_length(total_pages as u64 * page_size as u64);
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.set_length(total_pages as u64 * page_size as u64);
+                }
             }
             StartedFlashing => {
                 program_progress.enable_steady_tick(100);
@@ -269,10 +486,10 @@ fn main_try() -> Result<(), failure::Error> {
                 erase_progress.enable_steady_tick(100);
                 erase_progress.reset_elapsed();
             }
-            PageFlashed { size, time: _ } => {
+            PageFlashed { size, .. } => {
                 program_progress.inc(size as u64);
             }
-            SectorErased { size, time: _ } => {
+            SectorErased { size, .. } => {
                 erase_progress.inc(size as u64);
             }
             FinishedErasing => {
@@ -281,39 +498,105 @@ fn main_try() -> Result<(), failure::Error> {
             FinishedProgramming => {
                 program_progress.finish();
             }
+            // Skipped sectors are already excluded from the totals.
+            SectorSkipped { .. } => (),
+            StartedRegion { .. } => (),
+            StartedVerifying => {
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.enable_steady_tick(100);
+                    verify_progress.reset_elapsed();
+                }
+            }
+            PageVerified { size, .. } => {
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.inc(size as u64);
+                }
+            }
+            FinishedVerifying => {
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.finish();
+                }
+            }
+            FailedErasing => {
+                erase_progress.abandon();
+            }
+            FailedProgramming => {
+                program_progress.abandon();
+            }
+            FailedVerifying => {
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.abandon();
+                }
+            }
         }
     });
 
-    // Make the multi progresses print.
-    // indicatif requires this in a separate thread as this join is a blocking op,
-    // but is required for printing multiprogress.
-    let progress_thread_handle = std::thread::spawn(move || {
-        multi_progress.join().unwrap();
-    });
+    (progress, multi_progress)
+}
 
-    download_file_with_progress_reporting(
-        &mut session,
-        std::path::Path::new(&path_str.to_string().as_str()),
-        Format::Elf,
-        &mm,
-        &progress,
-    )
-    .map_err(|e| format_err!("failed to flash {}: {}", path_str, e))?;
+/// Prints the statistics of the flashing, with a breakdown per flash region.
+fn print_report(report: &FlashReport) {
+    let phase = |name: &str, bytes: u64, time: Duration, throughput: f64| {
+        println!(
+            "        {:<10} {:>8} bytes in {:>7.3}s ({:.1} kB/s)",
+            name,
+            bytes,
+            time.as_secs_f64(),
+            throughput / 1024.0
+        );
+    };
 
-    // We don't care if we cannot join this thread.
-    let _ = progress_thread_handle.join();
+    for region in &report.regions {
+        println!(
+            "    {} 0x{:08x}..0x{:08x} with {}",
+            "Region".green().bold(),
+            region.range.start,
+            region.range.end,
+            region.algorithm
+        );
+        phase(
+            "Erased",
+            region.bytes_erased,
+            region.erase_time,
+            region.erase_throughput(),
+        );
+        phase(
+            "Programmed",
+            region.bytes_programmed,
+            region.program_time,
+            region.program_throughput(),
+        );
+        if region.bytes_verified > 0 {
+            phase(
+                "Verified",
+                region.bytes_verified,
+                region.verify_time,
+                region.verify_throughput(),
+            );
+        }
+        println!(
+            "        {:<10} {:>8} bytes",
+            "Skipped", region.bytes_skipped
+        );
+        println!(
+            "        {} algorithm calls, chip erase: {}, double buffering: {}",
+            region.algorithm_calls, region.chip_erase, region.double_buffering
+        );
+    }
 
-    // Stop timer.
-    let elapsed = instant.elapsed();
-    println!(
-        "    {} in {}s",
-        "Finished".green().bold(),
-        elapsed.as_millis() as f32 / 1000.0
+    println!("    {}", "Total".green().bold());
+    phase(
+        "Erased",
+        report.bytes_erased(),
+        report.erase_time(),
+        report.erase_throughput(),
+    );
+    phase(
+        "Programmed",
+        report.bytes_programmed(),
+        report.program_time(),
+        report.program_throughput(),
     );
-
-    session.target.core.reset(&mut session.probe)?;
-
-    Ok(())
 }
 
 fn print_families() {
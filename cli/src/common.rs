@@ -1,15 +1,25 @@
 use crate::SharedOptions;
 
 use probe_rs::{
-    config::registry::{Registry, RegistryError, SelectionStrategy},
+    config::{
+        chip::DEFAULT_SCS_BASE,
+        registry::{Registry, RegistryError, SelectionStrategy},
+        target::TargetCore,
+    },
     cores::m0::FakeM0,
     coresight::access_ports::AccessPortError,
-    flash::download::FileDownloadError,
+    flash::{
+        config::ConfigError,
+        download::{parse_u32, FileDownloadError},
+        erase::EraseError,
+        readback::ReadbackError,
+    },
     probe::{
         daplink, stlink, DebugProbe, DebugProbeError, DebugProbeType, FakeProbe, MasterProbe,
         WireProtocol,
     },
-    session::Session,
+    sequences::{Generic, SequenceError},
+    session::{Session, SessionError},
     target::info::{self, ChipInfo},
 };
 
@@ -18,6 +28,7 @@ use ron;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
+use std::ops::Range;
 use std::path::Path;
 
 #[derive(Debug)]
@@ -27,8 +38,15 @@ pub enum CliError {
     AccessPort(AccessPortError),
     StdIO(std::io::Error),
     FileDownload(FileDownloadError),
+    Erase(EraseError),
+    Readback(ReadbackError),
+    Config(ConfigError),
+    Sequence(SequenceError),
     RegistryError(RegistryError),
+    Session(SessionError),
     MissingArgument,
+    InvalidArgument(String),
+    UnknownRegister(String),
     UnableToOpenProbe,
 }
 
@@ -42,9 +60,16 @@ impl Error for CliError {
             AccessPort(ref e) => Some(e),
             StdIO(ref e) => Some(e),
             RegistryError(ref e) => Some(e),
+            Session(ref e) => Some(e),
             MissingArgument => None,
+            InvalidArgument(_) => None,
+            UnknownRegister(_) => None,
             UnableToOpenProbe => None,
             FileDownload(ref e) => Some(e),
+            Erase(ref e) => Some(e),
+            Readback(ref e) => Some(e),
+            Config(ref e) => Some(e),
+            Sequence(ref e) => Some(e),
         }
     }
 }
@@ -59,8 +84,15 @@ impl fmt::Display for CliError {
             AccessPort(ref e) => e.fmt(f),
             StdIO(ref e) => e.fmt(f),
             FileDownload(ref e) => e.fmt(f),
+            Erase(ref e) => e.fmt(f),
+            Readback(ref e) => e.fmt(f),
+            Config(ref e) => e.fmt(f),
+            Sequence(ref e) => e.fmt(f),
             RegistryError(ref e) => e.fmt(f),
+            Session(ref e) => e.fmt(f),
             MissingArgument => write!(f, "Command expected more arguments."),
+            InvalidArgument(ref arg) => write!(f, "Invalid argument '{}'.", arg),
+            UnknownRegister(ref name) => write!(f, "Unknown register '{}'.", name),
             UnableToOpenProbe => write!(f, "Unable to open probe."),
         }
     }
@@ -96,13 +128,46 @@ impl From<RegistryError> for CliError {
     }
 }
 
+impl From<SessionError> for CliError {
+    fn from(error: SessionError) -> Self {
+        CliError::Session(error)
+    }
+}
+
+impl From<EraseError> for CliError {
+    fn from(error: EraseError) -> Self {
+        CliError::Erase(error)
+    }
+}
+
+impl From<ReadbackError> for CliError {
+    fn from(error: ReadbackError) -> Self {
+        CliError::Readback(error)
+    }
+}
+
+impl From<ConfigError> for CliError {
+    fn from(error: ConfigError) -> Self {
+        CliError::Config(error)
+    }
+}
+
+impl From<SequenceError> for CliError {
+    fn from(error: SequenceError) -> Self {
+        CliError::Sequence(error)
+    }
+}
+
 impl From<FileDownloadError> for CliError {
     fn from(error: FileDownloadError) -> Self {
         CliError::FileDownload(error)
     }
 }
 
-pub(crate) fn open_probe(index: Option<usize>) -> Result<MasterProbe, CliError> {
+pub(crate) fn open_probe(
+    index: Option<usize>,
+    protocol: WireProtocol,
+) -> Result<MasterProbe, CliError> {
     let mut list = daplink::tools::list_daplink_devices();
     list.extend(stlink::tools::list_stlink_devices());
 
@@ -122,14 +187,14 @@ pub(crate) fn open_probe(index: Option<usize>) -> Result<MasterProbe, CliError>
         DebugProbeType::DAPLink => {
             let mut link = daplink::DAPLink::new_from_probe_info(&device)?;
 
-            link.attach(Some(WireProtocol::Swd))?;
+            link.attach(Some(protocol))?;
 
             MasterProbe::from_specific_probe(link)
         }
         DebugProbeType::STLink => {
             let mut link = stlink::STLink::new_from_probe_info(&device)?;
 
-            link.attach(Some(WireProtocol::Swd))?;
+            link.attach(Some(protocol))?;
 
             MasterProbe::from_specific_probe(link)
         }
@@ -145,7 +210,7 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<(),
 where
     for<'a> F: FnOnce(Session) -> Result<(), CliError>,
 {
-    let mut probe = open_probe(shared_options.n)?;
+    let mut probe = open_probe(shared_options.n, shared_options.protocol)?;
 
     let strategy = if let Some(identifier) = &shared_options.target {
         SelectionStrategy::TargetIdentifier(identifier.into())
@@ -157,7 +222,11 @@ where
 
     let target = registry.get_target(strategy)?;
 
-    let session = Session::new(target, probe);
+    let mut session = Session::new(target, probe);
+    if shared_options.recover {
+        session.unlock()?;
+    }
+    session.attach()?;
 
     f(session)
 }
@@ -185,9 +254,33 @@ where
 
     let mut target = registry.get_target(strategy)?;
 
-    target.core = Box::new(core);
+    // A dump only contains the state of a single core.
+    target.cores = vec![TargetCore {
+        name: "dump".to_owned(),
+        core: Box::new(core),
+        ap: 0,
+        scs_base: DEFAULT_SCS_BASE,
+    }];
+    // There is no chip to run the vendor specific steps on.
+    target.debug_sequence = Box::new(Generic);
 
-    let session = Session::new(target, probe);
+    let mut session = Session::new(target, probe);
+    session.attach()?;
 
     f(session)
 }
+
+/// Parses a value given either in hexadecimal with a `0x` prefix, or in decimal.
+pub(crate) fn parse_value(value: &str) -> Result<u32, CliError> {
+    parse_u32(value).map_err(|_| CliError::InvalidArgument(value.to_string()))
+}
+
+/// Parses an address range of the form `start..end`, e.g. `0x8000..0x10000`.
+pub(crate) fn parse_range(value: &str) -> Result<Range<u32>, CliError> {
+    let mut parts = value.splitn(2, "..");
+
+    match (parts.next(), parts.next()) {
+        (Some(start), Some(end)) => Ok(parse_value(start)?..parse_value(end)?),
+        _ => Err(CliError::InvalidArgument(value.to_string())),
+    }
+}
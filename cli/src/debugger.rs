@@ -1,6 +1,9 @@
-use crate::common::CliError;
+use crate::common::{parse_value, CliError};
 
-use probe_rs::{cores::CortexDump, coresight::memory::MI, debug::DebugInfo, session::Session};
+use probe_rs::{
+    cores::CortexDump, coresight::memory::MI, debug::DebugInfo, session::Session,
+    target::RegisterGroup,
+};
 
 use capstone::Capstone;
 
@@ -22,11 +25,7 @@ impl DebugCli {
             help_text: "Step a single instruction",
 
             function: |cli_data, _args| {
-                let cpu_info = cli_data
-                    .session
-                    .target
-                    .core
-                    .step(&mut cli_data.session.probe)?;
+                let cpu_info = cli_data.session.core().step(&mut cli_data.session.probe)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
                 Ok(CliState::Continue)
@@ -38,13 +37,17 @@ impl DebugCli {
             help_text: "Stop the CPU",
 
             function: |cli_data, _args| {
-                let cpu_info = cli_data
-                    .session
-                    .target
-                    .core
-                    .halt(&mut cli_data.session.probe)?;
+                let cpu_info = cli_data.session.core().halt(&mut cli_data.session.probe)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
+                if let Some(state) = cli_data
+                    .session
+                    .core()
+                    .security_state(&mut cli_data.session.probe)?
+                {
+                    println!("Core is in the {:?} state", state);
+                }
+
                 let mut code = [0u8; 16 * 2];
 
                 cli_data.session.probe.read_block8(cpu_info.pc, &mut code)?;
@@ -67,11 +70,7 @@ impl DebugCli {
             help_text: "Resume execution of the CPU",
 
             function: |cli_data, _args| {
-                cli_data
-                    .session
-                    .target
-                    .core
-                    .run(&mut cli_data.session.probe)?;
+                cli_data.session.core().run(&mut cli_data.session.probe)?;
 
                 Ok(CliState::Continue)
             },
@@ -122,13 +121,11 @@ impl DebugCli {
 
                 cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .enable_breakpoints(&mut cli_data.session.probe, true)?;
                 cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .set_breakpoint(&mut cli_data.session.probe, address)?;
 
                 Ok(CliState::Continue)
@@ -140,11 +137,10 @@ impl DebugCli {
             help_text: "Show backtrace",
 
             function: |cli_data, _args| {
-                let regs = cli_data.session.target.core.registers();
+                let regs = cli_data.session.core().registers();
                 let program_counter = cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .read_core_reg(&mut cli_data.session.probe, regs.PC)?;
 
                 if let Some(di) = &cli_data.debug_info {
@@ -161,21 +157,100 @@ impl DebugCli {
 
         cli.add_command(Command {
             name: "regs",
-            help_text: "Show CPU register values",
+            help_text:
+                "Show CPU registers, optionally of one group (general, special, fp, security, all)",
 
-            function: |cli_data, _args| {
-                let mut regs = [0u32; 15];
+            function: |cli_data, args| {
+                let group = args.first().copied();
+                let core = cli_data.session.core();
+
+                for register in core.register_file() {
+                    let show = match group {
+                        // Floating point and security registers are not present on all cores,
+                        // so they are only shown on request.
+                        None => match register.group {
+                            RegisterGroup::General | RegisterGroup::Special => true,
+                            RegisterGroup::FloatingPoint | RegisterGroup::Security => false,
+                        },
+                        Some("all") => true,
+                        Some(group) => register.group.name() == group,
+                    };
+
+                    if show {
+                        let value = core.read_register(&mut cli_data.session.probe, register)?;
+                        println!("{:<10} {:#010x}", register.name, value);
+                    }
+                }
 
-                for i in 0..15 {
-                    regs[i as usize] = cli_data
-                        .session
-                        .target
-                        .core
-                        .read_core_reg(&mut cli_data.session.probe, i.into())?;
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "reg",
+            help_text: "Show the value of a single CPU register, e.g. 'reg sp'",
+
+            function: |cli_data, args| {
+                let name = args.first().ok_or(CliError::MissingArgument)?;
+                let core = cli_data.session.core();
+
+                let register = core
+                    .register_by_name(name)
+                    .ok_or_else(|| CliError::UnknownRegister(name.to_string()))?;
+
+                let value = core.read_register(&mut cli_data.session.probe, register)?;
+                println!("{:<10} {:#010x}", register.name, value);
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "set",
+            help_text: "Set the value of a CPU register, e.g. 'set reg r0 0x1234'",
+
+            function: |cli_data, args| {
+                match args.first() {
+                    Some(&"reg") => (),
+                    Some(other) => return Err(CliError::InvalidArgument(other.to_string())),
+                    None => return Err(CliError::MissingArgument),
+                }
+
+                let name = args.get(1).ok_or(CliError::MissingArgument)?;
+                let value = parse_value(args.get(2).ok_or(CliError::MissingArgument)?)?;
+                let core = cli_data.session.core();
+
+                let register = core
+                    .register_by_name(name)
+                    .ok_or_else(|| CliError::UnknownRegister(name.to_string()))?;
+
+                core.write_register(&mut cli_data.session.probe, register, value)?;
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "core",
+            help_text: "Show the cores of the target, or select a core by its index",
+
+            function: |cli_data, args| {
+                if let Some(index) = args.first() {
+                    let index = index
+                        .parse::<usize>()
+                        .map_err(|_| CliError::InvalidArgument(index.to_string()))?;
+
+                    cli_data.session.select_core(index)?;
                 }
 
-                for (i, val) in regs.iter().enumerate() {
-                    println!("Register {}: {:#08x}", i, val);
+                let current_core = cli_data.session.current_core();
+
+                for (index, core) in cli_data.session.cores().iter().enumerate() {
+                    let marker = if index == current_core { "*" } else { " " };
+                    println!(
+                        "{} {}: {} (AP {}, SCS at {:#010x})",
+                        marker, index, core.name, core.ap, core.scs_base
+                    );
                 }
 
                 Ok(CliState::Continue)
@@ -193,17 +268,15 @@ impl DebugCli {
 
                 let stack_top: u32 = 0x2000_0000 + 0x4_000;
 
-                let regs = cli_data.session.target.core.registers();
+                let regs = cli_data.session.core().registers();
 
                 let stack_bot: u32 = cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .read_core_reg(&mut cli_data.session.probe, regs.SP)?;
                 let pc: u32 = cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .read_core_reg(&mut cli_data.session.probe, regs.PC)?;
 
                 let mut stack = vec![0u8; (stack_top - stack_bot) as usize];
@@ -218,16 +291,14 @@ impl DebugCli {
                 for i in 0..12 {
                     dump.regs[i as usize] = cli_data
                         .session
-                        .target
-                        .core
+                        .core()
                         .read_core_reg(&mut cli_data.session.probe, i.into())?;
                 }
 
                 dump.regs[13] = stack_bot;
                 dump.regs[14] = cli_data
                     .session
-                    .target
-                    .core
+                    .core()
                     .read_core_reg(&mut cli_data.session.probe, regs.LR)?;
                 dump.regs[15] = pc;
 
@@ -249,19 +320,11 @@ impl DebugCli {
             help_text: "Reset the CPU",
 
             function: |cli_data, _args| {
-                cli_data
-                    .session
-                    .target
-                    .core
-                    .halt(&mut cli_data.session.probe)?;
+                cli_data.session.core().halt(&mut cli_data.session.probe)?;
 
                 // Enable vector catch after reset (set bit 1 in DEMCR register)
                 cli_data.session.probe.write32(0xE000_EDFC, 1)?;
-                cli_data
-                    .session
-                    .target
-                    .core
-                    .reset(&mut cli_data.session.probe)?;
+                cli_data.session.core().reset(&mut cli_data.session.probe)?;
 
                 Ok(CliState::Continue)
             },
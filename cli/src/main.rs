@@ -2,14 +2,26 @@ mod common;
 mod debugger;
 mod info;
 
-use common::{with_device, with_dump, CliError};
+use common::{parse_range, parse_value, with_device, with_dump, CliError};
 use debugger::CliState;
 
 use probe_rs::{
+    config::{
+        registry::{validate_yaml, Registry, RegistryError},
+        validation::Validator,
+    },
     coresight::memory::MI,
     debug::DebugInfo,
-    flash::download::{download_file, Format},
-    probe::{daplink, stlink, DebugProbeInfo},
+    flash::{
+        config::{read_field, write_fields},
+        download::{
+            download_file_with_progress_reporting, BinOptions, DownloadOptions, Format, Uf2Options,
+        },
+        erase::{erase_range, erase_sectors},
+        readback::{flash_ranges, read_memory, write_segments, OutputFormat},
+        FlashProgress, ProgressEvent,
+    },
+    probe::{daplink, stlink, DebugProbeInfo, WireProtocol},
 };
 
 use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
@@ -18,8 +30,10 @@ use rustyline::Editor;
 use structopt::StructOpt;
 
 use std::fs;
+use std::io::Write;
 use std::num::ParseIntError;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
@@ -84,6 +98,60 @@ enum CLI {
 
         /// The path to the file to be downloaded to the flash
         path: String,
+
+        #[structopt(flatten)]
+        download: DownloadFlags,
+    },
+    /// Erase the flash of the attached target
+    #[structopt(name = "erase")]
+    Erase {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Erase the entire chip, with the mass erase of the target if it has one
+        #[structopt(long = "all", alias = "chip-erase")]
+        all: bool,
+
+        /// Erase all sectors which overlap with the range, e.g. 0x8000..0x10000
+        #[structopt(long = "range", parse(try_from_str = parse_range))]
+        range: Option<Range<u32>>,
+
+        /// Erase the sector which contains the address
+        #[structopt(long = "sector", parse(try_from_str = parse_value))]
+        sector: Vec<u32>,
+    },
+    /// Read the flash of the attached target into a file
+    #[structopt(name = "read-flash")]
+    ReadFlash {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The range to read, e.g. 0x8000..0x10000. If left open, all flash regions are read
+        #[structopt(long = "range", parse(try_from_str = parse_range))]
+        range: Option<Range<u32>>,
+
+        /// The format of the output file, one of bin, hex or elf
+        #[structopt(long = "format", default_value = "bin")]
+        format: OutputFormat,
+
+        /// The path of the output file
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Read or write the config fields of the attached target, such as option bytes
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        #[structopt(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Check target descriptions for mistakes
+    #[structopt(name = "target")]
+    Target {
+        #[structopt(subcommand)]
+        command: TargetCommand,
     },
     #[structopt(name = "trace")]
     Trace {
@@ -96,6 +164,132 @@ enum CLI {
     },
 }
 
+#[derive(StructOpt)]
+enum ConfigCommand {
+    /// Print the value of a config field, e.g. UICR.APPROTECT. If left open, all fields are printed
+    #[structopt(name = "get")]
+    Get { field: Option<String> },
+    /// Write a value to a config field, e.g. UICR.APPROTECT 0x00
+    #[structopt(name = "set")]
+    Set {
+        field: String,
+        #[structopt(parse(try_from_str = parse_value))]
+        value: u32,
+        /// Write fields which can lock out the debugger, possibly for good, e.g. the readout protection
+        #[structopt(long = "force")]
+        force: bool,
+    },
+}
+
+#[derive(StructOpt)]
+enum TargetCommand {
+    /// Validate target description files. If no file is given, the built-in targets are validated
+    #[structopt(name = "validate")]
+    Validate {
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Options which control how a file is flashed
+#[derive(StructOpt)]
+struct DownloadFlags {
+    /// Erase the entire chip instead of only the touched sectors
+    #[structopt(long = "chip-erase")]
+    chip_erase: bool,
+
+    /// Restore the bytes of erased sectors which are not overwritten
+    #[structopt(long = "keep-unwritten")]
+    keep_unwritten: bool,
+
+    /// Verify the flash contents after programming
+    #[structopt(long = "verify")]
+    verify: bool,
+
+    /// Only report what would be flashed, without erasing or programming anything
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Leave out sectors which already contain the data to be flashed
+    #[structopt(long = "skip-unchanged")]
+    skip_unchanged: bool,
+
+    /// The format of the file, one of elf, hex, bin, srec, uf2 or auto
+    #[structopt(long = "format", default_value = "auto")]
+    format: Format,
+
+    /// The address a binary file is put at. Defaults to the start of the boot flash
+    #[structopt(long = "base-address", parse(try_from_str = parse_value))]
+    base_address: Option<u32>,
+
+    /// The number of bytes to skip at the start of a binary file
+    #[structopt(long = "skip", default_value = "0", parse(try_from_str = parse_value))]
+    skip: u32,
+
+    /// The family ID of the blocks to flash from an UF2 file
+    #[structopt(long = "family-id", parse(try_from_str = parse_value))]
+    family_id: Option<u32>,
+
+    /// The format of the progress messages, either human or json
+    #[structopt(long = "message-format", default_value = "human")]
+    message_format: MessageFormat,
+}
+
+/// The format of the messages which report the flash progress
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageFormat {
+    /// A message when erasing, programming or verifying is finished.
+    Human,
+    /// One JSON object per progress event on stdout.
+    Json,
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!(
+                "'{}' is not a valid message format. Choose from [human, json].",
+                s
+            )),
+        }
+    }
+}
+
+impl DownloadFlags {
+    /// Returns the file format, including the options of a binary or UF2 file.
+    ///
+    /// If the format is detected automatically, binary options imply a binary file
+    /// and a family ID implies an UF2 file.
+    fn format(&self) -> Format {
+        let bin = Format::Bin(BinOptions::new(self.base_address, self.skip));
+        let uf2 = Format::Uf2(Uf2Options::new(self.family_id));
+
+        match self.format {
+            Format::Bin(_) => bin,
+            Format::Uf2(_) => uf2,
+            Format::Auto if self.base_address.is_some() || self.skip != 0 => bin,
+            Format::Auto if self.family_id.is_some() => uf2,
+            format => format,
+        }
+    }
+}
+
+impl From<&DownloadFlags> for DownloadOptions {
+    fn from(flags: &DownloadFlags) -> Self {
+        DownloadOptions {
+            do_chip_erase: flags.chip_erase,
+            keep_unwritten: flags.keep_unwritten,
+            verify: flags.verify,
+            dry_run: flags.dry_run,
+            skip_unchanged: flags.skip_unchanged,
+        }
+    }
+}
+
 /// Shared options for all commands which use a specific probe
 #[derive(StructOpt)]
 struct SharedOptions {
@@ -106,6 +300,14 @@ struct SharedOptions {
     /// The target to be selected.
     #[structopt(short, long)]
     target: Option<String>,
+
+    /// The protocol used to talk to the target, either swd or jtag.
+    #[structopt(long = "protocol", default_value = "swd")]
+    protocol: WireProtocol,
+
+    /// Unlock the target before it is used, which usually erases the entire chip.
+    #[structopt(long = "recover", alias = "nrf-recover")]
+    recover: bool,
 }
 
 fn main() {
@@ -120,7 +322,33 @@ fn main() {
         CLI::Reset { shared, assert } => reset_target_of_device(&shared, assert),
         CLI::Debug { shared, exe, dump } => debug(&shared, exe, dump),
         CLI::Dump { shared, loc, words } => dump_memory(&shared, loc, words),
-        CLI::Download { shared, path } => download_program_fast(&shared, &path),
+        CLI::Download {
+            shared,
+            path,
+            download,
+        } => download_program_fast(
+            &shared,
+            &path,
+            download.format(),
+            &(&download).into(),
+            download.message_format,
+        ),
+        CLI::Erase {
+            shared,
+            all,
+            range,
+            sector,
+        } => erase_flash(&shared, all, range, &sector),
+        CLI::ReadFlash {
+            shared,
+            range,
+            format,
+            output,
+        } => read_flash(&shared, range, format, &output),
+        CLI::Config { shared, command } => config(&shared, &command),
+        CLI::Target {
+            command: TargetCommand::Validate { files },
+        } => validate_targets(&files),
         CLI::Trace { shared, loc } => trace_u32_on_target(&shared, loc),
     };
 
@@ -174,13 +402,189 @@ fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(
     })
 }
 
-fn download_program_fast(shared_options: &SharedOptions, path: &str) -> Result<(), CliError> {
+fn download_program_fast(
+    shared_options: &SharedOptions,
+    path: &str,
+    format: Format,
+    options: &DownloadOptions,
+    message_format: MessageFormat,
+) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
         // Start timer.
         // let instant = Instant::now();
 
         let mm = session.target.memory_map.clone();
-        download_file(&mut session, std::path::Path::new(&path), Format::Elf, &mm)?;
+        let progress = match message_format {
+            MessageFormat::Human => FlashProgress::new(|event| match event {
+                ProgressEvent::FinishedErasing => println!("Erased the flash."),
+                ProgressEvent::FinishedProgramming => println!("Programmed the flash."),
+                ProgressEvent::FinishedVerifying => println!("Verified the flash."),
+                _ => (),
+            }),
+            // Every event is printed as one JSON object per line.
+            MessageFormat::Json => {
+                FlashProgress::new(|event| match serde_json::to_string(&event) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => eprintln!("Failed to serialize the progress event: {}", e),
+                })
+            }
+        };
+        download_file_with_progress_reporting(
+            &mut session,
+            std::path::Path::new(&path),
+            format,
+            &mm,
+            options,
+            &progress,
+        )?;
+
+        Ok(())
+    })
+}
+
+fn config(shared_options: &SharedOptions, command: &ConfigCommand) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        match command {
+            ConfigCommand::Get { field: Some(field) } => {
+                let value = read_field(&mut session, field)?;
+                println!("{} = 0x{:x}", field, value);
+            }
+            ConfigCommand::Get { field: None } => {
+                if session.target.config_regions.is_empty() {
+                    println!("The target has no config fields.");
+                }
+
+                let names = session
+                    .target
+                    .config_regions
+                    .iter()
+                    .flat_map(|region| {
+                        region
+                            .fields
+                            .iter()
+                            .map(move |field| format!("{}.{}", region.name, field.name))
+                    })
+                    .collect::<Vec<_>>();
+
+                for name in names {
+                    let value = read_field(&mut session, &name)?;
+                    println!("{} = 0x{:x}", name, value);
+                }
+            }
+            ConfigCommand::Set {
+                field,
+                value,
+                force,
+            } => {
+                write_fields(&mut session, &[(field.as_str(), *value)], *force)?;
+                println!("Wrote 0x{:x} to {}.", value, field);
+                println!("The new value takes effect after the target is reset.");
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn validate_targets(files: &[PathBuf]) -> Result<(), CliError> {
+    let mut validator = Validator::new();
+    if files.is_empty() {
+        Registry::from_builtin_families().validate(&mut validator)?;
+    }
+    for file in files {
+        validate_yaml(&mut validator, file)?;
+    }
+
+    for warning in validator.warnings() {
+        println!("warning: {}", warning);
+    }
+    if !validator.errors().is_empty() {
+        return Err(RegistryError::InvalidTarget(validator.into_errors()).into());
+    }
+    println!(
+        "The target descriptions are valid, with {} warnings.",
+        validator.warnings().len()
+    );
+
+    Ok(())
+}
+
+fn erase_flash(
+    shared_options: &SharedOptions,
+    all: bool,
+    range: Option<Range<u32>>,
+    sectors: &[u32],
+) -> Result<(), CliError> {
+    let selected = [all, range.is_some(), !sectors.is_empty()]
+        .iter()
+        .filter(|&&s| s)
+        .count();
+    if selected != 1 {
+        return Err(CliError::InvalidArgument(
+            "exactly one of --all, --range or --sector".to_string(),
+        ));
+    }
+
+    with_device(shared_options, |mut session| {
+        let progress = FlashProgress::new(|event| {
+            if let ProgressEvent::SectorErased {
+                address,
+                size,
+                time,
+            } = event
+            {
+                println!("Erased {} bytes at 0x{:08x} in {} ms", size, address, time);
+            }
+        });
+
+        if all {
+            let instant = Instant::now();
+            session.mass_erase(&progress)?;
+            println!(
+                "Erased the entire chip in {} ms",
+                instant.elapsed().as_millis()
+            );
+        } else if let Some(range) = range.clone() {
+            erase_range(&mut session, range, &progress)?;
+        } else {
+            erase_sectors(&mut session, sectors, &progress)?;
+        }
+
+        Ok(())
+    })
+}
+
+fn read_flash(
+    shared_options: &SharedOptions,
+    range: Option<Range<u32>>,
+    format: OutputFormat,
+    output: &Path,
+) -> Result<(), CliError> {
+    with_device(shared_options, |mut session| {
+        let ranges = match range.clone() {
+            Some(range) => vec![range],
+            None => flash_ranges(&session.target.memory_map),
+        };
+        let total: u32 = ranges.iter().map(|r| r.end - r.start).sum();
+
+        let instant = Instant::now();
+        let mut read = 0;
+        let segments = read_memory(&mut session, &ranges, |bytes| {
+            read += bytes;
+            print!("\rRead {:>8}/{:>8} bytes", read, total);
+            let _ = std::io::stdout().flush();
+        })?;
+        println!();
+
+        let mut file = fs::File::create(output)?;
+        write_segments(&mut file, &segments, format)?;
+
+        println!(
+            "Read {} bytes to {} in {:?}",
+            total,
+            output.display(),
+            instant.elapsed()
+        );
 
         Ok(())
     })
@@ -191,7 +595,7 @@ fn reset_target_of_device(
     _assert: Option<bool>,
 ) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
-        session.probe.target_reset()?;
+        session.reset()?;
 
         Ok(())
     })
@@ -3,6 +3,14 @@ use std::fs::{read_dir, read_to_string, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
+#[allow(dead_code)]
+#[path = "src/config/flm.rs"]
+mod flm;
+
+#[allow(dead_code)]
+#[path = "src/config/validation.rs"]
+mod validation;
+
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("targets.rs");
@@ -12,7 +20,9 @@ fn main() {
     let mut files = vec![];
     visit_dirs(Path::new("targets"), &mut files).unwrap();
 
-    let mut configs: Vec<proc_macro2::TokenStream> = vec![];
+    // Parse and validate all files first, so all mistakes are reported at once.
+    let mut families = vec![];
+    let mut validator = validation::Validator::with_flm_files();
     for file in files {
         let string = read_to_string(&file).expect(
             "Algorithm definition file could not be read. This is a bug. Please report it.",
@@ -22,8 +32,8 @@ fn main() {
 
         match yaml {
             Ok(chip) => {
-                let chip = extract_chip_family(&chip);
-                configs.push(chip);
+                validator.validate_family(&file.display().to_string(), &chip);
+                families.push(chip);
             }
             Err(e) => {
                 panic!("Failed to parse target file: {:?} because:\n{}", file, e);
@@ -31,6 +41,19 @@ fn main() {
         }
     }
 
+    // Warnings are left to `cli target validate`, so they don't show up in every build.
+    let errors = validator.errors();
+    if !errors.is_empty() {
+        let errors = errors
+            .iter()
+            .map(|e| format!("    {}", e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        panic!("Invalid target descriptions:\n{}", errors);
+    }
+
+    let configs: Vec<proc_macro2::TokenStream> = families.iter().map(extract_chip_family).collect();
+
     let stream: String = format!(
         "{}",
         quote::quote! {
@@ -92,45 +115,75 @@ fn extract_algorithms(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                 .as_str()
                 .unwrap()
                 .to_ascii_lowercase();
+            let default = algorithm.get("default").unwrap().as_bool().unwrap();
+
+            // The algorithm is either given by an FLM file, relative to the crate,
+            // or by its instructions and entry points.
+            let raw = match algorithm.get("flm") {
+                Some(path) => load_flm(path.as_str().unwrap()),
+                None => extract_raw_algorithm(algorithm),
+            };
             let description = algorithm
                 .get("description")
-                .unwrap()
-                .as_str()
-                .unwrap()
+                .map(|v| v.as_str().unwrap().to_owned())
+                .unwrap_or_else(|| raw.flash_device.name.clone())
                 .to_ascii_lowercase();
-            let default = algorithm.get("default").unwrap().as_bool().unwrap();
-            let instructions = algorithm
-                .get("instructions")
-                .unwrap()
-                .as_sequence()
-                .unwrap()
-                .iter()
-                .map(|v| v.as_u64().unwrap() as u32);
-            let pc_init =
-                quote_option(algorithm.get("pc_init").unwrap().as_u64().map(|v| v as u32));
-            let pc_uninit = quote_option(
-                algorithm
-                    .get("pc_uninit")
-                    .unwrap()
-                    .as_u64()
-                    .map(|v| v as u32),
+            // The flash range is either given explicitly or by the flash device of the FLM file.
+            let flash_range = match algorithm.get("flash_range") {
+                Some(range) => Some((
+                    range.get("start").unwrap().as_u64().unwrap() as u32,
+                    range.get("end").unwrap().as_u64().unwrap() as u32,
+                )),
+                None if raw.flash_device.size > 0 => {
+                    let range = raw.flash_device.range();
+                    Some((range.start, range.end))
+                }
+                None => None,
+            };
+            let flash_range = quote_option(flash_range.map(|(start, end)| {
+                quote::quote! {
+                    #start..#end
+                }
+            }));
+
+            // The timeouts are either given explicitly or by the flash device of the FLM file.
+            let timeout = |key: &str, flm_timeout: u32| {
+                let timeout = algorithm
+                    .get(key)
+                    .map(|v| v.as_u64().unwrap() as u32)
+                    .or_else(|| Some(flm_timeout).filter(|&t| t > 0));
+                quote_option(timeout)
+            };
+            let program_page_timeout = timeout(
+                "program_page_timeout",
+                raw.flash_device.program_page_timeout,
+            );
+            let erase_sector_timeout = timeout(
+                "erase_sector_timeout",
+                raw.flash_device.erase_sector_timeout,
             );
-            let pc_program_page =
-                algorithm.get("pc_program_page").unwrap().as_u64().unwrap() as u32;
-            let pc_erase_sector =
-                algorithm.get("pc_erase_sector").unwrap().as_u64().unwrap() as u32;
-            let pc_erase_all = quote_option(
+
+            let stack_size = quote_option(
                 algorithm
-                    .get("pc_erase_all")
-                    .unwrap()
-                    .as_u64()
-                    .map(|v| v as u32),
+                    .get("stack_size")
+                    .map(|v| v.as_u64().unwrap() as u32),
             );
-            let data_section_offset = algorithm
-                .get("data_section_offset")
-                .unwrap()
-                .as_u64()
-                .unwrap() as u32;
+
+            let flm::FlmAlgorithm {
+                instructions,
+                pc_init,
+                pc_uninit,
+                pc_program_page,
+                pc_erase_sector,
+                pc_erase_all,
+                pc_verify,
+                data_section_offset,
+                ..
+            } = raw;
+            let pc_init = quote_option(pc_init);
+            let pc_uninit = quote_option(pc_uninit);
+            let pc_erase_all = quote_option(pc_erase_all);
+            let pc_verify = quote_option(pc_verify);
 
             // Quote the algorithm struct.
             let algorithm = quote::quote! {
@@ -146,7 +199,12 @@ fn extract_algorithms(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                     pc_program_page: #pc_program_page,
                     pc_erase_sector: #pc_erase_sector,
                     pc_erase_all: #pc_erase_all,
+                    pc_verify: #pc_verify,
                     data_section_offset: #data_section_offset,
+                    flash_range: #flash_range,
+                    program_page_timeout: #program_page_timeout,
+                    erase_sector_timeout: #erase_sector_timeout,
+                    stack_size: #stack_size,
                 }
             };
 
@@ -155,6 +213,47 @@ fn extract_algorithms(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
         .collect()
 }
 
+/// Loads the flash algorithm of an FLM file.
+fn load_flm(path: &str) -> flm::FlmAlgorithm {
+    let data = std::fs::read(path)
+        .unwrap_or_else(|e| panic!("Failed to read the flash algorithm {}: {}", path, e));
+
+    flm::FlmAlgorithm::parse(&data)
+        .unwrap_or_else(|e| panic!("Failed to parse the flash algorithm {}: {}", path, e))
+}
+
+/// Extracts the instructions and entry points of an algorithm from a yaml value.
+fn extract_raw_algorithm(algorithm: &serde_yaml::Value) -> flm::FlmAlgorithm {
+    let instructions = algorithm
+        .get("instructions")
+        .unwrap()
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_u64().unwrap() as u32)
+        .collect();
+    // A missing entry point is the same as one set to `~`.
+    let address = |key: &str| {
+        algorithm
+            .get(key)
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32)
+    };
+
+    flm::FlmAlgorithm {
+        instructions,
+        pc_init: address("pc_init"),
+        pc_uninit: address("pc_uninit"),
+        pc_program_page: address("pc_program_page").unwrap(),
+        pc_erase_sector: address("pc_erase_sector").unwrap(),
+        pc_erase_all: address("pc_erase_all"),
+        pc_verify: address("pc_verify"),
+        data_section_offset: address("data_section_offset").unwrap(),
+        // The flash is described by the memory map of the variants.
+        flash_device: Default::default(),
+    }
+}
+
 /// Extracts a list of algorithm token streams from a yaml value.
 fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
     // Get an iterator over all the algorithms contained in the chip value obtained from the yaml file.
@@ -190,8 +289,23 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                         let end = range.get("end").unwrap().as_u64().unwrap() as u32;
                         let is_boot_memory =
                             region.get("is_boot_memory").unwrap().as_bool().unwrap();
-                        let sector_size =
-                            region.get("sector_size").unwrap().as_u64().unwrap() as u32;
+                        let sectors = region
+                            .get("sectors")
+                            .unwrap()
+                            .as_sequence()
+                            .unwrap()
+                            .iter()
+                            .map(|sector| {
+                                let offset = sector.get("offset").unwrap().as_u64().unwrap() as u32;
+                                let size = sector.get("size").unwrap().as_u64().unwrap() as u32;
+
+                                quote::quote! {
+                                    SectorDescription {
+                                        offset: #offset,
+                                        size: #size,
+                                    }
+                                }
+                            });
                         let page_size = region.get("page_size").unwrap().as_u64().unwrap() as u32;
                         let erased_byte_value =
                             region.get("erased_byte_value").unwrap().as_u64().unwrap() as u8;
@@ -200,7 +314,9 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                             MemoryRegion::Flash(FlashRegion {
                                 range: #start..#end,
                                 is_boot_memory: #is_boot_memory,
-                                sector_size: #sector_size,
+                                sectors: vec![
+                                    #(#sectors,)*
+                                ],
                                 page_size: #page_size,
                                 erased_byte_value: #erased_byte_value,
                             })
@@ -211,6 +327,48 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
         .collect()
 }
 
+/// Extracts a list of core token streams from a yaml value.
+fn extract_cores(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    // The cores are optional. If there are none, the family core is used.
+    let cores = match chip.get("cores") {
+        Some(cores) => cores.as_sequence().unwrap(),
+        None => return vec![],
+    };
+
+    cores
+        .iter()
+        .map(|core| {
+            let name = core.get("name").unwrap().as_str().unwrap();
+            let core_type = core
+                .get("type")
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_ascii_lowercase();
+            let ap = core
+                .get("ap")
+                .map(|v| v.as_u64().unwrap() as u8)
+                .unwrap_or(0);
+            let scs_base = match core.get("scs_base") {
+                Some(v) => {
+                    let scs_base = v.as_u64().unwrap() as u32;
+                    quote::quote!(#scs_base)
+                }
+                None => quote::quote!(DEFAULT_SCS_BASE),
+            };
+
+            quote::quote! {
+                CoreDescription {
+                    name: #name.to_owned(),
+                    core_type: #core_type.to_owned(),
+                    ap: #ap,
+                    scs_base: #scs_base,
+                }
+            }
+        })
+        .collect()
+}
+
 /// Extracts a list of algorithm token streams from a yaml value.
 fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
     // Get an iterator over all the algorithms contained in the chip value obtained from the yaml file.
@@ -233,6 +391,18 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
             // Extract all the memory regions into a Vec of TookenStreams.
             let memory_map = extract_memory_map(&variant);
 
+            // Extract all the cores into a Vec of TokenStreams.
+            let cores = extract_cores(variant);
+
+            let flash_algorithm_ram =
+                quote_option(variant.get("flash_algorithm_ram").map(|range| {
+                    let start = range.get("start").unwrap().as_u64().unwrap() as u32;
+                    let end = range.get("end").unwrap().as_u64().unwrap() as u32;
+                    quote::quote! {
+                        #start..#end
+                    }
+                }));
+
             quote::quote! {
                 Chip {
                     name: #name.to_owned(),
@@ -240,6 +410,10 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                     memory_map: vec![
                         #(#memory_map,)*
                     ],
+                    cores: vec![
+                        #(#cores,)*
+                    ],
+                    flash_algorithm_ram: #flash_algorithm_ram,
                 }
             }
         })
@@ -267,6 +441,15 @@ fn extract_chip_family(chip_family: &serde_yaml::Value) -> proc_macro2::TokenStr
         .unwrap()
         .to_ascii_lowercase();
     let manufacturer = quote_option(extract_manufacturer(&chip_family));
+    let debug_sequence = quote_option(
+        chip_family
+            .get("debug_sequence")
+            .map(|sequence| sequence.as_str().unwrap())
+            .map(|sequence| quote::quote!(#sequence.to_owned())),
+    );
+
+    // Extract all the config regions into a Vec of TokenStreams.
+    let config_regions = extract_config_regions(chip_family);
 
     // Quote the chip.
     let chip_family = quote::quote! {
@@ -280,17 +463,155 @@ fn extract_chip_family(chip_family: &serde_yaml::Value) -> proc_macro2::TokenStr
                 #(#variants,)*
             ],
             core: #core.to_owned(),
+            debug_sequence: #debug_sequence,
+            config_regions: vec![
+                #(#config_regions,)*
+            ],
         }
     };
 
     chip_family
 }
 
+/// Extracts a list of config region token streams from a yaml value.
+fn extract_config_regions(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    let regions = match chip_family.get("config_regions") {
+        Some(regions) => regions.as_sequence().unwrap(),
+        None => return vec![],
+    };
+
+    regions
+        .iter()
+        .map(|region| {
+            let name = region.get("name").unwrap().as_str().unwrap();
+            let range = region.get("range").unwrap();
+            let start = range.get("start").unwrap().as_u64().unwrap() as u32;
+            let end = range.get("end").unwrap().as_u64().unwrap() as u32;
+            let requires_erase = region
+                .get("requires_erase")
+                .map(|v| v.as_bool().unwrap())
+                .unwrap_or(false);
+            let erase = region
+                .get("erase")
+                .map(extract_config_steps)
+                .unwrap_or_default();
+            let program = extract_config_steps(region.get("program").unwrap());
+            let fields = region
+                .get("fields")
+                .unwrap()
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .map(|field| {
+                    let name = field.get("name").unwrap().as_str().unwrap();
+                    let address = field.get("address").unwrap().as_u64().unwrap() as u32;
+                    let bit_offset = field
+                        .get("bit_offset")
+                        .map(|v| v.as_u64().unwrap() as u8)
+                        .unwrap_or(0);
+                    let bit_width = field.get("bit_width").unwrap().as_u64().unwrap() as u8;
+                    let dangerous = field
+                        .get("dangerous")
+                        .map(|v| v.as_bool().unwrap())
+                        .unwrap_or(false);
+
+                    quote::quote! {
+                        ConfigField {
+                            name: #name.to_owned(),
+                            address: #address,
+                            bit_offset: #bit_offset,
+                            bit_width: #bit_width,
+                            dangerous: #dangerous,
+                        }
+                    }
+                });
+
+            quote::quote! {
+                ConfigRegion {
+                    name: #name.to_owned(),
+                    range: #start..#end,
+                    requires_erase: #requires_erase,
+                    erase: vec![
+                        #(#erase,)*
+                    ],
+                    program: vec![
+                        #(#program,)*
+                    ],
+                    fields: vec![
+                        #(#fields,)*
+                    ],
+                }
+            }
+        })
+        .collect()
+}
+
+/// Extracts the steps of an erase or program sequence from a yaml value.
+fn extract_config_steps(steps: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    steps
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|step| {
+            let value = |step: &serde_yaml::Value, key: &str| {
+                step.get(key).unwrap().as_u64().unwrap() as u32
+            };
+
+            if step.as_str() == Some("WriteWord") {
+                quote::quote! {
+                    ConfigStep::WriteWord
+                }
+            } else if let Some(step) = step.get("Write") {
+                let address = value(step, "address");
+                let value = value(step, "value");
+                quote::quote! {
+                    ConfigStep::Write {
+                        address: #address,
+                        value: #value,
+                    }
+                }
+            } else if let Some(step) = step.get("Set") {
+                let address = value(step, "address");
+                let mask = value(step, "mask");
+                quote::quote! {
+                    ConfigStep::Set {
+                        address: #address,
+                        mask: #mask,
+                    }
+                }
+            } else if let Some(step) = step.get("Wait") {
+                let address = value(step, "address");
+                let mask = value(step, "mask");
+                let value_ = value(step, "value");
+                let timeout = match step.get("timeout") {
+                    Some(v) => {
+                        let timeout = v.as_u64().unwrap() as u32;
+                        quote::quote!(#timeout)
+                    }
+                    None => quote::quote!(DEFAULT_WAIT_TIMEOUT),
+                };
+                quote::quote! {
+                    ConfigStep::Wait {
+                        address: #address,
+                        mask: #mask,
+                        value: #value_,
+                        timeout: #timeout,
+                    }
+                }
+            } else {
+                unreachable!("The validator reports unknown config steps.");
+            }
+        })
+        .collect()
+}
+
 /// Extracts the jep code token stream from a yaml value.
 fn extract_manufacturer(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
-    chip.get("manufacturer").map(|manufacturer| {
-        let cc = manufacturer.get("cc").map(|v| v.as_u64().unwrap() as u8);
-        let id = manufacturer.get("id").map(|v| v.as_u64().unwrap() as u8);
+    // The validator checks that the codes fit into a u8.
+    let manufacturer = chip.get("manufacturer").filter(|m| !m.is_null());
+    manufacturer.map(|manufacturer| {
+        let cc = manufacturer.get("cc").unwrap().as_u64().unwrap() as u8;
+        let id = manufacturer.get("id").unwrap().as_u64().unwrap() as u8;
 
         quote::quote! {
             JEP106Code {
@@ -51,6 +51,9 @@ fn main() -> Result<(), &'static str> {
         .map_err(|_| "Failed to find target")?;
 
     let mut session = Session::new(target, probe);
+    session
+        .attach()
+        .map_err(|_| "Failed to attach to the target")?;
 
     let data_size_words = matches.size;
 
@@ -1,4 +1,5 @@
 use super::memory::MemoryRegion;
+use core::ops::Range;
 
 /// This describes a single chip model.
 /// It can come in different configurations (memory, peripherals).
@@ -13,4 +14,37 @@ pub struct Chip {
     pub part: Option<u16>,
     /// The memory regions available on the chip.
     pub memory_map: Vec<MemoryRegion>,
+    /// The cores of the chip.
+    /// If this is empty, the chip has a single core of the family's core type on AP 0.
+    #[serde(default)]
+    pub cores: Vec<CoreDescription>,
+    /// The RAM which the flash algorithms may use, e.g. to keep RAM reserved by a SoftDevice free.
+    /// It has to lie inside a RAM region. If this is `None`, the last RAM region is used.
+    #[serde(default)]
+    pub flash_algorithm_ram: Option<Range<u32>>,
+}
+
+/// The default base address of the System Control Space on Cortex-M cores.
+pub const DEFAULT_SCS_BASE: u32 = 0xE000_E000;
+
+fn default_scs_base() -> u32 {
+    DEFAULT_SCS_BASE
+}
+
+/// This describes a single core of a chip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreDescription {
+    /// The name of the core.
+    /// E.g. `cm33_0`.
+    pub name: String,
+    /// The name of the core type.
+    /// E.g. `M0` or `M33`.
+    #[serde(rename = "type")]
+    pub core_type: String,
+    /// The number of the memory AP through which the core is accessed.
+    #[serde(default)]
+    pub ap: u8,
+    /// The base address of the System Control Space of the core, as seen through its AP.
+    #[serde(default = "default_scs_base")]
+    pub scs_base: u32,
 }
@@ -1,4 +1,5 @@
 use super::chip::Chip;
+use super::config_region::ConfigRegion;
 use super::flash_algorithm::RawFlashAlgorithm;
 use crate::config::target::TargetParseError;
 use jep106::JEP106Code;
@@ -20,6 +21,13 @@ pub struct ChipFamily {
     /// The name of the core type.
     /// E.g. `M0` or `M4`.
     pub core: String,
+    /// The name of the debug sequence which unlocks, erases, resets and attaches to the chips.
+    /// E.g. `nrf52`. If left open, the generic sequence is used.
+    #[serde(default)]
+    pub debug_sequence: Option<String>,
+    /// The regions which hold configuration outside of the main flash, e.g. option bytes.
+    #[serde(default)]
+    pub config_regions: Vec<ConfigRegion>,
 }
 
 impl ChipFamily {
@@ -0,0 +1,127 @@
+use core::ops::Range;
+
+/// The default time a `Wait` step may take in milliseconds.
+pub const DEFAULT_WAIT_TIMEOUT: u32 = 1000;
+
+fn default_wait_timeout() -> u32 {
+    DEFAULT_WAIT_TIMEOUT
+}
+
+/// This describes a region which holds configuration outside of the main flash,
+/// such as the option bytes of STM32 chips or the UICR of nRF chips.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigRegion {
+    /// The name of the region.
+    /// E.g. `UICR`.
+    pub name: String,
+    /// The address range of the region.
+    pub range: Range<u32>,
+    /// Whether programming can only clear bits. Setting a bit then requires erasing the whole region.
+    #[serde(default)]
+    pub requires_erase: bool,
+    /// The steps to erase the whole region.
+    #[serde(default)]
+    pub erase: Vec<ConfigStep>,
+    /// The steps to program one word of the region.
+    pub program: Vec<ConfigStep>,
+    /// The named fields of the region.
+    pub fields: Vec<ConfigField>,
+}
+
+impl ConfigRegion {
+    /// Looks up a field by its name. The comparison is case insensitive.
+    pub fn field(&self, name: &str) -> Option<&ConfigField> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// A named group of bits inside a word of a config region.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigField {
+    /// The name of the field.
+    /// E.g. `APPROTECT`.
+    pub name: String,
+    /// The address of the word which contains the field.
+    pub address: u32,
+    /// The offset of the lowest bit of the field in the word.
+    #[serde(default)]
+    pub bit_offset: u8,
+    /// The number of bits of the field.
+    pub bit_width: u8,
+    /// Whether writing the field can lock out the debugger, possibly for good,
+    /// like setting the readout protection. Such writes have to be forced.
+    #[serde(default)]
+    pub dangerous: bool,
+}
+
+impl ConfigField {
+    /// Returns the mask of the field bits inside of the word.
+    pub fn mask(&self) -> u32 {
+        let mask = if self.bit_width >= 32 {
+            0xFFFF_FFFF
+        } else {
+            (1 << self.bit_width) - 1
+        };
+
+        mask << self.bit_offset
+    }
+
+    /// Extracts the field value from the word.
+    pub fn extract(&self, word: u32) -> u32 {
+        (word & self.mask()) >> self.bit_offset
+    }
+
+    /// Replaces the field value inside of the word.
+    pub fn insert(&self, word: u32, value: u32) -> u32 {
+        (word & !self.mask()) | ((value << self.bit_offset) & self.mask())
+    }
+
+    /// Checks whether `value` fits into the field.
+    pub fn fits(&self, value: u32) -> bool {
+        self.bit_width >= 32 || value >> self.bit_width == 0
+    }
+}
+
+/// A single step of the sequence which erases a config region or programs one of its words.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ConfigStep {
+    /// Writes `value` to the register at `address`.
+    Write { address: u32, value: u32 },
+    /// Sets the bits of `mask` in the register at `address`, keeping all other bits.
+    Set { address: u32, mask: u32 },
+    /// Writes the new value of the word which is programmed.
+    WriteWord,
+    /// Waits until the bits of `mask` in the register at `address` equal `value`.
+    /// The `timeout` is given in milliseconds.
+    Wait {
+        address: u32,
+        mask: u32,
+        value: u32,
+        #[serde(default = "default_wait_timeout")]
+        timeout: u32,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::ConfigField;
+
+    #[test]
+    fn field_bits() {
+        let field = ConfigField {
+            name: "RDP".to_owned(),
+            address: 0x4002_3C14,
+            bit_offset: 8,
+            bit_width: 8,
+            dangerous: true,
+        };
+
+        assert_eq!(field.mask(), 0x0000_FF00);
+        assert_eq!(field.extract(0x0FFF_AAED), 0xAA);
+        assert_eq!(field.insert(0x0FFF_AAED, 0xBB), 0x0FFF_BBED);
+        assert!(field.fits(0xFF));
+        assert!(!field.fits(0x100));
+    }
+}
@@ -1,4 +1,30 @@
-use super::memory::{FlashRegion, RamRegion};
+use super::flm::{FlmAlgorithm, FlmError};
+use super::memory::{FlashRegion, SectorDescription};
+use core::ops::Range;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlashAlgorithmError {
+    /// The RAM is too small for the algorithm. Contains the required and the available size.
+    InsufficientRam(u32, u32),
+}
+
+impl Error for FlashAlgorithmError {}
+
+impl fmt::Display for FlashAlgorithmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlashAlgorithmError::*;
+
+        match self {
+            InsufficientRam(required, available) => write!(
+                f,
+                "The flash algorithm requires {} bytes of RAM, but only {} bytes are available.",
+                required, available
+            ),
+        }
+    }
+}
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct FlashAlgorithm {
@@ -6,6 +32,8 @@ pub struct FlashAlgorithm {
     pub name: String,
     /// Whether this flash algorithm is the default one or not.
     pub default: bool,
+    /// The address range of the flash region this algorithm was assembled for.
+    pub flash_range: Range<u32>,
     /// Memory address where the flash algo instructions will be loaded to.
     pub load_address: u32,
     /// List of 32-bit words containing the position-independent code for the algo.
@@ -20,6 +48,8 @@ pub struct FlashAlgorithm {
     pub pc_erase_sector: u32,
     /// Address of the `EraseAll()` entry point. Optional.
     pub pc_erase_all: Option<u32>,
+    /// Address of the `Verify()` entry point. Optional.
+    pub pc_verify: Option<u32>,
     /// Initial value of the R9 register for calling flash algo entry points, which
     /// determines where the position-independent data resides.
     pub static_base: u32,
@@ -31,6 +61,17 @@ pub struct FlashAlgorithm {
     /// least as large as the region's `page_size` attribute. If at least 2 buffers are included in
     /// the list, then double buffered programming will be enabled.
     pub page_buffers: Vec<u32>,
+    /// The time the `ProgramPage()` and `Verify()` routines may take in milliseconds.
+    pub program_page_timeout: u32,
+    /// The time the `EraseSector()` routine may take in milliseconds.
+    pub erase_sector_timeout: u32,
+    /// The time the `EraseAll()` routine may take in milliseconds.
+    pub erase_all_timeout: u32,
+}
+
+impl FlashAlgorithm {
+    /// The time the `Init()` and `UnInit()` routines may take in milliseconds.
+    pub const INIT_TIMEOUT: u32 = 1000;
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -53,14 +94,35 @@ pub struct RawFlashAlgorithm {
     pub pc_erase_sector: u32,
     /// Address of the `EraseAll()` entry point. Optional.
     pub pc_erase_all: Option<u32>,
+    /// Address of the `Verify()` entry point. Optional.
+    pub pc_verify: Option<u32>,
     /// The offset from the start of RAM to the data section.
     pub data_section_offset: u32,
+    /// The address range of the flash this algorithm programs.
+    ///
+    /// An algorithm without a range is used for all flash regions
+    /// which are not covered by an algorithm with a range.
+    #[serde(default)]
+    pub flash_range: Option<Range<u32>>,
+    /// The time programming a page may take in milliseconds. A default is used if not given.
+    #[serde(default)]
+    pub program_page_timeout: Option<u32>,
+    /// The time erasing a sector may take in milliseconds. A default is used if not given.
+    #[serde(default)]
+    pub erase_sector_timeout: Option<u32>,
+    /// The size of the stack of the algorithm in bytes.
+    ///
+    /// If this is not given, the largest stack of at most 512 bytes which leaves room for a page buffer is used.
+    #[serde(default)]
+    pub stack_size: Option<u32>,
 }
 
 impl RawFlashAlgorithm {
     const FLASH_BLOB_HEADER_SIZE: u32 = 8 * 4;
     const FLASH_ALGO_STACK_SIZE: u32 = 512;
     const FLASH_ALGO_STACK_DECREMENT: u32 = 64;
+    const DEFAULT_PROGRAM_PAGE_TIMEOUT: u32 = 1000;
+    const DEFAULT_ERASE_SECTOR_TIMEOUT: u32 = 2000;
     const FLASH_BLOB_HEADER: [u32; Self::FLASH_BLOB_HEADER_SIZE as usize / 4] = [
         0xE00A_BE00,
         0x062D_780D,
@@ -72,51 +134,130 @@ impl RawFlashAlgorithm {
         0x0477_0D1F,
     ];
 
-    /// Constructs a complete flash algorithm, tailored to the flash and RAM sizes given.
-    pub fn assemble(&self, ram_region: &RamRegion, flash_region: &FlashRegion) -> FlashAlgorithm {
+    /// Loads a flash algorithm from the contents of a CMSIS-Pack `.FLM` file.
+    ///
+    /// Returns the algorithm, which is not marked as default, and the flash region it programs.
+    /// The region is not marked as boot memory.
+    pub fn from_flm(name: &str, data: &[u8]) -> Result<(Self, FlashRegion), FlmError> {
+        let flm = FlmAlgorithm::parse(data)?;
+        let device = &flm.flash_device;
+
+        let region = FlashRegion {
+            range: device.range(),
+            is_boot_memory: false,
+            sectors: device
+                .sectors
+                .iter()
+                .map(|group| SectorDescription {
+                    offset: group.address,
+                    size: group.size,
+                })
+                .collect(),
+            page_size: device.page_size,
+            erased_byte_value: device.erased_byte_value,
+        };
+
+        let algorithm = RawFlashAlgorithm {
+            name: name.to_owned(),
+            description: device.name.clone(),
+            default: false,
+            instructions: flm.instructions,
+            pc_init: flm.pc_init,
+            pc_uninit: flm.pc_uninit,
+            pc_program_page: flm.pc_program_page,
+            pc_erase_sector: flm.pc_erase_sector,
+            pc_erase_all: flm.pc_erase_all,
+            pc_verify: flm.pc_verify,
+            data_section_offset: flm.data_section_offset,
+            flash_range: Some(region.range.clone()),
+            program_page_timeout: Some(device.program_page_timeout).filter(|&t| t > 0),
+            erase_sector_timeout: Some(device.erase_sector_timeout).filter(|&t| t > 0),
+            stack_size: None,
+        };
+
+        Ok((algorithm, region))
+    }
+
+    /// Checks whether this algorithm programs the entire `region`.
+    ///
+    /// Algorithms without a flash range are assumed to program every region.
+    pub fn covers(&self, region: &FlashRegion) -> bool {
+        match &self.flash_range {
+            Some(range) => range.start <= region.range.start && region.range.end <= range.end,
+            None => true,
+        }
+    }
+
+    /// Constructs a complete flash algorithm, tailored to the flash region and the RAM given.
+    ///
+    /// The stack is put at the start of `ram`, followed by the code of the algorithm
+    /// and as many page buffers as fit into the remaining RAM.
+    /// Returns an error if not even one page buffer fits.
+    pub fn assemble(
+        &self,
+        ram: &Range<u32>,
+        flash_region: &FlashRegion,
+    ) -> Result<FlashAlgorithm, FlashAlgorithmError> {
         let mut instructions = Self::FLASH_BLOB_HEADER.to_vec();
 
         instructions.extend(&self.instructions);
 
-        let mut offset = 0;
-        let mut addr_stack = 0;
-        let mut addr_load = 0;
-        let mut addr_data = 0;
-
-        // Try to find a stack size that fits with at least one page of data.
-        for i in 0..Self::FLASH_ALGO_STACK_SIZE / Self::FLASH_ALGO_STACK_DECREMENT {
-            offset = Self::FLASH_ALGO_STACK_SIZE - Self::FLASH_ALGO_STACK_DECREMENT * i;
-            // Stack address
-            addr_stack = ram_region.range.start + offset;
-            // Load address
-            addr_load = addr_stack;
-            offset += instructions.len() as u32 * 4;
-
-            // Data buffer 1
-            addr_data = ram_region.range.start + offset;
-            offset += flash_region.page_size;
-
-            if offset <= ram_region.range.end - ram_region.range.start {
-                break;
-            }
+        let ram_size = ram.end.saturating_sub(ram.start);
+        let code_size = instructions.len() as u32 * 4;
+        let page_size = flash_region.page_size;
+        let fits = |stack_size: u32| {
+            u64::from(stack_size) + u64::from(code_size) + u64::from(page_size)
+                <= u64::from(ram_size)
+        };
+
+        let stack_size = match self.stack_size {
+            // The stack pointer has to be aligned to 8 bytes.
+            Some(stack_size) => (stack_size + 7) & !7,
+            // Try to find a stack size that fits with at least one page of data.
+            None => (1..=Self::FLASH_ALGO_STACK_SIZE / Self::FLASH_ALGO_STACK_DECREMENT)
+                .rev()
+                .map(|i| i * Self::FLASH_ALGO_STACK_DECREMENT)
+                .find(|&stack_size| fits(stack_size))
+                .unwrap_or(Self::FLASH_ALGO_STACK_SIZE),
+        };
+
+        if !fits(stack_size) {
+            return Err(FlashAlgorithmError::InsufficientRam(
+                stack_size
+                    .saturating_add(code_size)
+                    .saturating_add(page_size),
+                ram_size,
+            ));
         }
 
-        // Data buffer 2
-        let addr_data2 = ram_region.range.start + offset;
-        offset += flash_region.page_size;
+        // The stack grows down from the code.
+        let addr_stack = ram.start + stack_size;
+        let addr_load = addr_stack;
+        let addr_data = addr_load + code_size;
 
-        // Determine whether we can use double buffering or not by the remaining RAM region size.
-        let page_buffers = if offset <= ram_region.range.end - ram_region.range.start {
-            vec![addr_data, addr_data2]
-        } else {
-            vec![addr_data]
-        };
+        // Use as many page buffers as fit into the remaining RAM.
+        let free = ram_size - stack_size - code_size;
+        let buffer_count = free.checked_div(page_size).unwrap_or(1);
+        let page_buffers = (0..buffer_count)
+            .map(|i| addr_data + i * page_size)
+            .collect::<Vec<_>>();
 
         let code_start = addr_load + Self::FLASH_BLOB_HEADER_SIZE;
 
-        FlashAlgorithm {
+        let program_page_timeout = self
+            .program_page_timeout
+            .unwrap_or(Self::DEFAULT_PROGRAM_PAGE_TIMEOUT);
+        let erase_sector_timeout = self
+            .erase_sector_timeout
+            .unwrap_or(Self::DEFAULT_ERASE_SECTOR_TIMEOUT);
+        // Erasing the chip takes at most as long as erasing every sector on its own.
+        let erase_all_timeout =
+            erase_sector_timeout.saturating_mul(flash_region.sector_infos().len() as u32);
+
+        Ok(FlashAlgorithm {
             name: self.name.clone(),
             default: self.default,
+            flash_range: flash_region.range.clone(),
             load_address: addr_load,
             instructions,
             pc_init: self.pc_init.map(|v| code_start + v),
@@ -124,10 +265,125 @@ impl RawFlashAlgorithm {
             pc_program_page: code_start + self.pc_program_page,
             pc_erase_sector: code_start + self.pc_erase_sector,
             pc_erase_all: self.pc_erase_all.map(|v| code_start + v),
+            pc_verify: self.pc_verify.map(|v| code_start + v),
             static_base: code_start + self.data_section_offset,
             begin_stack: addr_stack,
             begin_data: page_buffers[0],
-            page_buffers: page_buffers.clone(),
-        }
+            page_buffers,
+            program_page_timeout,
+            erase_sector_timeout,
+            erase_all_timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashAlgorithmError, RawFlashAlgorithm};
+    use crate::config::flm::test::flm;
+    use crate::config::memory::SectorDescription;
+
+    #[test]
+    fn load_flm() {
+        let (algorithm, region) = RawFlashAlgorithm::from_flm("test", &flm(&[(0x400, 0)])).unwrap();
+
+        assert_eq!(algorithm.description, "Test 64kB");
+        assert_eq!(algorithm.pc_program_page, 0x21);
+        assert_eq!(region.range, 0x0800_0000..0x0801_0000);
+        assert_eq!(algorithm.flash_range, Some(0x0800_0000..0x0801_0000));
+        assert_eq!(
+            region.sectors,
+            vec![SectorDescription {
+                offset: 0,
+                size: 0x400
+            }]
+        );
+
+        // Sectors of different sizes are taken over as well.
+        let (_, region) =
+            RawFlashAlgorithm::from_flm("test", &flm(&[(0x400, 0), (0x800, 0x8000)])).unwrap();
+        assert_eq!(region.sectors[1].offset, 0x8000);
+        assert_eq!(region.sectors[1].size, 0x800);
+    }
+
+    #[test]
+    fn assemble_timeouts() {
+        let ram = 0x2000_0000..0x2000_4000;
+        let (algorithm, region) = RawFlashAlgorithm::from_flm("test", &flm(&[(0x400, 0)])).unwrap();
+
+        // The timeouts of the flash device are taken over.
+        let assembled = algorithm.assemble(&ram, &region).unwrap();
+        assert_eq!(assembled.program_page_timeout, 100);
+        assert_eq!(assembled.erase_sector_timeout, 3000);
+        assert_eq!(assembled.erase_all_timeout, 64 * 3000);
+
+        // Defaults are used for algorithms without timeouts.
+        let algorithm = RawFlashAlgorithm {
+            program_page_timeout: None,
+            erase_sector_timeout: None,
+            ..algorithm
+        };
+        let assembled = algorithm.assemble(&ram, &region).unwrap();
+        assert_eq!(
+            assembled.program_page_timeout,
+            RawFlashAlgorithm::DEFAULT_PROGRAM_PAGE_TIMEOUT
+        );
+        assert_eq!(
+            assembled.erase_sector_timeout,
+            RawFlashAlgorithm::DEFAULT_ERASE_SECTOR_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn assemble_ram_placement() {
+        let (algorithm, region) = RawFlashAlgorithm::from_flm("test", &flm(&[(0x400, 0)])).unwrap();
+        let code_size = (algorithm.instructions.len() as u32 + 8) * 4;
+
+        // The default stack is used and the remaining RAM is filled with page buffers.
+        let ram = 0x2000_0000..0x2000_0200 + code_size + 3 * 0x100 + 0x80;
+        let assembled = algorithm.assemble(&ram, &region).unwrap();
+        assert_eq!(assembled.begin_stack, 0x2000_0200);
+        assert_eq!(assembled.load_address, 0x2000_0200);
+        assert_eq!(
+            assembled.page_buffers,
+            vec![
+                0x2000_0200 + code_size,
+                0x2000_0300 + code_size,
+                0x2000_0400 + code_size
+            ]
+        );
+
+        // A large RAM holds many page buffers.
+        let large_ram = 0x2000_0000..0x2001_0000;
+        let assembled = algorithm.assemble(&large_ram, &region).unwrap();
+        assert_eq!(
+            assembled.page_buffers.len() as u32,
+            (0x1_0000 - 0x200 - code_size) / 0x100
+        );
+        assert!(assembled
+            .page_buffers
+            .iter()
+            .all(|&b| b + 0x100 <= 0x2001_0000));
+
+        // A single page buffer is used if there is no room for a second one.
+        let small_ram = 0x2000_0000..0x2000_0200 + code_size + 0x180;
+        let assembled = algorithm.assemble(&small_ram, &region).unwrap();
+        assert_eq!(assembled.page_buffers, vec![0x2000_0200 + code_size]);
+
+        // An explicit stack size is honoured.
+        let algorithm = RawFlashAlgorithm {
+            stack_size: Some(0x100),
+            ..algorithm
+        };
+        let assembled = algorithm.assemble(&ram, &region).unwrap();
+        assert_eq!(assembled.begin_stack, 0x2000_0100);
+        assert_eq!(assembled.page_buffers.len(), 4);
+
+        // The algorithm does not fit into a RAM which is too small.
+        let ram = 0x2000_0000..0x2000_0100 + code_size;
+        assert_eq!(
+            algorithm.assemble(&ram, &region).unwrap_err(),
+            FlashAlgorithmError::InsufficientRam(0x200 + code_size, 0x100 + code_size)
+        );
     }
 }
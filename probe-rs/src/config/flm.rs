@@ -0,0 +1,517 @@
+//! A parser for CMSIS-Pack flash algorithms (`.FLM` files).
+//!
+//! An FLM file is an ELF file which contains the position independent code of the algorithm
+//! in the `PrgCode` section, its data in the `PrgData` sections and the description of the
+//! flash in the `FlashDevice` structure.
+//!
+//! This module only depends on `std`, so it can be included by the build script as well.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+
+/// The size of the `FlashDevice` structure up to the list of sectors.
+const FLASH_DEVICE_HEADER_SIZE: usize = 160;
+/// Marks the end of the list of sectors.
+const SECTOR_END: u32 = 0xFFFF_FFFF;
+/// The largest image of code and data which is accepted. A flash algorithm has to fit into RAM.
+const MAX_IMAGE_SIZE: u32 = 0x10_0000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlmError {
+    /// The file is not a 32 bit little endian ELF file, or it is malformed.
+    InvalidElf(&'static str),
+    MissingSection(&'static str),
+    MissingSymbol(&'static str),
+    /// The `FlashDevice` structure is truncated or contains no sectors.
+    InvalidFlashDevice,
+}
+
+impl Error for FlmError {}
+
+impl fmt::Display for FlmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlmError::*;
+
+        match self {
+            InvalidElf(reason) => write!(
+                f,
+                "The flash algorithm is not a valid ELF file: {}.",
+                reason
+            ),
+            MissingSection(name) => write!(f, "The flash algorithm has no {} section.", name),
+            MissingSymbol(name) => write!(f, "The flash algorithm has no {} symbol.", name),
+            InvalidFlashDevice => write!(
+                f,
+                "The FlashDevice description of the flash algorithm is invalid."
+            ),
+        }
+    }
+}
+
+/// A group of equally sized sectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectorGroup {
+    /// The size of each sector in the group.
+    pub size: u32,
+    /// The address of the first sector of the group, relative to the start of the device.
+    pub address: u32,
+}
+
+/// The description of the flash, as contained in the `FlashDevice` structure.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlashDevice {
+    pub version: u16,
+    pub name: String,
+    pub device_type: u16,
+    /// The start address of the flash.
+    pub address: u32,
+    /// The total size of the flash.
+    pub size: u32,
+    pub page_size: u32,
+    pub erased_byte_value: u8,
+    /// The timeout of programming a page in milliseconds.
+    pub program_page_timeout: u32,
+    /// The timeout of erasing a sector in milliseconds.
+    pub erase_sector_timeout: u32,
+    pub sectors: Vec<SectorGroup>,
+}
+
+/// A flash algorithm extracted from an FLM file.
+///
+/// The entry points are offsets into the instructions, like in the target descriptions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlmAlgorithm {
+    pub instructions: Vec<u32>,
+    pub pc_init: Option<u32>,
+    pub pc_uninit: Option<u32>,
+    pub pc_program_page: u32,
+    pub pc_erase_sector: u32,
+    pub pc_erase_all: Option<u32>,
+    pub pc_verify: Option<u32>,
+    pub data_section_offset: u32,
+    pub flash_device: FlashDevice,
+}
+
+struct SectionHeader {
+    name: u32,
+    section_type: u32,
+    address: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+}
+
+struct Symbol<'a> {
+    name: &'a str,
+    value: u32,
+    section: u16,
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    sections: Vec<SectionHeader>,
+    section_names: &'a [u8],
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Reads the zero terminated string at `offset` of a string table.
+fn read_str(table: &[u8], offset: usize) -> Option<&str> {
+    let bytes = table.get(offset..)?;
+    let end = bytes.iter().position(|b| *b == 0)?;
+    std::str::from_utf8(&bytes[..end]).ok()
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, FlmError> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err(FlmError::InvalidElf("wrong magic number"));
+        }
+        // Check for ELFCLASS32 and ELFDATA2LSB.
+        if data.get(4) != Some(&1) || data.get(5) != Some(&1) {
+            return Err(FlmError::InvalidElf("not a 32 bit little endian file"));
+        }
+
+        let truncated = FlmError::InvalidElf("truncated file");
+        let section_offset = read_u32(data, 0x20).ok_or(truncated.clone())? as usize;
+        let section_size = read_u16(data, 0x2E).ok_or(truncated.clone())? as usize;
+        let section_count = read_u16(data, 0x30).ok_or(truncated.clone())? as usize;
+        let names_index = read_u16(data, 0x32).ok_or(truncated.clone())? as usize;
+
+        let sections = (0..section_count)
+            .map(|i| {
+                let start = i
+                    .checked_mul(section_size)
+                    .and_then(|offset| offset.checked_add(section_offset))?;
+                let header = data
+                    .get(start..)
+                    .filter(|header| header.len() >= 40 && section_size >= 40)?;
+                Some(SectionHeader {
+                    name: read_u32(header, 0)?,
+                    section_type: read_u32(header, 4)?,
+                    address: read_u32(header, 12)?,
+                    offset: read_u32(header, 16)?,
+                    size: read_u32(header, 20)?,
+                    link: read_u32(header, 24)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(truncated.clone())?;
+
+        let mut elf = Elf {
+            data,
+            sections,
+            section_names: &[],
+        };
+        elf.section_names = elf
+            .sections
+            .get(names_index)
+            .and_then(|section| elf.section_data(section))
+            .ok_or(truncated)?;
+
+        Ok(elf)
+    }
+
+    fn section_data(&self, section: &SectionHeader) -> Option<&'a [u8]> {
+        self.data
+            .get(section.offset as usize..)?
+            .get(..section.size as usize)
+    }
+
+    fn section_name(&self, section: &SectionHeader) -> Option<&'a str> {
+        read_str(self.section_names, section.name as usize)
+    }
+
+    fn symbols(&self) -> Result<Vec<Symbol<'a>>, FlmError> {
+        let invalid = FlmError::InvalidElf("invalid symbol table");
+
+        let symbol_table = self
+            .sections
+            .iter()
+            .find(|section| section.section_type == SHT_SYMTAB)
+            .ok_or(FlmError::MissingSection("symbol table"))?;
+        let symbols = self.section_data(symbol_table).ok_or(invalid.clone())?;
+        let names = self
+            .sections
+            .get(symbol_table.link as usize)
+            .and_then(|section| self.section_data(section))
+            .ok_or(invalid.clone())?;
+
+        symbols
+            .chunks_exact(16)
+            .map(|symbol| {
+                Some(Symbol {
+                    name: read_str(names, read_u32(symbol, 0)? as usize)?,
+                    value: read_u32(symbol, 4)?,
+                    section: read_u16(symbol, 14)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(invalid)
+    }
+}
+
+impl FlashDevice {
+    /// Returns the address range of the flash.
+    ///
+    /// A parsed device always ends inside the 32 bit address space.
+    pub fn range(&self) -> Range<u32> {
+        self.address..self.address.saturating_add(self.size)
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, FlmError> {
+        let invalid = || FlmError::InvalidFlashDevice;
+
+        let name = data.get(2..130).ok_or_else(invalid)?;
+        let name_end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+
+        let mut sectors = vec![];
+        let mut offset = FLASH_DEVICE_HEADER_SIZE;
+        loop {
+            let size = read_u32(data, offset).ok_or_else(invalid)?;
+            let address = read_u32(data, offset + 4).ok_or_else(invalid)?;
+            if size == SECTOR_END && address == SECTOR_END {
+                break;
+            }
+            sectors.push(SectorGroup { size, address });
+            offset += 8;
+        }
+
+        if sectors.is_empty() || sectors.iter().any(|sector| sector.size == 0) {
+            return Err(invalid());
+        }
+
+        let address = read_u32(data, 132).ok_or_else(invalid)?;
+        let size = read_u32(data, 136).ok_or_else(invalid)?;
+        if address.checked_add(size).is_none() {
+            return Err(invalid());
+        }
+
+        Ok(FlashDevice {
+            version: read_u16(data, 0).ok_or_else(invalid)?,
+            name: String::from_utf8_lossy(&name[..name_end]).into_owned(),
+            device_type: read_u16(data, 130).ok_or_else(invalid)?,
+            address,
+            size,
+            page_size: read_u32(data, 140).ok_or_else(invalid)?,
+            erased_byte_value: *data.get(148).ok_or_else(invalid)?,
+            program_page_timeout: read_u32(data, 152).ok_or_else(invalid)?,
+            erase_sector_timeout: read_u32(data, 156).ok_or_else(invalid)?,
+            sectors,
+        })
+    }
+}
+
+impl FlmAlgorithm {
+    /// Parses the contents of an FLM file.
+    pub fn parse(data: &[u8]) -> Result<Self, FlmError> {
+        let elf = Elf::parse(data)?;
+
+        // The code starts at address 0 and is followed by the (zero initialized) data.
+        let code = elf
+            .sections
+            .iter()
+            .find(|section| elf.section_name(section) == Some("PrgCode"))
+            .ok_or(FlmError::MissingSection("PrgCode"))?;
+        if code.address != 0 {
+            return Err(FlmError::InvalidElf("PrgCode does not start at address 0"));
+        }
+        let data_sections = elf
+            .sections
+            .iter()
+            .filter(|section| elf.section_name(section) == Some("PrgData"))
+            .collect::<Vec<_>>();
+
+        let data_section_offset = data_sections
+            .iter()
+            .map(|section| section.address)
+            .min()
+            .unwrap_or(code.size);
+        let end = std::iter::once(code)
+            .chain(data_sections.iter().cloned())
+            .map(|section| section.address.checked_add(section.size))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(FlmError::InvalidElf("section exceeds the address space"))?
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        if end > MAX_IMAGE_SIZE {
+            return Err(FlmError::InvalidElf("code and data are too large"));
+        }
+
+        let mut image = vec![0u8; ((end + 3) & !0x3) as usize];
+        for section in std::iter::once(code).chain(data_sections.iter().cloned()) {
+            if section.section_type == SHT_NOBITS {
+                continue;
+            }
+            let truncated = FlmError::InvalidElf("truncated section");
+            let section_data = elf.section_data(section).ok_or(truncated.clone())?;
+            image
+                .get_mut(section.address as usize..)
+                .and_then(|image| image.get_mut(..section_data.len()))
+                .ok_or(truncated)?
+                .copy_from_slice(section_data);
+        }
+        let instructions = image
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        let symbols = elf.symbols()?;
+        let symbol = |name: &str| {
+            symbols
+                .iter()
+                .find(|symbol| symbol.name == name)
+                .map(|symbol| symbol.value)
+        };
+        let required = |name: &'static str| symbol(name).ok_or(FlmError::MissingSymbol(name));
+
+        let device = symbols
+            .iter()
+            .find(|symbol| symbol.name == "FlashDevice")
+            .ok_or(FlmError::MissingSymbol("FlashDevice"))?;
+        let device_section = elf
+            .sections
+            .get(device.section as usize)
+            .ok_or(FlmError::MissingSection("DevDscr"))?;
+        let device_data = elf
+            .section_data(device_section)
+            .zip(device.value.checked_sub(device_section.address))
+            .and_then(|(data, offset)| data.get(offset as usize..))
+            .ok_or(FlmError::InvalidFlashDevice)?;
+
+        Ok(FlmAlgorithm {
+            instructions,
+            pc_init: symbol("Init"),
+            pc_uninit: symbol("UnInit"),
+            pc_program_page: required("ProgramPage")?,
+            pc_erase_sector: required("EraseSector")?,
+            pc_erase_all: symbol("EraseChip"),
+            pc_verify: symbol("Verify"),
+            data_section_offset,
+            flash_device: FlashDevice::parse(device_data)?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{FlmAlgorithm, FlmError, SectorGroup};
+
+    /// Builds a minimal FLM file with a code, a data and a device description section.
+    pub(crate) fn flm(sectors: &[(u32, u32)]) -> Vec<u8> {
+        let code: Vec<u8> = (0..0x40).collect();
+        let data = vec![0xAA; 6];
+
+        let mut device = vec![0u8; 160];
+        device[0..2].copy_from_slice(&0x0101u16.to_le_bytes());
+        device[2..2 + 9].copy_from_slice(b"Test 64kB");
+        device[132..136].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+        device[136..140].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        device[140..144].copy_from_slice(&0x100u32.to_le_bytes());
+        device[148] = 0xFF;
+        device[152..156].copy_from_slice(&100u32.to_le_bytes());
+        device[156..160].copy_from_slice(&3000u32.to_le_bytes());
+        for (size, address) in sectors.iter().chain(&[(!0, !0)]) {
+            device.extend(&size.to_le_bytes());
+            device.extend(&address.to_le_bytes());
+        }
+
+        let names = b"\0PrgCode\0PrgData\0DevDscr\0.symtab\0.strtab\0.shstrtab\0";
+        let strings = b"\0Init\0ProgramPage\0EraseSector\0FlashDevice\0";
+        let mut symbols = vec![0u8; 16];
+        for (name, value, section) in &[
+            (1u32, 1u32, 1u16),
+            (6, 0x21, 1),
+            (18, 0x11, 1),
+            (30, 0x1000, 3),
+        ] {
+            symbols.extend(&name.to_le_bytes());
+            symbols.extend(&value.to_le_bytes());
+            symbols.extend(&[0; 6]);
+            symbols.extend(&section.to_le_bytes());
+        }
+
+        // The section contents follow the ELF header, the section headers are at the end.
+        let mut file = vec![0u8; 52];
+        file[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        let mut headers = vec![0u8; 40];
+        let contents: [(u32, u32, u32, &[u8], u32); 6] = [
+            (1, 1, 0x00, &code, 0),
+            (9, 1, 0x40, &data, 0),
+            (17, 1, 0x1000, &device, 0),
+            (25, 2, 0, &symbols, 5),
+            (33, 3, 0, strings, 0),
+            (41, 3, 0, names, 0),
+        ];
+        for (name, section_type, address, content, link) in contents.iter() {
+            let mut header: Vec<u8> = vec![];
+            for word in &[
+                *name,
+                *section_type,
+                0,
+                *address,
+                file.len() as u32,
+                content.len() as u32,
+                *link,
+                0,
+                0,
+                0,
+            ] {
+                header.extend(&word.to_le_bytes());
+            }
+            headers.extend(header);
+            file.extend(*content);
+        }
+
+        let section_offset = file.len() as u32;
+        file.extend(headers);
+        file[0x20..0x24].copy_from_slice(&section_offset.to_le_bytes());
+        file[0x2E..0x30].copy_from_slice(&40u16.to_le_bytes());
+        file[0x30..0x32].copy_from_slice(&7u16.to_le_bytes());
+        file[0x32..0x34].copy_from_slice(&6u16.to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn parse_flm() {
+        let algorithm = FlmAlgorithm::parse(&flm(&[(0x400, 0)])).unwrap();
+
+        assert_eq!(algorithm.instructions.len(), 0x48 / 4);
+        assert_eq!(algorithm.instructions[0], 0x0302_0100);
+        assert_eq!(algorithm.instructions[0x10], 0xAAAA_AAAA);
+        assert_eq!(algorithm.instructions[0x11], 0x0000_AAAA);
+        assert_eq!(algorithm.pc_init, Some(1));
+        assert_eq!(algorithm.pc_uninit, None);
+        assert_eq!(algorithm.pc_program_page, 0x21);
+        assert_eq!(algorithm.pc_erase_sector, 0x11);
+        assert_eq!(algorithm.data_section_offset, 0x40);
+
+        let device = algorithm.flash_device;
+        assert_eq!(device.name, "Test 64kB");
+        assert_eq!(
+            device.address..device.address + device.size,
+            0x0800_0000..0x0801_0000
+        );
+        assert_eq!(device.page_size, 0x100);
+        assert_eq!(device.erased_byte_value, 0xFF);
+        assert_eq!(device.program_page_timeout, 100);
+        assert_eq!(device.erase_sector_timeout, 3000);
+        assert_eq!(
+            device.sectors,
+            vec![SectorGroup {
+                size: 0x400,
+                address: 0
+            }]
+        );
+    }
+
+    /// Sets a field of the header of the data section.
+    fn patch_data_section(file: &mut [u8], field: usize, value: u32) {
+        let mut offset = [0u8; 4];
+        offset.copy_from_slice(&file[0x20..0x24]);
+        let header = u32::from_le_bytes(offset) as usize + 2 * 40;
+        file[header + field..header + field + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn reject_invalid_sections() {
+        let mut file = flm(&[(0x400, 0)]);
+        patch_data_section(&mut file, 12, 0xFFFF_FFFC);
+        assert_eq!(
+            FlmAlgorithm::parse(&file),
+            Err(FlmError::InvalidElf("section exceeds the address space"))
+        );
+
+        let mut file = flm(&[(0x400, 0)]);
+        patch_data_section(&mut file, 20, 0x1000);
+        assert_eq!(
+            FlmAlgorithm::parse(&file),
+            Err(FlmError::InvalidElf("truncated section"))
+        );
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        assert_eq!(
+            FlmAlgorithm::parse(b"\x7fELF\x02\x01"),
+            Err(FlmError::InvalidElf("not a 32 bit little endian file"))
+        );
+        assert_eq!(
+            FlmAlgorithm::parse(&flm(&[])),
+            Err(FlmError::InvalidFlashDevice)
+        );
+    }
+}
@@ -1,11 +1,23 @@
 use core::ops::Range;
 
+/// Describes a group of equally sized sectors of a flash region.
+///
+/// The group reaches from its offset up to the offset of the next group or the end of the region.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SectorDescription {
+    /// The offset of the first sector of the group from the start of the region.
+    pub offset: u32,
+    /// The size of each sector in the group.
+    pub size: u32,
+}
+
 /// Represents a region in flash.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FlashRegion {
     pub range: Range<u32>,
     pub is_boot_memory: bool,
-    pub sector_size: u32,
+    /// The sector layout of the region, ordered by offset.
+    pub sectors: Vec<SectorDescription>,
     pub page_size: u32,
     pub erased_byte_value: u8,
 }
@@ -18,13 +30,33 @@ impl FlashRegion {
             return None;
         }
 
+        let offset = address - self.range.start;
+        let group = self
+            .sectors
+            .iter()
+            .rev()
+            .find(|group| group.offset <= offset)
+            .filter(|group| group.size > 0)?;
+        let index = (offset - group.offset) / group.size;
+
         Some(SectorInfo {
-            base_address: address - (address % self.sector_size),
+            base_address: self.range.start + group.offset + index * group.size,
             page_size: self.page_size,
-            size: self.sector_size,
+            size: group.size,
         })
     }
 
+    /// Returns all sectors of the region.
+    pub fn sector_infos(&self) -> Vec<SectorInfo> {
+        let mut sectors = vec![];
+        let mut address = self.range.start;
+        while let Some(sector) = self.sector_info(address) {
+            address = sector.base_address + sector.size;
+            sectors.push(sector);
+        }
+        sectors
+    }
+
     /// Returns the necessary information about the page which `address` resides in
     /// if the address is inside the flash region.
     pub fn page_info(&self, address: u32) -> Option<PageInfo> {
@@ -227,4 +259,54 @@ mod test {
         let range2 = 6..8;
         assert!(!range1.intersects_range(&range2));
     }
+
+    /// The flash of a 1 MB STM32F4, with four 16 kB, one 64 kB and seven 128 kB sectors.
+    fn stm32f4_flash() -> FlashRegion {
+        FlashRegion {
+            range: 0x0800_0000..0x0810_0000,
+            is_boot_memory: true,
+            sectors: vec![
+                SectorDescription {
+                    offset: 0x0,
+                    size: 0x4000,
+                },
+                SectorDescription {
+                    offset: 0x1_0000,
+                    size: 0x1_0000,
+                },
+                SectorDescription {
+                    offset: 0x2_0000,
+                    size: 0x2_0000,
+                },
+            ],
+            page_size: 0x400,
+            erased_byte_value: 0xFF,
+        }
+    }
+
+    #[test]
+    fn mixed_sector_sizes() {
+        let flash = stm32f4_flash();
+
+        let sector = flash.sector_info(0x0800_4321).unwrap();
+        assert_eq!((sector.base_address, sector.size), (0x0800_4000, 0x4000));
+
+        let sector = flash.sector_info(0x0801_FFFF).unwrap();
+        assert_eq!((sector.base_address, sector.size), (0x0801_0000, 0x1_0000));
+
+        let sector = flash.sector_info(0x080A_0000).unwrap();
+        assert_eq!((sector.base_address, sector.size), (0x080A_0000, 0x2_0000));
+
+        assert!(flash.sector_info(0x0810_0000).is_none());
+    }
+
+    #[test]
+    fn all_sectors() {
+        let sectors = stm32f4_flash().sector_infos();
+
+        assert_eq!(sectors.len(), 12);
+        assert_eq!(sectors[4].base_address, 0x0801_0000);
+        assert_eq!(sectors[11].base_address, 0x080E_0000);
+        assert_eq!(sectors.iter().map(|s| s.size).sum::<u32>(), 0x10_0000);
+    }
 }
@@ -1,6 +1,9 @@
 pub mod chip;
 pub mod chip_family;
+pub mod config_region;
 pub mod flash_algorithm;
+pub mod flm;
 pub mod memory;
 pub mod registry;
 pub mod target;
+pub mod validation;
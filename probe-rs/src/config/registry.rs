@@ -1,8 +1,10 @@
 use crate::config::{
-    chip::Chip,
+    chip::{Chip, CoreDescription, DEFAULT_SCS_BASE},
     chip_family::ChipFamily,
-    flash_algorithm::RawFlashAlgorithm,
-    memory::{FlashRegion, MemoryRegion, RamRegion},
+    config_region::{ConfigField, ConfigRegion, ConfigStep, DEFAULT_WAIT_TIMEOUT},
+    flash_algorithm::{FlashAlgorithmError, RawFlashAlgorithm},
+    memory::{FlashRegion, MemoryRegion, RamRegion, SectorDescription},
+    validation::{ValidationError, Validator},
 };
 use crate::target::info::ChipInfo;
 use jep106::JEP106Code;
@@ -10,8 +12,9 @@ use std::error::Error;
 use std::fs::File;
 use std::path::Path;
 
-use super::target::Target;
+use super::target::{Target, TargetCore};
 use crate::cores::get_core;
+use crate::sequences::{get_sequence, Generic};
 
 #[derive(Debug)]
 pub enum RegistryError {
@@ -19,8 +22,12 @@ pub enum RegistryError {
     ChipAutodetectFailed,
     AlgorithmNotFound,
     CoreNotFound,
+    DebugSequenceNotFound(String),
     RamMissing,
     FlashMissing,
+    InvalidFlashAlgorithmRam,
+    InvalidTarget(Vec<ValidationError>),
+    FlashAlgorithm(FlashAlgorithmError),
     Io(std::io::Error),
     Yaml(serde_yaml::Error),
 }
@@ -34,8 +41,12 @@ impl Error for RegistryError {
             ChipAutodetectFailed => None,
             AlgorithmNotFound => None,
             CoreNotFound => None,
+            DebugSequenceNotFound(_) => None,
             RamMissing => None,
             FlashMissing => None,
+            InvalidFlashAlgorithmRam => None,
+            InvalidTarget(_) => None,
+            FlashAlgorithm(ref e) => Some(e),
             Io(ref e) => Some(e),
             Yaml(ref e) => Some(e),
         }
@@ -54,8 +65,23 @@ impl std::fmt::Display for RegistryError {
             ),
             AlgorithmNotFound => write!(f, "The requested algorithm was not found."),
             CoreNotFound => write!(f, "The requested core was not found."),
+            DebugSequenceNotFound(ref name) => {
+                write!(f, "The debug sequence '{}' was not found.", name)
+            }
             RamMissing => write!(f, "No RAM description was found."),
             FlashMissing => write!(f, "No flash description was found."),
+            InvalidFlashAlgorithmRam => write!(
+                f,
+                "The RAM for the flash algorithms is not inside a RAM region."
+            ),
+            InvalidTarget(ref errors) => {
+                write!(f, "The target description is invalid:")?;
+                for error in errors {
+                    write!(f, "\n    {}", error)?;
+                }
+                Ok(())
+            }
+            FlashAlgorithm(ref e) => e.fmt(f),
             Io(ref e) => e.fmt(f),
             Yaml(ref e) => e.fmt(f),
         }
@@ -68,6 +94,12 @@ impl From<std::io::Error> for RegistryError {
     }
 }
 
+impl From<FlashAlgorithmError> for RegistryError {
+    fn from(value: FlashAlgorithmError) -> RegistryError {
+        RegistryError::FlashAlgorithm(value)
+    }
+}
+
 impl From<serde_yaml::Error> for RegistryError {
     fn from(value: serde_yaml::Error) -> RegistryError {
         RegistryError::Yaml(value)
@@ -98,7 +130,7 @@ impl Registry {
     }
 
     pub fn get_target(&self, strategy: SelectionStrategy) -> Result<Target, RegistryError> {
-        let (family, chip, flash_algorithm) = match strategy {
+        let (family, chip, flash_algorithm_name) = match strategy {
             SelectionStrategy::TargetIdentifier(identifier) => {
                 // Try get the corresponding chip.
                 let mut selected_family_and_chip = None;
@@ -124,22 +156,7 @@ impl Registry {
                 }
                 let (family, chip) = selected_family_and_chip.ok_or(RegistryError::ChipNotFound)?;
 
-                // Try get the correspnding flash algorithm.
-                let flash_algorithm = family
-                    .flash_algorithms
-                    .iter()
-                    .find(|flash_algorithm| {
-                        if let Some(flash_algorithm_name) = identifier.flash_algorithm_name.clone()
-                        {
-                            flash_algorithm.name == flash_algorithm_name
-                        } else {
-                            flash_algorithm.default
-                        }
-                    })
-                    .or_else(|| family.flash_algorithms.first())
-                    .ok_or(RegistryError::AlgorithmNotFound)?;
-
-                (family, chip, flash_algorithm)
+                (family, chip, identifier.flash_algorithm_name)
             }
             SelectionStrategy::ChipInfo(chip_info) => {
                 // Try get the corresponding chip.
@@ -160,45 +177,120 @@ impl Registry {
                 let (family, chip) =
                     selected_family_and_chip.ok_or(RegistryError::ChipAutodetectFailed)?;
 
-                // Try get the correspnding flash algorithm.
-                let flash_algorithm = family
-                    .flash_algorithms
-                    .first()
-                    .ok_or(RegistryError::AlgorithmNotFound)?;
-
-                (family, chip, flash_algorithm)
+                (family, chip, None)
             }
         };
 
-        // Try get the corresponding chip.
-        let core = if let Some(core) = get_core(&family.core) {
-            core
+        if family.flash_algorithms.is_empty() {
+            return Err(RegistryError::AlgorithmNotFound);
+        }
+
+        // Try get the corresponding cores.
+        // A chip without a core description has a single core, so there always is a main core.
+        let cores = if chip.cores.is_empty() {
+            vec![TargetCore {
+                name: "main".to_owned(),
+                core: get_core(&family.core).ok_or(RegistryError::CoreNotFound)?,
+                ap: 0,
+                scs_base: DEFAULT_SCS_BASE,
+            }]
         } else {
-            return Err(RegistryError::CoreNotFound);
+            chip.cores
+                .iter()
+                .map(|core| {
+                    Ok(TargetCore {
+                        name: core.name.clone(),
+                        core: get_core(&core.core_type).ok_or(RegistryError::CoreNotFound)?,
+                        ap: core.ap,
+                        scs_base: core.scs_base,
+                    })
+                })
+                .collect::<Result<Vec<_>, RegistryError>>()?
+        };
+
+        let debug_sequence = match &family.debug_sequence {
+            Some(name) => get_sequence(name)
+                .ok_or_else(|| RegistryError::DebugSequenceNotFound(name.clone()))?,
+            None => Box::new(Generic),
         };
 
         let mut ram = None;
-        let mut flash = None;
+        let mut flash_regions = vec![];
         for region in &chip.memory_map {
             match region {
                 MemoryRegion::Ram(r) => ram = Some(r),
-                MemoryRegion::Flash(r) => flash = Some(r),
+                MemoryRegion::Flash(r) => flash_regions.push(r),
                 _ => (),
             };
         }
+        let ram = ram.ok_or(RegistryError::RamMissing)?;
+
+        // The flash algorithms may be restricted to a part of the RAM.
+        let algorithm_ram = match &chip.flash_algorithm_ram {
+            Some(range) => {
+                let inside_ram = chip.memory_map.iter().any(|region| match region {
+                    MemoryRegion::Ram(r) => {
+                        r.range.start <= range.start && range.end <= r.range.end
+                    }
+                    _ => false,
+                });
+                if range.start >= range.end || !inside_ram {
+                    return Err(RegistryError::InvalidFlashAlgorithmRam);
+                }
+                range.clone()
+            }
+            None => ram.range.clone(),
+        };
+
+        if flash_regions.is_empty() {
+            return Err(RegistryError::FlashMissing);
+        }
+
+        // Every flash region is programmed by its own algorithm.
+        let mut flash_algorithms = vec![];
+        for region in flash_regions {
+            match select_flash_algorithm(
+                &family.flash_algorithms,
+                region,
+                flash_algorithm_name.as_deref(),
+            ) {
+                Some(algorithm) => {
+                    flash_algorithms.push(algorithm.assemble(&algorithm_ram, region)?)
+                }
+                None => log::warn!(
+                    "No flash algorithm found for the flash region {:#010x}..{:#010x}.",
+                    region.range.start,
+                    region.range.end
+                ),
+            }
+        }
 
         Ok(Target::new(
             chip,
-            ram.ok_or(RegistryError::RamMissing)?,
-            flash.ok_or(RegistryError::FlashMissing)?,
-            flash_algorithm,
-            core,
+            flash_algorithms,
+            cores,
+            family.config_regions.clone(),
+            debug_sequence,
         ))
     }
 
+    /// Adds the chip family described by the YAML file at `path_to_yaml`.
+    ///
+    /// The description is validated first, an existing family of the same name is replaced.
     pub fn add_target_from_yaml(&mut self, path_to_yaml: &Path) -> Result<(), RegistryError> {
         let file = File::open(path_to_yaml)?;
-        let chip = ChipFamily::from_yaml_reader(file)?;
+        let value: serde_yaml::Value = serde_yaml::from_reader(file)?;
+
+        let mut validator = Validator::new();
+        validator.validate_family(&path_to_yaml.display().to_string(), &value);
+        for warning in validator.warnings() {
+            log::warn!("{}", warning);
+        }
+        if !validator.errors().is_empty() {
+            return Err(RegistryError::InvalidTarget(validator.into_errors()));
+        }
+
+        let chip: ChipFamily = serde_yaml::from_value(value)?;
 
         let index = self
             .families
@@ -211,6 +303,56 @@ impl Registry {
 
         Ok(())
     }
+
+    /// Validates all chip families of the registry with `validator`.
+    pub fn validate(&self, validator: &mut Validator) -> Result<(), RegistryError> {
+        for family in &self.families {
+            let value = serde_yaml::to_value(family)?;
+            validator.validate_family(&format!("<built-in {}>", family.name), &value);
+        }
+
+        Ok(())
+    }
+}
+
+/// Validates the chip family described by the YAML file at `path` with `validator`.
+pub fn validate_yaml(validator: &mut Validator, path: &Path) -> Result<(), RegistryError> {
+    let file = File::open(path)?;
+    let value: serde_yaml::Value = serde_yaml::from_reader(file)?;
+    validator.validate_family(&path.display().to_string(), &value);
+
+    Ok(())
+}
+
+/// Selects the algorithm which programs the flash `region`.
+///
+/// Algorithms dedicated to an address range covering the region are preferred over the ones without a range.
+/// Among them, the algorithm called `name` is used if given, then the default one, then the first one.
+fn select_flash_algorithm<'a>(
+    algorithms: &'a [RawFlashAlgorithm],
+    region: &FlashRegion,
+    name: Option<&str>,
+) -> Option<&'a RawFlashAlgorithm> {
+    let mut candidates = algorithms
+        .iter()
+        .filter(|algorithm| algorithm.flash_range.is_some() && algorithm.covers(region))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        candidates = algorithms
+            .iter()
+            .filter(|algorithm| algorithm.flash_range.is_none())
+            .collect();
+    }
+
+    candidates
+        .iter()
+        .find(|algorithm| match name {
+            Some(name) => algorithm.name == name,
+            None => algorithm.default,
+        })
+        .or_else(|| candidates.iter().find(|algorithm| algorithm.default))
+        .or_else(|| candidates.first())
+        .copied()
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
@@ -265,4 +407,127 @@ mod tests {
             .get_target(SelectionStrategy::TargetIdentifier("nrf51822_Xxaa".into()))
             .is_ok());
     }
+
+    #[test]
+    fn assemble_builtin_targets() {
+        let registry = Registry::from_builtin_families();
+        for family in registry.families() {
+            for variant in family.variants() {
+                let target = registry.get_target(SelectionStrategy::TargetIdentifier(
+                    variant.name.as_str().into(),
+                ));
+                match target {
+                    Ok(target) => assert!(!target.flash_algorithms.is_empty()),
+                    // Not all core types of the built-in targets are supported yet.
+                    Err(RegistryError::CoreNotFound) => (),
+                    Err(e) => panic!("Failed to get the target {}: {}", variant.name, e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn validate_builtin_targets() {
+        let registry = Registry::from_builtin_families();
+        let mut validator = Validator::new();
+        registry.validate(&mut validator).unwrap();
+
+        assert_eq!(validator.errors(), &[]);
+    }
+
+    #[test]
+    fn select_flash_algorithm_by_range() {
+        let algorithm = |name: &str, default, flash_range| RawFlashAlgorithm {
+            name: name.to_owned(),
+            default,
+            flash_range,
+            ..Default::default()
+        };
+        let region = |range| FlashRegion {
+            range,
+            is_boot_memory: false,
+            sectors: vec![],
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+        };
+        let algorithms = vec![
+            algorithm("main", true, None),
+            algorithm("other", false, None),
+            algorithm("uicr", false, Some(0x1000_1000..0x1000_2000)),
+        ];
+
+        let selected = |range, name| {
+            select_flash_algorithm(&algorithms, &region(range), name).map(|a| a.name.as_str())
+        };
+
+        assert_eq!(selected(0x0..0x8_0000, None), Some("main"));
+        assert_eq!(selected(0x0..0x8_0000, Some("other")), Some("other"));
+        assert_eq!(selected(0x0..0x8_0000, Some("unknown")), Some("main"));
+        // A dedicated algorithm is preferred, even if another one is requested.
+        assert_eq!(
+            selected(0x1000_1000..0x1000_2000, Some("other")),
+            Some("uicr")
+        );
+    }
+
+    #[test]
+    fn flash_algorithm_ram_from_yaml() {
+        let yaml = r#"
+name: Test
+manufacturer: ~
+core: M4
+variants:
+  - name: Test_Restricted
+    memory_map:
+      - Ram:
+          range:
+            start: 0x20000000
+            end: 0x20010000
+          is_boot_memory: false
+      - Flash:
+          range:
+            start: 0x0
+            end: 0x10000
+          is_boot_memory: true
+          sectors:
+            - offset: 0x0
+              size: 0x1000
+          page_size: 0x400
+          erased_byte_value: 0xFF
+    flash_algorithm_ram:
+      start: 0x20008000
+      end: 0x20010000
+flash_algorithms:
+  - name: test
+    description: Test algorithm
+    default: true
+    instructions: [0xE7FEBE00, 0xE7FEBE00, 0xE7FEBE00, 0xE7FEBE00]
+    pc_init: ~
+    pc_uninit: ~
+    pc_program_page: 0x1
+    pc_erase_sector: 0x5
+    pc_erase_all: ~
+    pc_verify: ~
+    data_section_offset: 0x10
+"#;
+        let path = std::env::temp_dir().join(format!("probe-rs-{}.yaml", std::process::id()));
+        std::fs::write(&path, yaml).unwrap();
+        let mut registry = Registry::from_builtin_families();
+        let result = registry.add_target_from_yaml(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let target = registry
+            .get_target(SelectionStrategy::TargetIdentifier(
+                "Test_Restricted".into(),
+            ))
+            .unwrap();
+        let algorithm = &target.flash_algorithms[0];
+        let ram = 0x2000_8000..0x2001_0000;
+        assert_eq!(algorithm.load_address, 0x2000_8200);
+        assert!(ram.contains(&algorithm.begin_stack));
+        for buffer in &algorithm.page_buffers {
+            assert!(ram.contains(buffer) && ram.contains(&(buffer + 0x400 - 1)));
+        }
+    }
 }
@@ -1,40 +1,79 @@
 use super::chip::Chip;
-use super::flash_algorithm::{FlashAlgorithm, RawFlashAlgorithm};
-use super::memory::{FlashRegion, MemoryRegion, RamRegion};
+use super::config_region::ConfigRegion;
+use super::flash_algorithm::FlashAlgorithm;
+use super::memory::{FlashRegion, MemoryRegion};
 use super::registry::TargetIdentifier;
+use crate::sequences::DebugSequence;
 use crate::target::Core;
 
+/// A single core of a target.
+#[derive(Debug, Clone)]
+pub struct TargetCore {
+    /// The name of the core.
+    pub name: String,
+    /// The core type.
+    pub core: Box<dyn Core>,
+    /// The number of the memory AP through which the core is accessed.
+    pub ap: u8,
+    /// The base address of the System Control Space of the core.
+    pub scs_base: u32,
+}
+
 /// This describes a complete target with a fixed chip model and variant.
 #[derive(Debug, Clone)]
 pub struct Target {
     /// The complete identifier of the target.
     pub identifier: TargetIdentifier,
-    /// The name of the flash algorithm.
-    pub flash_algorithm: Option<FlashAlgorithm>,
-    /// The core type.
-    pub core: Box<dyn Core>,
+    /// The flash algorithms of the target, one for each flash region which can be programmed.
+    pub flash_algorithms: Vec<FlashAlgorithm>,
+    /// The cores of the target. There is always at least one core.
+    pub cores: Vec<TargetCore>,
     /// The memory map of the target.
     pub memory_map: Vec<MemoryRegion>,
+    /// The regions which hold configuration outside of the main flash.
+    pub config_regions: Vec<ConfigRegion>,
+    /// The vendor specific sequence to unlock, erase, reset and attach to the target.
+    pub debug_sequence: Box<dyn DebugSequence>,
 }
 
 pub type TargetParseError = serde_yaml::Error;
 
 impl Target {
+    /// Creates a new target.
+    ///
+    /// The `flash_algorithms` have to be assembled for the flash regions of the `chip`.
+    /// `cores` must contain at least one core, the first one is used as the main core.
     pub fn new(
         chip: &Chip,
-        ram: &RamRegion,
-        flash: &FlashRegion,
-        flash_algorithm: &RawFlashAlgorithm,
-        core: Box<dyn Core>,
+        flash_algorithms: Vec<FlashAlgorithm>,
+        cores: Vec<TargetCore>,
+        config_regions: Vec<ConfigRegion>,
+        debug_sequence: Box<dyn DebugSequence>,
     ) -> Target {
         Target {
             identifier: TargetIdentifier {
                 chip_name: chip.name.clone(),
-                flash_algorithm_name: Some(flash_algorithm.name.clone()),
+                flash_algorithm_name: flash_algorithms.first().map(|a| a.name.clone()),
             },
-            flash_algorithm: Some(flash_algorithm.assemble(ram, flash)),
-            core,
+            flash_algorithms,
+            cores,
             memory_map: chip.memory_map.clone(),
+            config_regions,
+            debug_sequence,
         }
     }
+
+    /// Returns the flash algorithm which programs the given flash region.
+    pub fn flash_algorithm_for_region(&self, region: &FlashRegion) -> Option<&FlashAlgorithm> {
+        self.flash_algorithms
+            .iter()
+            .find(|algorithm| algorithm.flash_range == region.range)
+    }
+
+    /// Returns the main core of the target.
+    ///
+    /// This is the core which is used to run the flash algorithm.
+    pub fn main_core(&self) -> &TargetCore {
+        &self.cores[0]
+    }
 }
@@ -0,0 +1,892 @@
+//! Checks target descriptions before they are used.
+//!
+//! The checks work on the YAML values of chip families, so the same rules apply to the
+//! built-in targets in the build script, to targets loaded at runtime and to the
+//! `cli target validate` command. Every finding names the file and the path of the field,
+//! e.g. `targets/nRF52 Series.yaml: variants[1].memory_map[0].Flash.page_size`.
+
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+/// A finding of the `Validator`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// The file which contains the chip family.
+    pub file: String,
+    /// The path of the field inside the chip family. It is empty for the family itself.
+    pub field: String,
+    /// What is wrong with the field.
+    pub message: String,
+}
+
+impl Error for ValidationError {}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.file, self.field, self.message)
+        }
+    }
+}
+
+/// Validates chip families and collects the errors and warnings.
+///
+/// Errors make a description unusable, while warnings point out descriptions which work,
+/// but probably not as intended. Part numbers and variant names are also checked across
+/// all families passed to the same validator.
+#[derive(Debug, Default)]
+pub struct Validator {
+    file: String,
+    errors: Vec<ValidationError>,
+    warnings: Vec<ValidationError>,
+    /// The variants by their lowercase name.
+    names: HashMap<String, String>,
+    /// The variants by their manufacturer code and part number.
+    parts: HashMap<(u64, u64, u16), String>,
+    /// Whether flash algorithms may be loaded from FLM files.
+    flm_files: bool,
+}
+
+/// The names of the debug sequences known to `sequences::get_sequence`.
+///
+/// The list is kept here, as the build script can't use the sequences.
+pub const SEQUENCE_NAMES: &[&str] = &["generic", "nrf52", "stm32", "kinetis"];
+
+/// Returns the path of the field `key` inside of `path`.
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Returns the path of the element `index` of the sequence at `path`.
+fn index(path: &str, index: usize) -> String {
+    format!("{}[{}]", path, index)
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|v| u32::try_from(v).ok())
+}
+
+fn as_range(value: &Value) -> Option<Range<u32>> {
+    Some(as_u32(value.get("start")?)?..as_u32(value.get("end")?)?)
+}
+
+fn overlaps(a: &Range<u32>, b: &Range<u32>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a validator which accepts flash algorithms loaded from FLM files.
+    ///
+    /// Only the build script loads FLM files, so they are rejected in descriptions loaded at runtime.
+    pub fn with_flm_files() -> Self {
+        Self {
+            flm_files: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the errors found so far.
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.errors
+    }
+
+    /// Returns the warnings found so far.
+    pub fn warnings(&self) -> &[ValidationError] {
+        &self.warnings
+    }
+
+    /// Consumes the validator and returns its errors.
+    pub fn into_errors(self) -> Vec<ValidationError> {
+        self.errors
+    }
+
+    fn finding(&self, path: &str, message: String) -> ValidationError {
+        ValidationError {
+            file: self.file.clone(),
+            field: path.to_owned(),
+            message,
+        }
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        let error = self.finding(path, message);
+        self.errors.push(error);
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        let warning = self.finding(path, message);
+        self.warnings.push(warning);
+    }
+
+    /// Returns the field `key` of `value` converted by `convert`.
+    ///
+    /// Records an error if the field can't be converted, or if it is `required` but missing.
+    /// Fields set to `null` count as missing.
+    fn get<'v, T>(
+        &mut self,
+        value: &'v Value,
+        path: &str,
+        key: &str,
+        required: bool,
+        expected: &str,
+        convert: impl Fn(&'v Value) -> Option<T>,
+    ) -> Option<T> {
+        match value.get(key).filter(|v| !v.is_null()) {
+            Some(field) => {
+                let converted = convert(field);
+                if converted.is_none() {
+                    self.error(&join(path, key), format!("expected {}", expected));
+                }
+                converted
+            }
+            None => {
+                if required {
+                    self.error(&join(path, key), "missing field".to_owned());
+                }
+                None
+            }
+        }
+    }
+
+    fn u32(&mut self, value: &Value, path: &str, key: &str, required: bool) -> Option<u32> {
+        self.get(
+            value,
+            path,
+            key,
+            required,
+            "an unsigned 32 bit number",
+            as_u32,
+        )
+    }
+
+    fn u8(&mut self, value: &Value, path: &str, key: &str, required: bool) -> Option<u8> {
+        self.get(
+            value,
+            path,
+            key,
+            required,
+            "an unsigned 8 bit number",
+            |v| v.as_u64().and_then(|v| u8::try_from(v).ok()),
+        )
+    }
+
+    fn string<'v>(
+        &mut self,
+        value: &'v Value,
+        path: &str,
+        key: &str,
+        required: bool,
+    ) -> Option<&'v str> {
+        self.get(value, path, key, required, "a string", Value::as_str)
+    }
+
+    fn boolean(&mut self, value: &Value, path: &str, key: &str, required: bool) -> Option<bool> {
+        self.get(value, path, key, required, "a boolean", Value::as_bool)
+    }
+
+    fn sequence<'v>(
+        &mut self,
+        value: &'v Value,
+        path: &str,
+        key: &str,
+        required: bool,
+    ) -> Option<&'v Vec<Value>> {
+        self.get(value, path, key, required, "a list", Value::as_sequence)
+    }
+
+    /// Returns the range in the field `key` of `value` and checks that it is not empty.
+    fn range(
+        &mut self,
+        value: &Value,
+        path: &str,
+        key: &str,
+        required: bool,
+    ) -> Option<Range<u32>> {
+        let range = self.get(
+            value,
+            path,
+            key,
+            required,
+            "a range with an unsigned 32 bit start and end",
+            as_range,
+        )?;
+        if range.start >= range.end {
+            self.error(
+                &join(path, key),
+                format!(
+                    "the range {:#010x}..{:#010x} is empty",
+                    range.start, range.end
+                ),
+            );
+            return None;
+        }
+        Some(range)
+    }
+
+    /// Validates the chip family `family`, which was read from `file`.
+    pub fn validate_family(&mut self, file: &str, family: &Value) {
+        self.file = file.to_owned();
+
+        if family.as_mapping().is_none() {
+            self.error("", "expected a chip family".to_owned());
+            return;
+        }
+
+        self.string(family, "", "name", true);
+        self.string(family, "", "core", true);
+        if let Some(sequence) = self.string(family, "", "debug_sequence", false) {
+            if !SEQUENCE_NAMES.contains(&&sequence.to_ascii_lowercase()[..]) {
+                self.error(
+                    "debug_sequence",
+                    format!(
+                        "unknown debug sequence {}, expected one of {}",
+                        sequence,
+                        SEQUENCE_NAMES.join(", ")
+                    ),
+                );
+            }
+        }
+        let manufacturer = self.get(
+            family,
+            "",
+            "manufacturer",
+            false,
+            "a JEP106 code with an 8 bit cc and id",
+            |m| {
+                let code = |key| m.get(key)?.as_u64().filter(|&v| v <= u64::from(u8::MAX));
+                Some((code("cc")?, code("id")?))
+            },
+        );
+
+        if let Some(variants) = self.sequence(family, "", "variants", true) {
+            if variants.is_empty() {
+                self.error(
+                    "variants",
+                    "a chip family needs at least one variant".to_owned(),
+                );
+            }
+            for (i, variant) in variants.iter().enumerate() {
+                self.validate_variant(&index("variants", i), variant, manufacturer);
+            }
+        }
+
+        if let Some(algorithms) = self.sequence(family, "", "flash_algorithms", true) {
+            let mut names = HashMap::new();
+            for (i, algorithm) in algorithms.iter().enumerate() {
+                let path = index("flash_algorithms", i);
+                if let Some(name) = self.validate_algorithm(&path, algorithm) {
+                    if let Some(other) = names.insert(name.to_ascii_lowercase(), path.clone()) {
+                        self.error(
+                            &join(&path, "name"),
+                            format!("the algorithm {} is already described in {}", name, other),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(regions) = self.sequence(family, "", "config_regions", false) {
+            let mut names = HashMap::new();
+            for (i, region) in regions.iter().enumerate() {
+                let path = index("config_regions", i);
+                if let Some(name) = self.validate_config_region(&path, region) {
+                    if let Some(other) = names.insert(name.to_ascii_lowercase(), path.clone()) {
+                        self.error(
+                            &join(&path, "name"),
+                            format!("the region {} is already described in {}", name, other),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn validate_variant(&mut self, path: &str, variant: &Value, manufacturer: Option<(u64, u64)>) {
+        let name = self.string(variant, path, "name", true);
+        let part = self.get(
+            variant,
+            path,
+            "part",
+            false,
+            "an unsigned 16 bit number",
+            |v| v.as_u64().and_then(|v| u16::try_from(v).ok()),
+        );
+
+        if let Some(name) = name {
+            let location = format!("{}: {}", self.file, path);
+            if let Some(other) = self.names.insert(name.to_ascii_lowercase(), location) {
+                self.error(
+                    &join(path, "name"),
+                    format!("the variant {} is already described in {}", name, other),
+                );
+            }
+
+            if let (Some((cc, id)), Some(part)) = (manufacturer, part) {
+                if let Some(other) = self.parts.insert((cc, id, part), name.to_owned()) {
+                    self.warning(
+                        &join(path, "part"),
+                        format!(
+                            "the part number {:#x} is also used by {}, so the variants can't be told apart when the chip is detected",
+                            part, other
+                        ),
+                    );
+                }
+            }
+        }
+
+        let mut ram = vec![];
+        let mut flash = 0;
+        if let Some(regions) = self.sequence(variant, path, "memory_map", true) {
+            let mut ranges: Vec<(String, Range<u32>)> = vec![];
+            for (i, region) in regions.iter().enumerate() {
+                let path = index(&join(path, "memory_map"), i);
+                let range = if let Some(region) = region.get("Ram") {
+                    let range = self.range(region, &join(&path, "Ram"), "range", true);
+                    self.boolean(region, &join(&path, "Ram"), "is_boot_memory", true);
+                    ram.extend(range.clone());
+                    range
+                } else if let Some(region) = region.get("Flash") {
+                    flash += 1;
+                    self.validate_flash_region(&join(&path, "Flash"), region)
+                } else if let Some(region) = region.get("Generic") {
+                    self.range(region, &join(&path, "Generic"), "range", true)
+                } else {
+                    self.error(&path, "expected a Ram, Flash or Generic region".to_owned());
+                    None
+                };
+
+                if let Some(range) = range {
+                    if let Some((other, _)) = ranges.iter().find(|(_, r)| overlaps(r, &range)) {
+                        self.error(&path, format!("the region overlaps with {}", other));
+                    }
+                    ranges.push((path, range));
+                }
+            }
+
+            let path = join(path, "memory_map");
+            if ram.is_empty() {
+                self.error(&path, "a variant needs at least one RAM region".to_owned());
+            }
+            if flash == 0 {
+                self.error(
+                    &path,
+                    "a variant needs at least one flash region".to_owned(),
+                );
+            }
+        }
+
+        if let Some(cores) = self.sequence(variant, path, "cores", false) {
+            let mut names = HashMap::new();
+            for (i, core) in cores.iter().enumerate() {
+                let path = index(&join(path, "cores"), i);
+                self.string(core, &path, "type", true);
+                self.u8(core, &path, "ap", false);
+                self.u32(core, &path, "scs_base", false);
+                if let Some(name) = self.string(core, &path, "name", true) {
+                    if let Some(other) = names.insert(name.to_owned(), path.clone()) {
+                        self.error(
+                            &join(&path, "name"),
+                            format!("the core {} is already described in {}", name, other),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(range) = self.range(variant, path, "flash_algorithm_ram", false) {
+            let path = join(path, "flash_algorithm_ram");
+            if !ram
+                .iter()
+                .any(|r| r.start <= range.start && range.end <= r.end)
+            {
+                self.error(&path, "the range is not inside a RAM region".to_owned());
+            }
+            // The stack of the flash algorithms starts at the beginning of the range.
+            if range.start % 8 != 0 {
+                self.error(
+                    &path,
+                    format!(
+                        "the start {:#010x} is not aligned to 8 bytes, as the stack pointer requires",
+                        range.start
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Validates a flash region and returns its range.
+    fn validate_flash_region(&mut self, path: &str, region: &Value) -> Option<Range<u32>> {
+        let range = self.range(region, path, "range", true);
+        self.boolean(region, path, "is_boot_memory", true);
+        self.u8(region, path, "erased_byte_value", true);
+        let page_size = self.u32(region, path, "page_size", true);
+        let sectors = self.sequence(region, path, "sectors", true);
+
+        let page_size = match page_size {
+            Some(0) => {
+                self.error(&join(path, "page_size"), "the page size is 0".to_owned());
+                None
+            }
+            page_size => page_size,
+        };
+        if let (Some(range), Some(page_size)) = (&range, page_size) {
+            if range.start % page_size != 0 {
+                self.error(
+                    &join(path, "range"),
+                    format!(
+                        "the start {:#010x} is not aligned to the page size {:#x}",
+                        range.start, page_size
+                    ),
+                );
+            }
+        }
+
+        let sectors = sectors?;
+        if sectors.is_empty() {
+            self.error(
+                &join(path, "sectors"),
+                "a flash region needs at least one sector group".to_owned(),
+            );
+        }
+
+        // The sector groups are ordered by offset and each one starts at a sector boundary of the previous one.
+        // Groups starting behind the end of the region are unused, as smaller chips often share the layout
+        // of the larger ones.
+        let length = range.as_ref().map(|range| range.end - range.start);
+        let mut previous: Option<(u32, u32)> = None;
+        let mut last = None;
+        for (i, sector) in sectors.iter().enumerate() {
+            let path = index(&join(path, "sectors"), i);
+            let offset = self.u32(sector, &path, "offset", true);
+            let size = self.u32(sector, &path, "size", true);
+            let (offset, size) = match (offset, size) {
+                (Some(offset), Some(size)) => (offset, size),
+                _ => {
+                    previous = None;
+                    continue;
+                }
+            };
+
+            if size == 0 {
+                self.error(&join(&path, "size"), "the sector size is 0".to_owned());
+                previous = None;
+                continue;
+            }
+            if let Some(page_size) = page_size {
+                if size % page_size != 0 {
+                    self.error(
+                        &join(&path, "size"),
+                        format!(
+                            "the sector size {:#x} is not a multiple of the page size {:#x}",
+                            size, page_size
+                        ),
+                    );
+                }
+            }
+
+            match previous {
+                None if i == 0 && offset != 0 => self.error(
+                    &join(&path, "offset"),
+                    "the first sector group has to start at offset 0".to_owned(),
+                ),
+                Some((previous_offset, previous_size))
+                    if offset <= previous_offset
+                        || (offset - previous_offset) % previous_size != 0 =>
+                {
+                    self.error(
+                        &join(&path, "offset"),
+                        format!(
+                            "the offset {:#x} is not a sector boundary after the previous group",
+                            offset
+                        ),
+                    )
+                }
+                _ => (),
+            }
+            previous = Some((offset, size));
+            if let Some(length) = length {
+                if offset < length {
+                    last = previous;
+                }
+            }
+        }
+
+        if let (Some(range), Some(length), Some((offset, size))) = (&range, length, last) {
+            if (length - offset) % size != 0 {
+                self.error(
+                    &join(path, "range"),
+                    format!(
+                        "the end {:#010x} is not a sector boundary of the last sector group",
+                        range.end
+                    ),
+                );
+            }
+        }
+
+        range
+    }
+
+    /// Validates a flash algorithm and returns its name.
+    fn validate_algorithm<'v>(&mut self, path: &str, algorithm: &'v Value) -> Option<&'v str> {
+        let name = self.string(algorithm, path, "name", true);
+        let flm = self.string(algorithm, path, "flm", false).is_some();
+        self.boolean(algorithm, path, "default", true);
+        // The description of an FLM file is used if none is given.
+        self.string(algorithm, path, "description", !flm);
+        self.range(algorithm, path, "flash_range", false);
+        self.u32(algorithm, path, "program_page_timeout", false);
+        self.u32(algorithm, path, "erase_sector_timeout", false);
+        self.u32(algorithm, path, "stack_size", false);
+
+        if flm {
+            if !self.flm_files {
+                self.error(
+                    &join(path, "flm"),
+                    "FLM files are only supported in the built-in targets".to_owned(),
+                );
+            }
+            // The code and the entry points of an FLM file are checked when the file is parsed.
+            return name;
+        }
+
+        let size = match self.sequence(algorithm, path, "instructions", true) {
+            Some(instructions) => {
+                for (i, instruction) in instructions.iter().enumerate() {
+                    if as_u32(instruction).is_none() {
+                        self.error(
+                            &index(&join(path, "instructions"), i),
+                            "expected an unsigned 32 bit number".to_owned(),
+                        );
+                    }
+                }
+                Some(instructions.len() as u64 * 4)
+            }
+            None => None,
+        };
+
+        // The entry points are offsets into the instructions, with the Thumb bit set.
+        for &(key, required) in &[
+            ("pc_init", false),
+            ("pc_uninit", false),
+            ("pc_program_page", true),
+            ("pc_erase_sector", true),
+            ("pc_erase_all", false),
+            ("pc_verify", false),
+        ] {
+            let pc = self.u32(algorithm, path, key, required);
+            if let (Some(pc), Some(size)) = (pc, size) {
+                if u64::from(pc & !1) >= size {
+                    self.error(
+                        &join(path, key),
+                        format!(
+                            "the entry point {:#x} is outside of the {} bytes of instructions",
+                            pc, size
+                        ),
+                    );
+                }
+            }
+        }
+
+        let data_section_offset = self.u32(algorithm, path, "data_section_offset", true);
+        if let (Some(offset), Some(size)) = (data_section_offset, size) {
+            if u64::from(offset) > size {
+                self.error(
+                    &join(path, "data_section_offset"),
+                    format!(
+                        "the offset {:#x} is outside of the {} bytes of instructions",
+                        offset, size
+                    ),
+                );
+            }
+        }
+
+        name
+    }
+
+    /// Validates a config region and returns its name.
+    fn validate_config_region<'v>(&mut self, path: &str, region: &'v Value) -> Option<&'v str> {
+        let name = self.string(region, path, "name", true);
+        let range = self.range(region, path, "range", true);
+        self.boolean(region, path, "requires_erase", false);
+
+        if let Some(steps) = self.sequence(region, path, "erase", false) {
+            self.validate_config_steps(&join(path, "erase"), steps);
+        }
+        if let Some(steps) = self.sequence(region, path, "program", true) {
+            self.validate_config_steps(&join(path, "program"), steps);
+            if !steps.iter().any(|step| step.as_str() == Some("WriteWord")) {
+                self.error(
+                    &join(path, "program"),
+                    "the program sequence never writes the word".to_owned(),
+                );
+            }
+        }
+
+        if let Some(fields) = self.sequence(region, path, "fields", true) {
+            let mut names = HashMap::new();
+            for (i, field) in fields.iter().enumerate() {
+                let path = index(&join(path, "fields"), i);
+                let address = self.u32(field, &path, "address", true);
+                let bit_offset = self.u8(field, &path, "bit_offset", false).unwrap_or(0);
+                let bit_width = self.u8(field, &path, "bit_width", true);
+                self.boolean(field, &path, "dangerous", false);
+
+                if let Some(name) = self.string(field, &path, "name", true) {
+                    if let Some(other) = names.insert(name.to_ascii_lowercase(), path.clone()) {
+                        self.error(
+                            &join(&path, "name"),
+                            format!("the field {} is already described in {}", name, other),
+                        );
+                    }
+                }
+                if let Some(address) = address {
+                    if address % 4 != 0 {
+                        self.error(
+                            &join(&path, "address"),
+                            format!("the address {:#010x} is not aligned to a word", address),
+                        );
+                    }
+                    if let Some(range) = &range {
+                        if address < range.start || u64::from(address) + 4 > u64::from(range.end) {
+                            self.error(
+                                &join(&path, "address"),
+                                format!("the address {:#010x} is outside of the region", address),
+                            );
+                        }
+                    }
+                }
+                if let Some(bit_width) = bit_width {
+                    if bit_width == 0 || u32::from(bit_offset) + u32::from(bit_width) > 32 {
+                        self.error(
+                            &path,
+                            format!(
+                                "{} bits at offset {} don't fit into a word",
+                                bit_width, bit_offset
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+
+        name
+    }
+
+    fn validate_config_steps(&mut self, path: &str, steps: &[Value]) {
+        for (i, step) in steps.iter().enumerate() {
+            let path = index(path, i);
+            if step.as_str() == Some("WriteWord") {
+                continue;
+            }
+
+            if let Some(step) = step.get("Write") {
+                let path = join(&path, "Write");
+                self.u32(step, &path, "address", true);
+                self.u32(step, &path, "value", true);
+            } else if let Some(step) = step.get("Set") {
+                let path = join(&path, "Set");
+                self.u32(step, &path, "address", true);
+                self.u32(step, &path, "mask", true);
+            } else if let Some(step) = step.get("Wait") {
+                let path = join(&path, "Wait");
+                self.u32(step, &path, "address", true);
+                self.u32(step, &path, "mask", true);
+                self.u32(step, &path, "value", true);
+                self.u32(step, &path, "timeout", false);
+            } else {
+                self.error(
+                    &path,
+                    "expected a Write, Set, Wait or WriteWord step".to_owned(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Validator;
+
+    const FAMILY: &str = r#"
+name: Test Series
+core: M4
+manufacturer:
+  cc: 0
+  id: 0x20
+variants:
+  - name: Test_A
+    part: 0x413
+    memory_map:
+      - Ram:
+          range: { start: 0x20000000, end: 0x20010000 }
+          is_boot_memory: false
+      - Flash:
+          range: { start: 0x08000000, end: 0x08030000 }
+          is_boot_memory: true
+          sectors:
+            - { offset: 0x0, size: 0x4000 }
+            - { offset: 0x10000, size: 0x10000 }
+          page_size: 0x400
+          erased_byte_value: 0xFF
+  - name: Test_B
+    part: 0x413
+    memory_map:
+      - Ram:
+          range: { start: 0x20000000, end: 0x20010000 }
+          is_boot_memory: false
+      - Flash:
+          range: { start: 0x08000200, end: 0x08010000 }
+          is_boot_memory: true
+          sectors:
+            - { offset: 0x0, size: 0x4000 }
+            - { offset: 0x6000, size: 0x2100 }
+          page_size: 0x400
+          erased_byte_value: 0xFF
+      - Generic:
+          range: { start: 0x2000f000, end: 0x20011000 }
+flash_algorithms:
+  - name: test
+    description: Test algorithm
+    default: true
+    instructions: [1, 2, 3, 4]
+    pc_init: 1
+    pc_program_page: 0x11
+    pc_erase_sector: 9
+    data_section_offset: 16
+"#;
+
+    #[test]
+    fn find_mistakes() {
+        let family = serde_yaml::from_str(FAMILY).unwrap();
+        let mut validator = Validator::new();
+        validator.validate_family("test.yaml", &family);
+
+        let errors = validator
+            .errors()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "test.yaml: variants[1].memory_map[1].Flash.range: the start 0x08000200 is not aligned to the page size 0x400",
+                "test.yaml: variants[1].memory_map[1].Flash.sectors[1].size: the sector size 0x2100 is not a multiple of the page size 0x400",
+                "test.yaml: variants[1].memory_map[1].Flash.sectors[1].offset: the offset 0x6000 is not a sector boundary after the previous group",
+                "test.yaml: variants[1].memory_map[1].Flash.range: the end 0x08010000 is not a sector boundary of the last sector group",
+                "test.yaml: variants[1].memory_map[2]: the region overlaps with variants[1].memory_map[0]",
+                "test.yaml: flash_algorithms[0].pc_program_page: the entry point 0x11 is outside of the 16 bytes of instructions",
+            ]
+        );
+
+        // Both variants share a part number.
+        assert_eq!(validator.warnings().len(), 1);
+        assert_eq!(validator.warnings()[0].field, "variants[1].part");
+
+        // The variant names are checked across families.
+        validator.validate_family("other.yaml", &family);
+        assert!(validator.errors()[6]
+            .message
+            .starts_with("the variant Test_A is already described in test.yaml: variants[0]"));
+    }
+
+    #[test]
+    fn missing_fields() {
+        let family = serde_yaml::from_str("name: Test\nvariants: 3\nflash_algorithms: []").unwrap();
+        let mut validator = Validator::new();
+        validator.validate_family("test.yaml", &family);
+
+        let errors = validator
+            .errors()
+            .iter()
+            .map(|e| (e.field.as_str(), e.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![("core", "missing field"), ("variants", "expected a list")]
+        );
+    }
+
+    #[test]
+    fn unknown_config_steps() {
+        let region = serde_yaml::from_str(
+            "name: OPT\nrange: { start: 0x1000, end: 0x1004 }\nprogram: [WriteWord, Unlock]\nfields: []",
+        )
+        .unwrap();
+        let mut validator = Validator::new();
+        validator.validate_config_region("config_regions[0]", &region);
+
+        let errors = validator
+            .errors()
+            .iter()
+            .map(|e| (e.field.as_str(), e.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![(
+                "config_regions[0].program[1]",
+                "expected a Write, Set, Wait or WriteWord step"
+            )]
+        );
+    }
+
+    #[test]
+    fn match_the_runtime_loader() {
+        let family = serde_yaml::from_str(
+            r#"
+name: Test
+core: M4
+debug_sequence: nrf53
+manufacturer: { cc: 0x100, id: 0x20 }
+variants: []
+flash_algorithms:
+  - { name: a, default: true, instructions: [1], pc_program_page: 1, pc_erase_sector: 1, data_section_offset: 0 }
+  - { name: b, default: false, flm: b.flm }
+"#,
+        )
+        .unwrap();
+
+        let errors = |mut validator: Validator| {
+            validator.validate_family("test.yaml", &family);
+            validator
+                .into_errors()
+                .into_iter()
+                .map(|e| e.field)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            errors(Validator::new()),
+            vec![
+                "debug_sequence",
+                "manufacturer",
+                "variants",
+                "flash_algorithms[0].description",
+                "flash_algorithms[1].flm",
+            ]
+        );
+        // The build script loads FLM files, which have a description of their own.
+        assert_eq!(
+            errors(Validator::with_flm_files()),
+            vec![
+                "debug_sequence",
+                "manufacturer",
+                "variants",
+                "flash_algorithms[0].description",
+            ]
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::coresight::memory::MI;
 use crate::probe::{DebugProbeError, MasterProbe};
 use crate::target::{
     BasicRegisterAddresses, Core, CoreInformation, CoreRegister, CoreRegisterAddress,
+    RegisterDescription, RegisterGroup,
 };
 use bitfield::bitfield;
 
@@ -250,8 +251,33 @@ pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
     XPSR: CoreRegisterAddress(0b1_0000),
 };
 
-pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b01001);
-pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b01010);
+pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b1_0001);
+pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b1_0010);
+
+/// All registers which can be accessed through the DCRSR register of an ARMv6-M core.
+pub const REGISTER_FILE: &[RegisterDescription] = &[
+    RegisterDescription::new("R0", RegisterGroup::General, 0),
+    RegisterDescription::new("R1", RegisterGroup::General, 1),
+    RegisterDescription::new("R2", RegisterGroup::General, 2),
+    RegisterDescription::new("R3", RegisterGroup::General, 3),
+    RegisterDescription::new("R4", RegisterGroup::General, 4),
+    RegisterDescription::new("R5", RegisterGroup::General, 5),
+    RegisterDescription::new("R6", RegisterGroup::General, 6),
+    RegisterDescription::new("R7", RegisterGroup::General, 7),
+    RegisterDescription::new("R8", RegisterGroup::General, 8),
+    RegisterDescription::new("R9", RegisterGroup::General, 9),
+    RegisterDescription::new("R10", RegisterGroup::General, 10),
+    RegisterDescription::new("R11", RegisterGroup::General, 11),
+    RegisterDescription::new("R12", RegisterGroup::General, 12),
+    RegisterDescription::new("SP", RegisterGroup::General, 13),
+    RegisterDescription::new("LR", RegisterGroup::General, 14),
+    RegisterDescription::new("PC", RegisterGroup::General, 15),
+    RegisterDescription::new("XPSR", RegisterGroup::Special, 16),
+    RegisterDescription::new("MSP", RegisterGroup::Special, 17),
+    RegisterDescription::new("PSP", RegisterGroup::Special, 18),
+    RegisterDescription::packed("CONTROL", RegisterGroup::Special, 20, 24, 2),
+    RegisterDescription::packed("PRIMASK", RegisterGroup::Special, 20, 0, 1),
+];
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct M0;
@@ -463,6 +489,10 @@ impl Core for M0 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn register_file(&self) -> &'static [RegisterDescription] {
+        REGISTER_FILE
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -572,4 +602,8 @@ impl Core for FakeM0 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn register_file(&self) -> &'static [RegisterDescription] {
+        REGISTER_FILE
+    }
 }
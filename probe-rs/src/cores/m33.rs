@@ -3,8 +3,10 @@
 
 use crate::coresight::memory::MI;
 use crate::probe::{DebugProbeError, MasterProbe};
+pub use crate::target::SecurityState;
 use crate::target::{
     BasicRegisterAddresses, Core, CoreInformation, CoreRegister, CoreRegisterAddress,
+    RegisterDescription, RegisterGroup,
 };
 
 use bitfield::bitfield;
@@ -25,6 +27,124 @@ impl M33 {
         }
         Err(DebugProbeError::Timeout)
     }
+
+    /// Returns true if the core implements the security extension (TrustZone).
+    pub fn has_security_extension(&self, mi: &mut impl MI) -> Result<bool, DebugProbeError> {
+        let dauthstatus = Dauthstatus(mi.read32(Dauthstatus::ADDRESS)?);
+
+        Ok(dauthstatus.sid() != 0)
+    }
+
+    /// Reads the security state the core is currently executing in.
+    ///
+    /// On cores without the security extension, this is always `NonSecure`.
+    pub fn security_state(&self, mi: &mut impl MI) -> Result<SecurityState, DebugProbeError> {
+        if !self.has_security_extension(mi)? {
+            return Ok(SecurityState::NonSecure);
+        }
+
+        let dscsr = Dscsr(mi.read32(Dscsr::ADDRESS)?);
+
+        Ok(if dscsr.cds() {
+            SecurityState::Secure
+        } else {
+            SecurityState::NonSecure
+        })
+    }
+
+    /// Reads the debug authentication status from the DAUTHSTATUS register.
+    pub fn debug_authentication(
+        &self,
+        mi: &mut impl MI,
+    ) -> Result<DebugAuthentication, DebugProbeError> {
+        let dauthstatus = Dauthstatus(mi.read32(Dauthstatus::ADDRESS)?);
+
+        Ok(DebugAuthentication::from(dauthstatus))
+    }
+
+    /// Reads a banked register of the given security state.
+    ///
+    /// Accessing the secure bank requires secure debug to be enabled, otherwise
+    /// [`DebugProbeError::SecureDebugDisabled`] is returned.
+    ///
+    /// [`DebugProbeError::SecureDebugDisabled`]: ../../probe/enum.DebugProbeError.html#variant.SecureDebugDisabled
+    pub fn read_banked_register(
+        &self,
+        mi: &mut MasterProbe,
+        register: BankedRegister,
+        state: SecurityState,
+    ) -> Result<u32, DebugProbeError> {
+        self.check_bank_access(mi, state)?;
+
+        self.read_register(mi, &register.description(state))
+    }
+
+    /// Writes a banked register of the given security state.
+    ///
+    /// Accessing the secure bank requires secure debug to be enabled, otherwise
+    /// [`DebugProbeError::SecureDebugDisabled`] is returned.
+    ///
+    /// [`DebugProbeError::SecureDebugDisabled`]: ../../probe/enum.DebugProbeError.html#variant.SecureDebugDisabled
+    pub fn write_banked_register(
+        &self,
+        mi: &mut MasterProbe,
+        register: BankedRegister,
+        state: SecurityState,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.check_bank_access(mi, state)?;
+
+        self.write_register(mi, &register.description(state), value)
+    }
+
+    fn check_bank_access(
+        &self,
+        mi: &mut impl MI,
+        state: SecurityState,
+    ) -> Result<(), DebugProbeError> {
+        if state == SecurityState::Secure {
+            let dhcsr = Dhcsr(mi.read32(Dhcsr::ADDRESS)?);
+
+            if !dhcsr.s_sde() {
+                return Err(DebugProbeError::SecureDebugDisabled);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the configuration of the Security Attribution Unit.
+    ///
+    /// The configuration of the Implementation Defined Attribution Unit (IDAU) is not
+    /// architecturally visible to a debugger. Addresses which are not covered by an
+    /// enabled SAU region get their attribution from the IDAU, see [`SauConfiguration`].
+    ///
+    /// The SAU registers are only accessible with secure debug enabled.
+    ///
+    /// [`SauConfiguration`]: struct.SauConfiguration.html
+    pub fn sau_configuration(&self, mi: &mut impl MI) -> Result<SauConfiguration, DebugProbeError> {
+        self.check_bank_access(mi, SecurityState::Secure)?;
+
+        let ctrl = SauCtrl(mi.read32(SauCtrl::ADDRESS)?);
+        let sau_type = SauType(mi.read32(SauType::ADDRESS)?);
+
+        let mut regions = Vec::with_capacity(sau_type.sregion() as usize);
+
+        for number in 0..sau_type.sregion() {
+            mi.write32(SauRnr::ADDRESS, SauRnr(number).into())?;
+
+            let rbar = SauRbar(mi.read32(SauRbar::ADDRESS)?);
+            let rlar = SauRlar(mi.read32(SauRlar::ADDRESS)?);
+
+            regions.push(SauRegion::new(number as u8, rbar, rlar));
+        }
+
+        Ok(SauConfiguration {
+            enabled: ctrl.enable(),
+            all_non_secure: ctrl.allns(),
+            regions,
+        })
+    }
 }
 
 impl Core for M33 {
@@ -198,6 +318,17 @@ impl Core for M33 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn register_file(&self) -> &'static [RegisterDescription] {
+        REGISTER_FILE
+    }
+
+    fn security_state(
+        &self,
+        mi: &mut MasterProbe,
+    ) -> Result<Option<SecurityState>, DebugProbeError> {
+        M33::security_state(self, mi).map(Some)
+    }
 }
 
 pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
@@ -213,6 +344,85 @@ pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
     XPSR: CoreRegisterAddress(0b1_0000),
 };
 
+/// All registers which can be accessed through the DCRSR register of an ARMv8-M Mainline core.
+///
+/// The floating point registers are only present on cores with an FPU, the banked
+/// stack pointers and stack limit registers only on cores with the security extension.
+pub const REGISTER_FILE: &[RegisterDescription] = &[
+    RegisterDescription::new("R0", RegisterGroup::General, 0),
+    RegisterDescription::new("R1", RegisterGroup::General, 1),
+    RegisterDescription::new("R2", RegisterGroup::General, 2),
+    RegisterDescription::new("R3", RegisterGroup::General, 3),
+    RegisterDescription::new("R4", RegisterGroup::General, 4),
+    RegisterDescription::new("R5", RegisterGroup::General, 5),
+    RegisterDescription::new("R6", RegisterGroup::General, 6),
+    RegisterDescription::new("R7", RegisterGroup::General, 7),
+    RegisterDescription::new("R8", RegisterGroup::General, 8),
+    RegisterDescription::new("R9", RegisterGroup::General, 9),
+    RegisterDescription::new("R10", RegisterGroup::General, 10),
+    RegisterDescription::new("R11", RegisterGroup::General, 11),
+    RegisterDescription::new("R12", RegisterGroup::General, 12),
+    RegisterDescription::new("SP", RegisterGroup::General, 13),
+    RegisterDescription::new("LR", RegisterGroup::General, 14),
+    RegisterDescription::new("PC", RegisterGroup::General, 15),
+    RegisterDescription::new("XPSR", RegisterGroup::Special, 16),
+    RegisterDescription::new("MSP", RegisterGroup::Special, 17),
+    RegisterDescription::new("PSP", RegisterGroup::Special, 18),
+    RegisterDescription::packed("CONTROL", RegisterGroup::Special, 20, 24, 4),
+    RegisterDescription::packed("FAULTMASK", RegisterGroup::Special, 20, 16, 1),
+    RegisterDescription::packed("BASEPRI", RegisterGroup::Special, 20, 8, 8),
+    RegisterDescription::packed("PRIMASK", RegisterGroup::Special, 20, 0, 1),
+    RegisterDescription::new("MSP_NS", RegisterGroup::Security, 0x18),
+    RegisterDescription::new("PSP_NS", RegisterGroup::Security, 0x19),
+    RegisterDescription::new("MSP_S", RegisterGroup::Security, 0x1A),
+    RegisterDescription::new("PSP_S", RegisterGroup::Security, 0x1B),
+    RegisterDescription::new("MSPLIM_S", RegisterGroup::Security, 0x1C),
+    RegisterDescription::new("PSPLIM_S", RegisterGroup::Security, 0x1D),
+    RegisterDescription::new("MSPLIM_NS", RegisterGroup::Security, 0x1E),
+    RegisterDescription::new("PSPLIM_NS", RegisterGroup::Security, 0x1F),
+    RegisterDescription::packed("CONTROL_S", RegisterGroup::Security, 0x22, 24, 4),
+    RegisterDescription::packed("FAULTMASK_S", RegisterGroup::Security, 0x22, 16, 1),
+    RegisterDescription::packed("BASEPRI_S", RegisterGroup::Security, 0x22, 8, 8),
+    RegisterDescription::packed("PRIMASK_S", RegisterGroup::Security, 0x22, 0, 1),
+    RegisterDescription::packed("CONTROL_NS", RegisterGroup::Security, 0x23, 24, 4),
+    RegisterDescription::packed("FAULTMASK_NS", RegisterGroup::Security, 0x23, 16, 1),
+    RegisterDescription::packed("BASEPRI_NS", RegisterGroup::Security, 0x23, 8, 8),
+    RegisterDescription::packed("PRIMASK_NS", RegisterGroup::Security, 0x23, 0, 1),
+    RegisterDescription::new("FPSCR", RegisterGroup::FloatingPoint, 33),
+    RegisterDescription::new("S0", RegisterGroup::FloatingPoint, 64),
+    RegisterDescription::new("S1", RegisterGroup::FloatingPoint, 65),
+    RegisterDescription::new("S2", RegisterGroup::FloatingPoint, 66),
+    RegisterDescription::new("S3", RegisterGroup::FloatingPoint, 67),
+    RegisterDescription::new("S4", RegisterGroup::FloatingPoint, 68),
+    RegisterDescription::new("S5", RegisterGroup::FloatingPoint, 69),
+    RegisterDescription::new("S6", RegisterGroup::FloatingPoint, 70),
+    RegisterDescription::new("S7", RegisterGroup::FloatingPoint, 71),
+    RegisterDescription::new("S8", RegisterGroup::FloatingPoint, 72),
+    RegisterDescription::new("S9", RegisterGroup::FloatingPoint, 73),
+    RegisterDescription::new("S10", RegisterGroup::FloatingPoint, 74),
+    RegisterDescription::new("S11", RegisterGroup::FloatingPoint, 75),
+    RegisterDescription::new("S12", RegisterGroup::FloatingPoint, 76),
+    RegisterDescription::new("S13", RegisterGroup::FloatingPoint, 77),
+    RegisterDescription::new("S14", RegisterGroup::FloatingPoint, 78),
+    RegisterDescription::new("S15", RegisterGroup::FloatingPoint, 79),
+    RegisterDescription::new("S16", RegisterGroup::FloatingPoint, 80),
+    RegisterDescription::new("S17", RegisterGroup::FloatingPoint, 81),
+    RegisterDescription::new("S18", RegisterGroup::FloatingPoint, 82),
+    RegisterDescription::new("S19", RegisterGroup::FloatingPoint, 83),
+    RegisterDescription::new("S20", RegisterGroup::FloatingPoint, 84),
+    RegisterDescription::new("S21", RegisterGroup::FloatingPoint, 85),
+    RegisterDescription::new("S22", RegisterGroup::FloatingPoint, 86),
+    RegisterDescription::new("S23", RegisterGroup::FloatingPoint, 87),
+    RegisterDescription::new("S24", RegisterGroup::FloatingPoint, 88),
+    RegisterDescription::new("S25", RegisterGroup::FloatingPoint, 89),
+    RegisterDescription::new("S26", RegisterGroup::FloatingPoint, 90),
+    RegisterDescription::new("S27", RegisterGroup::FloatingPoint, 91),
+    RegisterDescription::new("S28", RegisterGroup::FloatingPoint, 92),
+    RegisterDescription::new("S29", RegisterGroup::FloatingPoint, 93),
+    RegisterDescription::new("S30", RegisterGroup::FloatingPoint, 94),
+    RegisterDescription::new("S31", RegisterGroup::FloatingPoint, 95),
+];
+
 bitfield! {
     #[derive(Copy, Clone)]
     pub struct Dhcsr(u32);
@@ -404,3 +614,354 @@ impl CoreRegister for Demcr {
     const ADDRESS: u32 = 0xe000_edfc;
     const NAME: &'static str = "DEMCR";
 }
+
+/// Registers which are banked between the secure and the non-secure state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BankedRegister {
+    Msp,
+    Psp,
+    Msplim,
+    Psplim,
+    Control,
+    Faultmask,
+    Basepri,
+    Primask,
+}
+
+impl BankedRegister {
+    /// Returns the register description of the given bank.
+    pub fn description(self, state: SecurityState) -> RegisterDescription {
+        use BankedRegister::*;
+        use SecurityState::*;
+
+        let name = match (self, state) {
+            (Msp, Secure) => "MSP_S",
+            (Msp, NonSecure) => "MSP_NS",
+            (Psp, Secure) => "PSP_S",
+            (Psp, NonSecure) => "PSP_NS",
+            (Msplim, Secure) => "MSPLIM_S",
+            (Msplim, NonSecure) => "MSPLIM_NS",
+            (Psplim, Secure) => "PSPLIM_S",
+            (Psplim, NonSecure) => "PSPLIM_NS",
+            (Control, Secure) => "CONTROL_S",
+            (Control, NonSecure) => "CONTROL_NS",
+            (Faultmask, Secure) => "FAULTMASK_S",
+            (Faultmask, NonSecure) => "FAULTMASK_NS",
+            (Basepri, Secure) => "BASEPRI_S",
+            (Basepri, NonSecure) => "BASEPRI_NS",
+            (Primask, Secure) => "PRIMASK_S",
+            (Primask, NonSecure) => "PRIMASK_NS",
+        };
+
+        *REGISTER_FILE
+            .iter()
+            .find(|r| r.name == name)
+            .expect("All banked registers are part of the register file.")
+    }
+}
+
+bitfield! {
+    /// Debug Security Control and Status Register
+    #[derive(Copy, Clone)]
+    pub struct Dscsr(u32);
+    impl Debug;
+    /// Must be written as zero together with CDS, otherwise the write to CDS is ignored.
+    pub cdskey, set_cdskey: 17;
+    /// The current security state of the processor.
+    pub cds, set_cds: 16;
+    /// Selects the secure bank for debugger accesses if SBRSELEN is set.
+    pub sbrsel, set_sbrsel: 1;
+    pub sbrselen, set_sbrselen: 0;
+}
+
+impl From<u32> for Dscsr {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Dscsr> for u32 {
+    fn from(value: Dscsr) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for Dscsr {
+    const ADDRESS: u32 = 0xE000_EE08;
+    const NAME: &'static str = "DSCSR";
+}
+
+bitfield! {
+    /// Debug Authentication Status Register
+    #[derive(Copy, Clone)]
+    pub struct Dauthstatus(u32);
+    impl Debug;
+    /// Secure non-invasive debug
+    pub snid, _: 7, 6;
+    /// Secure invasive debug
+    pub sid, _: 5, 4;
+    /// Non-secure non-invasive debug
+    pub nsnid, _: 3, 2;
+    /// Non-secure invasive debug
+    pub nsid, _: 1, 0;
+}
+
+impl From<u32> for Dauthstatus {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Dauthstatus> for u32 {
+    fn from(value: Dauthstatus) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for Dauthstatus {
+    const ADDRESS: u32 = 0xE000_EFB8;
+    const NAME: &'static str = "DAUTHSTATUS";
+}
+
+/// The status of a single debug authentication field in DAUTHSTATUS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AuthenticationStatus {
+    NotImplemented,
+    Disabled,
+    Enabled,
+}
+
+impl From<u32> for AuthenticationStatus {
+    fn from(value: u32) -> Self {
+        match value & 0b11 {
+            0b10 => AuthenticationStatus::Disabled,
+            0b11 => AuthenticationStatus::Enabled,
+            // 0b01 is reserved.
+            _ => AuthenticationStatus::NotImplemented,
+        }
+    }
+}
+
+/// The debug authentication status of a core, as reported by DAUTHSTATUS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DebugAuthentication {
+    pub secure_invasive: AuthenticationStatus,
+    pub secure_non_invasive: AuthenticationStatus,
+    pub non_secure_invasive: AuthenticationStatus,
+    pub non_secure_non_invasive: AuthenticationStatus,
+}
+
+impl From<Dauthstatus> for DebugAuthentication {
+    fn from(value: Dauthstatus) -> Self {
+        DebugAuthentication {
+            secure_invasive: value.sid().into(),
+            secure_non_invasive: value.snid().into(),
+            non_secure_invasive: value.nsid().into(),
+            non_secure_non_invasive: value.nsnid().into(),
+        }
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct SauCtrl(u32);
+    impl Debug;
+    /// Memory is marked as Non-secure when the SAU is disabled.
+    pub allns, set_allns: 1;
+    pub enable, set_enable: 0;
+}
+
+impl From<u32> for SauCtrl {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SauCtrl> for u32 {
+    fn from(value: SauCtrl) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for SauCtrl {
+    const ADDRESS: u32 = 0xE000_EDD0;
+    const NAME: &'static str = "SAU_CTRL";
+}
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct SauType(u32);
+    impl Debug;
+    /// The number of implemented SAU regions.
+    pub sregion, _: 7, 0;
+}
+
+impl From<u32> for SauType {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SauType> for u32 {
+    fn from(value: SauType) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for SauType {
+    const ADDRESS: u32 = 0xE000_EDD4;
+    const NAME: &'static str = "SAU_TYPE";
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SauRnr(u32);
+
+impl From<u32> for SauRnr {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SauRnr> for u32 {
+    fn from(value: SauRnr) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for SauRnr {
+    const ADDRESS: u32 = 0xE000_EDD8;
+    const NAME: &'static str = "SAU_RNR";
+}
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct SauRbar(u32);
+    impl Debug;
+    /// Bits [31:5] of the base address of the region.
+    pub baddr, _: 31, 5;
+}
+
+impl From<u32> for SauRbar {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SauRbar> for u32 {
+    fn from(value: SauRbar) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for SauRbar {
+    const ADDRESS: u32 = 0xE000_EDDC;
+    const NAME: &'static str = "SAU_RBAR";
+}
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct SauRlar(u32);
+    impl Debug;
+    /// Bits [31:5] of the limit address of the region.
+    pub laddr, _: 31, 5;
+    /// The region is Non-secure callable.
+    pub nsc, _: 1;
+    pub enable, _: 0;
+}
+
+impl From<u32> for SauRlar {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SauRlar> for u32 {
+    fn from(value: SauRlar) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for SauRlar {
+    const ADDRESS: u32 = 0xE000_EDE0;
+    const NAME: &'static str = "SAU_RLAR";
+}
+
+/// A single region of the Security Attribution Unit.
+///
+/// Memory inside an enabled region is marked as Non-secure,
+/// or Secure and Non-secure callable if `non_secure_callable` is set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SauRegion {
+    pub number: u8,
+    pub enabled: bool,
+    pub non_secure_callable: bool,
+    /// The first address of the region.
+    pub base: u32,
+    /// The last address of the region (inclusive).
+    pub limit: u32,
+}
+
+impl SauRegion {
+    fn new(number: u8, rbar: SauRbar, rlar: SauRlar) -> Self {
+        SauRegion {
+            number,
+            enabled: rlar.enable(),
+            non_secure_callable: rlar.nsc(),
+            base: rbar.baddr() << 5,
+            limit: (rlar.laddr() << 5) | 0x1F,
+        }
+    }
+}
+
+/// The configuration of the Security Attribution Unit.
+///
+/// The final security attribution of an address is the combination of the SAU and the
+/// implementation defined IDAU, where the more secure attribution wins. As the IDAU is
+/// not visible to the debugger, memory outside of the SAU regions is reported as
+/// determined by the IDAU (or Secure, if the SAU is enabled and no IDAU exists).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SauConfiguration {
+    pub enabled: bool,
+    /// If the SAU is disabled, all memory is Non-secure if this is set and Secure otherwise.
+    pub all_non_secure: bool,
+    pub regions: Vec<SauRegion>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AuthenticationStatus, BankedRegister, Dauthstatus, DebugAuthentication, SauRbar, SauRegion,
+        SauRlar, SecurityState,
+    };
+
+    #[test]
+    fn decode_debug_authentication() {
+        // Non-secure debug enabled, secure debug disabled.
+        let auth = DebugAuthentication::from(Dauthstatus(0b1010_1111));
+
+        assert_eq!(auth.non_secure_invasive, AuthenticationStatus::Enabled);
+        assert_eq!(auth.non_secure_non_invasive, AuthenticationStatus::Enabled);
+        assert_eq!(auth.secure_invasive, AuthenticationStatus::Disabled);
+        assert_eq!(auth.secure_non_invasive, AuthenticationStatus::Disabled);
+    }
+
+    #[test]
+    fn decode_sau_region() {
+        let region = SauRegion::new(2, SauRbar(0x0004_0000), SauRlar(0x0007_FFE0 | 0b11));
+
+        assert_eq!(region.base, 0x0004_0000);
+        assert_eq!(region.limit, 0x0007_FFFF);
+        assert!(region.enabled);
+        assert!(region.non_secure_callable);
+    }
+
+    #[test]
+    fn banked_register_selectors() {
+        let msp_s = BankedRegister::Msp.description(SecurityState::Secure);
+        let basepri_ns = BankedRegister::Basepri.description(SecurityState::NonSecure);
+
+        assert_eq!(msp_s.address.0, 0x1A);
+        assert_eq!(basepri_ns.address.0, 0x23);
+        assert_eq!(basepri_ns.bit_offset, 8);
+    }
+}
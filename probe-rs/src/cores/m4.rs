@@ -2,6 +2,7 @@ use crate::coresight::memory::MI;
 use crate::probe::{DebugProbeError, MasterProbe};
 use crate::target::{
     BasicRegisterAddresses, Core, CoreInformation, CoreRegister, CoreRegisterAddress,
+    RegisterDescription, RegisterGroup,
 };
 use bitfield::bitfield;
 
@@ -202,8 +203,70 @@ pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
     XPSR: CoreRegisterAddress(0b001_0000),
 };
 
-pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1001);
-pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1010);
+pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b001_0001);
+pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b001_0010);
+
+/// All registers which can be accessed through the DCRSR register of an ARMv7-M core.
+///
+/// The floating point registers are only present on cores with an FPU (Cortex-M4F).
+pub const REGISTER_FILE: &[RegisterDescription] = &[
+    RegisterDescription::new("R0", RegisterGroup::General, 0),
+    RegisterDescription::new("R1", RegisterGroup::General, 1),
+    RegisterDescription::new("R2", RegisterGroup::General, 2),
+    RegisterDescription::new("R3", RegisterGroup::General, 3),
+    RegisterDescription::new("R4", RegisterGroup::General, 4),
+    RegisterDescription::new("R5", RegisterGroup::General, 5),
+    RegisterDescription::new("R6", RegisterGroup::General, 6),
+    RegisterDescription::new("R7", RegisterGroup::General, 7),
+    RegisterDescription::new("R8", RegisterGroup::General, 8),
+    RegisterDescription::new("R9", RegisterGroup::General, 9),
+    RegisterDescription::new("R10", RegisterGroup::General, 10),
+    RegisterDescription::new("R11", RegisterGroup::General, 11),
+    RegisterDescription::new("R12", RegisterGroup::General, 12),
+    RegisterDescription::new("SP", RegisterGroup::General, 13),
+    RegisterDescription::new("LR", RegisterGroup::General, 14),
+    RegisterDescription::new("PC", RegisterGroup::General, 15),
+    RegisterDescription::new("XPSR", RegisterGroup::Special, 16),
+    RegisterDescription::new("MSP", RegisterGroup::Special, 17),
+    RegisterDescription::new("PSP", RegisterGroup::Special, 18),
+    RegisterDescription::packed("CONTROL", RegisterGroup::Special, 20, 24, 3),
+    RegisterDescription::packed("FAULTMASK", RegisterGroup::Special, 20, 16, 1),
+    RegisterDescription::packed("BASEPRI", RegisterGroup::Special, 20, 8, 8),
+    RegisterDescription::packed("PRIMASK", RegisterGroup::Special, 20, 0, 1),
+    RegisterDescription::new("FPSCR", RegisterGroup::FloatingPoint, 33),
+    RegisterDescription::new("S0", RegisterGroup::FloatingPoint, 64),
+    RegisterDescription::new("S1", RegisterGroup::FloatingPoint, 65),
+    RegisterDescription::new("S2", RegisterGroup::FloatingPoint, 66),
+    RegisterDescription::new("S3", RegisterGroup::FloatingPoint, 67),
+    RegisterDescription::new("S4", RegisterGroup::FloatingPoint, 68),
+    RegisterDescription::new("S5", RegisterGroup::FloatingPoint, 69),
+    RegisterDescription::new("S6", RegisterGroup::FloatingPoint, 70),
+    RegisterDescription::new("S7", RegisterGroup::FloatingPoint, 71),
+    RegisterDescription::new("S8", RegisterGroup::FloatingPoint, 72),
+    RegisterDescription::new("S9", RegisterGroup::FloatingPoint, 73),
+    RegisterDescription::new("S10", RegisterGroup::FloatingPoint, 74),
+    RegisterDescription::new("S11", RegisterGroup::FloatingPoint, 75),
+    RegisterDescription::new("S12", RegisterGroup::FloatingPoint, 76),
+    RegisterDescription::new("S13", RegisterGroup::FloatingPoint, 77),
+    RegisterDescription::new("S14", RegisterGroup::FloatingPoint, 78),
+    RegisterDescription::new("S15", RegisterGroup::FloatingPoint, 79),
+    RegisterDescription::new("S16", RegisterGroup::FloatingPoint, 80),
+    RegisterDescription::new("S17", RegisterGroup::FloatingPoint, 81),
+    RegisterDescription::new("S18", RegisterGroup::FloatingPoint, 82),
+    RegisterDescription::new("S19", RegisterGroup::FloatingPoint, 83),
+    RegisterDescription::new("S20", RegisterGroup::FloatingPoint, 84),
+    RegisterDescription::new("S21", RegisterGroup::FloatingPoint, 85),
+    RegisterDescription::new("S22", RegisterGroup::FloatingPoint, 86),
+    RegisterDescription::new("S23", RegisterGroup::FloatingPoint, 87),
+    RegisterDescription::new("S24", RegisterGroup::FloatingPoint, 88),
+    RegisterDescription::new("S25", RegisterGroup::FloatingPoint, 89),
+    RegisterDescription::new("S26", RegisterGroup::FloatingPoint, 90),
+    RegisterDescription::new("S27", RegisterGroup::FloatingPoint, 91),
+    RegisterDescription::new("S28", RegisterGroup::FloatingPoint, 92),
+    RegisterDescription::new("S29", RegisterGroup::FloatingPoint, 93),
+    RegisterDescription::new("S30", RegisterGroup::FloatingPoint, 94),
+    RegisterDescription::new("S31", RegisterGroup::FloatingPoint, 95),
+];
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct M4;
@@ -401,4 +464,8 @@ impl Core for M4 {
     fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
         &REGISTERS
     }
+
+    fn register_file(&self) -> &'static [RegisterDescription] {
+        REGISTER_FILE
+    }
 }
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 pub mod m0;
 pub mod m33;
 pub mod m4;
+pub mod riscv;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CortexDump {
@@ -27,6 +28,7 @@ pub fn get_core(name: impl AsRef<str>) -> Option<Box<dyn Core>> {
         "m0" => Box::new(self::m0::M0) as _,
         "m4" => Box::new(self::m4::M4) as _,
         "m33" => Box::new(self::m33::M33) as _,
+        "riscv" => Box::new(self::riscv::Riscv) as _,
     };
 
     map.get(&name.as_ref().to_ascii_lowercase()[..]).cloned()
@@ -0,0 +1,828 @@
+//! The Debug Module, as specified in chapter 3 of the
+//! RISC-V External Debug Support Version 0.13.
+
+use super::{DmiAccess, RiscvError};
+
+use bitfield::bitfield;
+
+/// How often a status register is polled before giving up.
+const POLL_RETRIES: usize = 100;
+
+/// Addresses of the Debug Module registers on the DMI.
+mod address {
+    pub const DATA0: u8 = 0x04;
+    pub const DMCONTROL: u8 = 0x10;
+    pub const DMSTATUS: u8 = 0x11;
+    pub const ABSTRACTCS: u8 = 0x16;
+    pub const COMMAND: u8 = 0x17;
+    pub const PROGBUF0: u8 = 0x20;
+}
+
+/// Register numbers of the abstract register commands.
+pub mod regno {
+    /// The first general purpose register, x0.
+    pub const GPR_BASE: u16 = 0x1000;
+    pub const TSELECT: u16 = 0x7a0;
+    pub const TDATA1: u16 = 0x7a1;
+    pub const TDATA2: u16 = 0x7a2;
+    pub const DCSR: u16 = 0x7b0;
+    pub const DPC: u16 = 0x7b1;
+}
+
+/// `lw s1, 0(s0)`
+const INSTRUCTION_LW_S1_S0: u32 = 0x0004_2483;
+/// `sw s1, 0(s0)`
+const INSTRUCTION_SW_S1_S0: u32 = 0x0094_2023;
+/// `ebreak`
+const INSTRUCTION_EBREAK: u32 = 0x0010_0073;
+
+const REGNO_S0: u16 = regno::GPR_BASE + 8;
+const REGNO_S1: u16 = regno::GPR_BASE + 9;
+
+bitfield! {
+    /// Debug Module Control
+    #[derive(Copy, Clone)]
+    pub struct Dmcontrol(u32);
+    impl Debug;
+    pub haltreq, set_haltreq: 31;
+    pub resumereq, set_resumereq: 30;
+    pub hartreset, set_hartreset: 29;
+    pub ackhavereset, set_ackhavereset: 28;
+    pub hasel, set_hasel: 26;
+    pub hartsello, set_hartsello: 25, 16;
+    pub hartselhi, set_hartselhi: 15, 6;
+    pub ndmreset, set_ndmreset: 1;
+    pub dmactive, set_dmactive: 0;
+}
+
+bitfield! {
+    /// Debug Module Status
+    #[derive(Copy, Clone)]
+    pub struct Dmstatus(u32);
+    impl Debug;
+    pub impebreak, _: 22;
+    pub allhavereset, _: 19;
+    pub anyhavereset, _: 18;
+    pub allresumeack, _: 17;
+    pub anyresumeack, _: 16;
+    pub allnonexistent, _: 15;
+    pub anynonexistent, _: 14;
+    pub allunavail, _: 13;
+    pub anyunavail, _: 12;
+    pub allrunning, _: 11;
+    pub anyrunning, _: 10;
+    pub allhalted, _: 9;
+    pub anyhalted, _: 8;
+    pub authenticated, _: 7;
+    pub version, _: 3, 0;
+}
+
+bitfield! {
+    /// Abstract Control and Status
+    #[derive(Copy, Clone)]
+    pub struct Abstractcs(u32);
+    impl Debug;
+    pub progbufsize, _: 28, 24;
+    pub busy, _: 12;
+    pub cmderr, set_cmderr: 10, 8;
+    pub datacount, _: 3, 0;
+}
+
+bitfield! {
+    /// An abstract command of the type "Access Register"
+    #[derive(Copy, Clone)]
+    pub struct AccessRegisterCommand(u32);
+    impl Debug;
+    pub cmdtype, set_cmdtype: 31, 24;
+    pub aarsize, set_aarsize: 22, 20;
+    pub postexec, set_postexec: 18;
+    pub transfer, set_transfer: 17;
+    pub write, set_write: 16;
+    pub regno, set_regno: 15, 0;
+}
+
+bitfield! {
+    /// Debug Control and Status, a CSR of the hart
+    #[derive(Copy, Clone)]
+    pub struct Dcsr(u32);
+    impl Debug;
+    pub xdebugver, _: 31, 28;
+    pub ebreakm, set_ebreakm: 15;
+    pub ebreaks, set_ebreaks: 13;
+    pub ebreaku, set_ebreaku: 12;
+    pub cause, _: 8, 6;
+    pub step, set_step: 2;
+    pub prv, set_prv: 1, 0;
+}
+
+bitfield! {
+    /// Match Control, the type 2 variant of the `tdata1` trigger CSR
+    #[derive(Copy, Clone)]
+    pub struct Mcontrol(u32);
+    impl Debug;
+    pub trigger_type, set_trigger_type: 31, 28;
+    pub dmode, set_dmode: 27;
+    pub action, set_action: 15, 12;
+    pub m, set_m: 6;
+    pub s, set_s: 4;
+    pub u, set_u: 3;
+    pub execute, set_execute: 2;
+    pub store, set_store: 1;
+    pub load, set_load: 0;
+}
+
+/// The `tdata1` trigger type of an address/data match trigger.
+const TRIGGER_TYPE_MCONTROL: u32 = 2;
+
+/// The maximum number of triggers which are probed.
+const MAX_TRIGGERS: u32 = 32;
+
+/// Access to the Debug Module of a RISC-V target.
+///
+/// Only the first hart of the Debug Module is used.
+pub struct DebugModule<D: DmiAccess> {
+    dmi: D,
+}
+
+impl<D: DmiAccess> DebugModule<D> {
+    /// Activates the Debug Module and checks that it implements version 0.13.
+    pub fn new(mut dmi: D) -> Result<Self, RiscvError> {
+        let dmcontrol = Dmcontrol(dmi.read_dmi(address::DMCONTROL)?);
+
+        if !dmcontrol.dmactive() {
+            let mut dmcontrol = Dmcontrol(0);
+            dmcontrol.set_dmactive(true);
+            dmi.write_dmi(address::DMCONTROL, dmcontrol.0)?;
+        }
+
+        let dmstatus = Dmstatus(dmi.read_dmi(address::DMSTATUS)?);
+
+        if dmstatus.version() != 2 {
+            return Err(RiscvError::UnsupportedDebugModuleVersion(
+                dmstatus.version() as u8,
+            ));
+        }
+
+        if !dmstatus.authenticated() {
+            return Err(RiscvError::NotAuthenticated);
+        }
+
+        Ok(DebugModule { dmi })
+    }
+
+    /// Uses a Debug Module which was activated and checked with `new` before.
+    pub fn reattach(dmi: D) -> Self {
+        DebugModule { dmi }
+    }
+
+    /// Returns the underlying DMI access.
+    pub fn into_dmi(self) -> D {
+        self.dmi
+    }
+
+    fn dmstatus(&mut self) -> Result<Dmstatus, RiscvError> {
+        Ok(Dmstatus(self.dmi.read_dmi(address::DMSTATUS)?))
+    }
+
+    fn write_dmcontrol(&mut self, mut dmcontrol: Dmcontrol) -> Result<(), RiscvError> {
+        dmcontrol.set_dmactive(true);
+        self.dmi.write_dmi(address::DMCONTROL, dmcontrol.0)
+    }
+
+    pub fn is_halted(&mut self) -> Result<bool, RiscvError> {
+        Ok(self.dmstatus()?.allhalted())
+    }
+
+    /// Waits until the hart is halted.
+    pub fn wait_for_halted(&mut self) -> Result<(), RiscvError> {
+        for _ in 0..POLL_RETRIES {
+            if self.is_halted()? {
+                return Ok(());
+            }
+        }
+        Err(RiscvError::Timeout)
+    }
+
+    /// Halts the hart.
+    pub fn halt(&mut self) -> Result<(), RiscvError> {
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_haltreq(true);
+        self.write_dmcontrol(dmcontrol)?;
+
+        let result = self.wait_for_halted();
+
+        // The halt request has to be cleared again in any case.
+        self.write_dmcontrol(Dmcontrol(0))?;
+
+        result
+    }
+
+    /// Resumes the hart.
+    pub fn resume(&mut self) -> Result<(), RiscvError> {
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_resumereq(true);
+        self.write_dmcontrol(dmcontrol)?;
+
+        for _ in 0..POLL_RETRIES {
+            if self.dmstatus()?.allresumeack() {
+                return self.write_dmcontrol(Dmcontrol(0));
+            }
+        }
+        Err(RiscvError::Timeout)
+    }
+
+    /// Executes a single instruction and halts again.
+    pub fn step(&mut self) -> Result<(), RiscvError> {
+        let mut dcsr = Dcsr(self.read_register(regno::DCSR)?);
+        dcsr.set_step(true);
+        self.write_register(regno::DCSR, dcsr.0)?;
+
+        self.resume()?;
+        let result = self.wait_for_halted();
+
+        dcsr.set_step(false);
+        self.write_register(regno::DCSR, dcsr.0)?;
+
+        result
+    }
+
+    /// Resets the whole system through `ndmreset`, optionally halting the hart
+    /// directly after the reset.
+    pub fn reset(&mut self, halt: bool) -> Result<(), RiscvError> {
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_ndmreset(true);
+        dmcontrol.set_haltreq(halt);
+        self.write_dmcontrol(dmcontrol)?;
+
+        dmcontrol.set_ndmreset(false);
+        self.write_dmcontrol(dmcontrol)?;
+
+        if halt {
+            self.wait_for_halted()?;
+        }
+
+        // Acknowledge the reset and clear the halt request.
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_ackhavereset(true);
+        self.write_dmcontrol(dmcontrol)
+    }
+
+    /// Executes an abstract command and waits for its completion.
+    fn execute_command(&mut self, command: AccessRegisterCommand) -> Result<(), RiscvError> {
+        self.dmi.write_dmi(address::COMMAND, command.0)?;
+
+        for _ in 0..POLL_RETRIES {
+            let abstractcs = Abstractcs(self.dmi.read_dmi(address::ABSTRACTCS)?);
+
+            if !abstractcs.busy() {
+                if abstractcs.cmderr() != 0 {
+                    // The error bits are cleared by writing ones.
+                    let mut clear = Abstractcs(0);
+                    clear.set_cmderr(0b111);
+                    self.dmi.write_dmi(address::ABSTRACTCS, clear.0)?;
+
+                    return Err(RiscvError::AbstractCommand(abstractcs.cmderr() as u8));
+                }
+                return Ok(());
+            }
+        }
+        Err(RiscvError::Timeout)
+    }
+
+    fn access_register_command(regno: u16, write: bool, postexec: bool) -> AccessRegisterCommand {
+        let mut command = AccessRegisterCommand(0);
+        command.set_cmdtype(0);
+        // 32 bit access
+        command.set_aarsize(2);
+        command.set_transfer(true);
+        command.set_write(write);
+        command.set_postexec(postexec);
+        command.set_regno(u32::from(regno));
+        command
+    }
+
+    /// Reads a register of the hart with an abstract command.
+    pub fn read_register(&mut self, regno: u16) -> Result<u32, RiscvError> {
+        self.execute_command(Self::access_register_command(regno, false, false))?;
+        self.dmi.read_dmi(address::DATA0)
+    }
+
+    /// Writes a register of the hart with an abstract command.
+    pub fn write_register(&mut self, regno: u16, value: u32) -> Result<(), RiscvError> {
+        self.dmi.write_dmi(address::DATA0, value)?;
+        self.execute_command(Self::access_register_command(regno, true, false))
+    }
+
+    /// Writes a register, and then executes the program buffer.
+    fn write_register_and_execute(&mut self, regno: u16, value: u32) -> Result<(), RiscvError> {
+        self.dmi.write_dmi(address::DATA0, value)?;
+        self.execute_command(Self::access_register_command(regno, true, true))
+    }
+
+    /// Writes a single instruction followed by an `ebreak` into the program buffer.
+    fn setup_program_buffer(&mut self, instruction: u32) -> Result<(), RiscvError> {
+        let abstractcs = Abstractcs(self.dmi.read_dmi(address::ABSTRACTCS)?);
+        let impebreak = self.dmstatus()?.impebreak();
+
+        let required = if impebreak { 1 } else { 2 };
+        if abstractcs.progbufsize() < required {
+            return Err(RiscvError::ProgramBufferTooSmall);
+        }
+
+        self.dmi.write_dmi(address::PROGBUF0, instruction)?;
+        if !impebreak {
+            self.dmi
+                .write_dmi(address::PROGBUF0 + 1, INSTRUCTION_EBREAK)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f` and restores the registers `s0` and `s1`, which are used by
+    /// the program buffer memory accesses.
+    fn with_saved_registers<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, RiscvError>,
+    ) -> Result<T, RiscvError> {
+        let s0 = self.read_register(REGNO_S0)?;
+        let s1 = self.read_register(REGNO_S1)?;
+
+        let result = f(self);
+
+        self.write_register(REGNO_S0, s0)?;
+        self.write_register(REGNO_S1, s1)?;
+
+        result
+    }
+
+    /// Reads 32 bit words from memory using the program buffer.
+    ///
+    /// The hart has to be halted. The address has to be word aligned.
+    pub fn read_memory(&mut self, address: u32, data: &mut [u32]) -> Result<(), RiscvError> {
+        if address & 0x3 != 0 {
+            return Err(RiscvError::UnalignedAccess(address));
+        }
+
+        self.with_saved_registers(|dm| {
+            dm.setup_program_buffer(INSTRUCTION_LW_S1_S0)?;
+
+            for (offset, word) in data.iter_mut().enumerate() {
+                // Writing the address to s0 executes the load into s1.
+                dm.write_register_and_execute(REGNO_S0, address + offset as u32 * 4)?;
+                *word = dm.read_register(REGNO_S1)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes 32 bit words to memory using the program buffer.
+    ///
+    /// The hart has to be halted. The address has to be word aligned.
+    pub fn write_memory(&mut self, address: u32, data: &[u32]) -> Result<(), RiscvError> {
+        if address & 0x3 != 0 {
+            return Err(RiscvError::UnalignedAccess(address));
+        }
+
+        self.with_saved_registers(|dm| {
+            dm.setup_program_buffer(INSTRUCTION_SW_S1_S0)?;
+
+            for (offset, word) in data.iter().enumerate() {
+                dm.write_register(REGNO_S0, address + offset as u32 * 4)?;
+                // Writing the value to s1 executes the store.
+                dm.write_register_and_execute(REGNO_S1, *word)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Reads bytes from memory, using word accesses.
+    pub fn read_memory8(&mut self, address: u32, data: &mut [u8]) -> Result<(), RiscvError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let start = address & !0x3;
+        let end = address + data.len() as u32;
+        let word_count = (((end + 3) & !0x3) - start) as usize / 4;
+
+        let mut words = vec![0u32; word_count];
+        self.read_memory(start, &mut words)?;
+
+        let bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        let offset = (address - start) as usize;
+        data.copy_from_slice(&bytes[offset..offset + data.len()]);
+
+        Ok(())
+    }
+
+    /// Writes bytes to memory, using word accesses.
+    ///
+    /// Partially written words at the start and the end are read first, so the
+    /// surrounding bytes are preserved.
+    pub fn write_memory8(&mut self, address: u32, data: &[u8]) -> Result<(), RiscvError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let start = address & !0x3;
+        let end = address + data.len() as u32;
+        let word_count = (((end + 3) & !0x3) - start) as usize / 4;
+
+        let mut words = vec![0u32; word_count];
+        if start != address {
+            self.read_memory(start, &mut words[..1])?;
+        }
+        if end & 0x3 != 0 {
+            self.read_memory(
+                start + (word_count as u32 - 1) * 4,
+                &mut words[word_count - 1..],
+            )?;
+        }
+
+        let mut bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        let offset = (address - start) as usize;
+        bytes[offset..offset + data.len()].copy_from_slice(data);
+
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        self.write_memory(start, &words)
+    }
+
+    /// Selects a trigger. Returns false if the trigger does not exist.
+    fn select_trigger(&mut self, index: u32) -> Result<bool, RiscvError> {
+        self.write_register(regno::TSELECT, index)?;
+        Ok(self.read_register(regno::TSELECT)? == index)
+    }
+
+    /// Returns the number of triggers which can be used as breakpoints.
+    pub fn available_breakpoint_units(&mut self) -> Result<u32, RiscvError> {
+        let mut count = 0;
+
+        for index in 0..MAX_TRIGGERS {
+            if !self.select_trigger(index)? {
+                break;
+            }
+
+            let tdata1 = Mcontrol(self.read_register(regno::TDATA1)?);
+            if tdata1.trigger_type() == 0 {
+                break;
+            }
+            if tdata1.trigger_type() == TRIGGER_TYPE_MCONTROL {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Sets an execution breakpoint at `address` using a free match trigger.
+    pub fn set_breakpoint(&mut self, address: u32) -> Result<(), RiscvError> {
+        for index in 0..MAX_TRIGGERS {
+            if !self.select_trigger(index)? {
+                break;
+            }
+
+            let tdata1 = Mcontrol(self.read_register(regno::TDATA1)?);
+            if tdata1.trigger_type() == 0 {
+                break;
+            }
+
+            let in_use = tdata1.execute() || tdata1.load() || tdata1.store();
+            if tdata1.trigger_type() != TRIGGER_TYPE_MCONTROL || in_use {
+                continue;
+            }
+
+            let mut mcontrol = Mcontrol(0);
+            mcontrol.set_trigger_type(TRIGGER_TYPE_MCONTROL);
+            mcontrol.set_dmode(true);
+            // Enter debug mode when the trigger fires.
+            mcontrol.set_action(1);
+            mcontrol.set_m(true);
+            mcontrol.set_u(true);
+            mcontrol.set_execute(true);
+
+            // The trigger is disabled while the address is changed.
+            self.write_register(regno::TDATA1, 0)?;
+            self.write_register(regno::TDATA2, address)?;
+            self.write_register(regno::TDATA1, mcontrol.0)?;
+
+            return Ok(());
+        }
+
+        Err(RiscvError::NoFreeTrigger)
+    }
+
+    /// Makes `ebreak` instructions enter debug mode, instead of raising an exception.
+    pub fn enable_software_breakpoints(&mut self, state: bool) -> Result<(), RiscvError> {
+        let mut dcsr = Dcsr(self.read_register(regno::DCSR)?);
+        dcsr.set_ebreakm(state);
+        dcsr.set_ebreaku(state);
+        self.write_register(regno::DCSR, dcsr.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{DmiAccess, RiscvError};
+    use super::*;
+
+    use std::collections::HashMap;
+
+    /// A simulated Debug Module with a single hart, which supports the abstract
+    /// register commands and the two program buffer instructions used for memory access.
+    struct SimulatedDm {
+        halted: bool,
+        resumeack: bool,
+        dmcontrol: u32,
+        data0: u32,
+        cmderr: u32,
+        progbuf: [u32; 2],
+        gprs: [u32; 32],
+        csrs: HashMap<u16, u32>,
+        memory: HashMap<u32, u32>,
+        /// The number of implemented triggers.
+        triggers: u32,
+        tdata1: Vec<u32>,
+        tdata2: Vec<u32>,
+    }
+
+    impl SimulatedDm {
+        fn new() -> Self {
+            let mut csrs = HashMap::new();
+            csrs.insert(regno::DCSR, 0x4000_0003);
+            csrs.insert(regno::DPC, 0x2000_0000);
+            csrs.insert(regno::TSELECT, 0);
+
+            SimulatedDm {
+                halted: false,
+                resumeack: false,
+                dmcontrol: 0,
+                data0: 0,
+                cmderr: 0,
+                progbuf: [0; 2],
+                gprs: [0; 32],
+                csrs,
+                memory: HashMap::new(),
+                triggers: 2,
+                tdata1: vec![TRIGGER_TYPE_MCONTROL << 28; 2],
+                tdata2: vec![0; 2],
+            }
+        }
+
+        fn read_reg(&self, regno: u16) -> Option<u32> {
+            match regno {
+                r if (regno::GPR_BASE..regno::GPR_BASE + 32).contains(&r) => {
+                    Some(self.gprs[(r - regno::GPR_BASE) as usize])
+                }
+                regno::TDATA1 => Some(self.tdata1[self.csrs[&regno::TSELECT] as usize]),
+                regno::TDATA2 => Some(self.tdata2[self.csrs[&regno::TSELECT] as usize]),
+                r => self.csrs.get(&r).copied(),
+            }
+        }
+
+        fn write_reg(&mut self, regno: u16, value: u32) -> bool {
+            match regno {
+                r if (regno::GPR_BASE..regno::GPR_BASE + 32).contains(&r) => {
+                    if r != regno::GPR_BASE {
+                        self.gprs[(r - regno::GPR_BASE) as usize] = value;
+                    }
+                }
+                regno::TSELECT => {
+                    // Selecting a trigger which does not exist keeps the old value.
+                    if value < self.triggers {
+                        self.csrs.insert(regno::TSELECT, value);
+                    }
+                }
+                regno::TDATA1 => {
+                    let index = self.csrs[&regno::TSELECT] as usize;
+                    self.tdata1[index] = value;
+                }
+                regno::TDATA2 => {
+                    let index = self.csrs[&regno::TSELECT] as usize;
+                    self.tdata2[index] = value;
+                }
+                r if self.csrs.contains_key(&r) => {
+                    self.csrs.insert(r, value);
+                }
+                _ => return false,
+            }
+            true
+        }
+
+        fn execute_program_buffer(&mut self) {
+            let s0 = self.gprs[8];
+            match self.progbuf[0] {
+                INSTRUCTION_LW_S1_S0 => {
+                    self.gprs[9] = self.memory.get(&s0).copied().unwrap_or(0);
+                }
+                INSTRUCTION_SW_S1_S0 => {
+                    self.memory.insert(s0, self.gprs[9]);
+                }
+                _ => self.cmderr = 3,
+            }
+        }
+
+        fn execute_command(&mut self, command: AccessRegisterCommand) {
+            if !self.halted {
+                // The hart has to be halted for abstract commands.
+                self.cmderr = 4;
+                return;
+            }
+
+            let regno = command.regno() as u16;
+
+            if command.transfer() {
+                if command.write() {
+                    if !self.write_reg(regno, self.data0) {
+                        self.cmderr = 3;
+                        return;
+                    }
+                } else if let Some(value) = self.read_reg(regno) {
+                    self.data0 = value;
+                } else {
+                    self.cmderr = 3;
+                    return;
+                }
+            }
+
+            if command.postexec() {
+                self.execute_program_buffer();
+            }
+        }
+
+        fn resume(&mut self) {
+            self.halted = false;
+            self.resumeack = true;
+
+            // A single step halts the hart again after one instruction.
+            if Dcsr(self.csrs[&regno::DCSR]).step() {
+                let dpc = self.csrs[&regno::DPC];
+                self.csrs.insert(regno::DPC, dpc + 4);
+                self.halted = true;
+            }
+        }
+    }
+
+    impl DmiAccess for SimulatedDm {
+        fn read_dmi(&mut self, address: u8) -> Result<u32, RiscvError> {
+            let mut dmstatus = Dmstatus(2 | (1 << 7));
+
+            Ok(match address {
+                address::DATA0 => self.data0,
+                address::DMCONTROL => self.dmcontrol,
+                address::DMSTATUS => {
+                    if self.halted {
+                        dmstatus.0 |= (1 << 9) | (1 << 8);
+                    } else {
+                        dmstatus.0 |= (1 << 11) | (1 << 10);
+                    }
+                    if self.resumeack {
+                        dmstatus.0 |= (1 << 17) | (1 << 16);
+                    }
+                    dmstatus.0
+                }
+                address::ABSTRACTCS => (2 << 24) | (self.cmderr << 8) | 1,
+                a if a == address::PROGBUF0 || a == address::PROGBUF0 + 1 => {
+                    self.progbuf[(a - address::PROGBUF0) as usize]
+                }
+                a => return Err(RiscvError::DmiFailed(a)),
+            })
+        }
+
+        fn write_dmi(&mut self, address: u8, value: u32) -> Result<(), RiscvError> {
+            match address {
+                address::DATA0 => self.data0 = value,
+                address::DMCONTROL => {
+                    let dmcontrol = Dmcontrol(value);
+                    if dmcontrol.haltreq() {
+                        self.halted = true;
+                    }
+                    if dmcontrol.resumereq() && !dmcontrol.haltreq() {
+                        self.resume();
+                    } else {
+                        self.resumeack = false;
+                    }
+                    self.dmcontrol = value & !((1 << 31) | (1 << 30) | (1 << 28));
+                }
+                address::ABSTRACTCS => {
+                    self.cmderr &= !((value >> 8) & 0b111);
+                }
+                address::COMMAND => {
+                    if self.cmderr == 0 {
+                        self.execute_command(AccessRegisterCommand(value));
+                    }
+                }
+                a if a == address::PROGBUF0 || a == address::PROGBUF0 + 1 => {
+                    self.progbuf[(a - address::PROGBUF0) as usize] = value;
+                }
+                a => return Err(RiscvError::DmiFailed(a)),
+            }
+            Ok(())
+        }
+    }
+
+    fn halted_debug_module() -> DebugModule<SimulatedDm> {
+        let mut dm = DebugModule::new(SimulatedDm::new()).unwrap();
+        dm.halt().unwrap();
+        dm
+    }
+
+    #[test]
+    fn halt_and_resume() {
+        let mut dm = DebugModule::new(SimulatedDm::new()).unwrap();
+
+        assert!(!dm.is_halted().unwrap());
+        dm.halt().unwrap();
+        assert!(dm.is_halted().unwrap());
+        dm.resume().unwrap();
+        assert!(!dm.is_halted().unwrap());
+    }
+
+    #[test]
+    fn step_advances_pc() {
+        let mut dm = halted_debug_module();
+
+        dm.step().unwrap();
+
+        assert!(dm.is_halted().unwrap());
+        assert_eq!(dm.read_register(regno::DPC).unwrap(), 0x2000_0004);
+        assert!(!Dcsr(dm.read_register(regno::DCSR).unwrap()).step());
+    }
+
+    #[test]
+    fn abstract_register_access() {
+        let mut dm = halted_debug_module();
+
+        dm.write_register(regno::GPR_BASE + 10, 0xDEAD_BEEF)
+            .unwrap();
+
+        assert_eq!(dm.read_register(regno::GPR_BASE + 10).unwrap(), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn abstract_command_error_is_reported_and_cleared() {
+        let mut dm = DebugModule::new(SimulatedDm::new()).unwrap();
+
+        // The hart is running, so the command fails.
+        let result = dm.read_register(regno::GPR_BASE + 1);
+        assert!(matches!(result, Err(RiscvError::AbstractCommand(4))));
+
+        dm.halt().unwrap();
+        assert!(dm.read_register(regno::GPR_BASE + 1).is_ok());
+    }
+
+    #[test]
+    fn memory_access_through_program_buffer() {
+        let mut dm = halted_debug_module();
+
+        dm.write_register(REGNO_S0, 0x1111_1111).unwrap();
+        dm.write_register(REGNO_S1, 0x2222_2222).unwrap();
+
+        dm.write_memory(0x8000_0000, &[0x0403_0201, 0x0807_0605])
+            .unwrap();
+
+        let mut words = [0u32; 2];
+        dm.read_memory(0x8000_0000, &mut words).unwrap();
+        assert_eq!(words, [0x0403_0201, 0x0807_0605]);
+
+        let mut bytes = [0u8; 3];
+        dm.read_memory8(0x8000_0003, &mut bytes).unwrap();
+        assert_eq!(bytes, [0x04, 0x05, 0x06]);
+
+        dm.write_memory8(0x8000_0003, &[0xAA, 0xBB]).unwrap();
+        dm.read_memory(0x8000_0000, &mut words).unwrap();
+        assert_eq!(words, [0xAA03_0201, 0x0807_06BB]);
+
+        // The registers used for the memory access are restored.
+        assert_eq!(dm.read_register(REGNO_S0).unwrap(), 0x1111_1111);
+        assert_eq!(dm.read_register(REGNO_S1).unwrap(), 0x2222_2222);
+    }
+
+    #[test]
+    fn breakpoints_use_free_triggers() {
+        let mut dm = halted_debug_module();
+
+        assert_eq!(dm.available_breakpoint_units().unwrap(), 2);
+
+        dm.set_breakpoint(0x2000_0100).unwrap();
+        dm.set_breakpoint(0x2000_0200).unwrap();
+        assert!(matches!(
+            dm.set_breakpoint(0x2000_0300),
+            Err(RiscvError::NoFreeTrigger)
+        ));
+
+        dm.select_trigger(1).unwrap();
+        assert_eq!(dm.read_register(regno::TDATA2).unwrap(), 0x2000_0200);
+        assert!(Mcontrol(dm.read_register(regno::TDATA1).unwrap()).execute());
+    }
+}
@@ -0,0 +1,171 @@
+//! Debug Transport Module over JTAG, as specified in chapter 6 of the
+//! RISC-V External Debug Support Version 0.13.
+
+use super::{DmiAccess, RiscvError};
+use crate::probe::JTAGAccess;
+
+use bitfield::bitfield;
+
+/// The length of the instruction register of a RISC-V TAP.
+const IR_LENGTH: u8 = 5;
+
+/// The instruction to select the DTM Control and Status register.
+const DTMCS: u32 = 0x10;
+/// The instruction to select the Debug Module Interface Access register.
+const DMI: u32 = 0x11;
+
+/// How often an operation is retried when the DMI reports it as busy.
+const BUSY_RETRIES: usize = 10;
+
+bitfield! {
+    /// DTM Control and Status
+    #[derive(Copy, Clone)]
+    pub struct Dtmcs(u32);
+    impl Debug;
+    pub _, set_dmihardreset: 17;
+    pub _, set_dmireset: 16;
+    /// The minimum number of cycles to stay in Run-Test/Idle after a DMI access.
+    pub idle, _: 14, 12;
+    pub dmistat, _: 11, 10;
+    /// The size of the address field in the DMI register.
+    pub abits, _: 9, 4;
+    pub version, _: 3, 0;
+}
+
+/// The result of a DMI operation, as reported in the `op` field.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DmiStatus {
+    Success,
+    Failed,
+    Busy,
+}
+
+impl From<u64> for DmiStatus {
+    fn from(value: u64) -> Self {
+        match value & 0b11 {
+            0 => DmiStatus::Success,
+            3 => DmiStatus::Busy,
+            // 1 is reserved and treated like a failure.
+            _ => DmiStatus::Failed,
+        }
+    }
+}
+
+const DMI_OP_NOP: u64 = 0;
+const DMI_OP_READ: u64 = 1;
+const DMI_OP_WRITE: u64 = 2;
+
+/// The configuration of a Debug Transport Module, which is kept for the whole session.
+#[derive(Debug, Copy, Clone)]
+pub struct DtmState {
+    abits: u8,
+    idle_cycles: u8,
+}
+
+/// A Debug Transport Module, which accesses the Debug Module through JTAG.
+pub struct JtagDtm<'a> {
+    jtag: &'a mut dyn JTAGAccess,
+    abits: u8,
+    idle_cycles: u8,
+}
+
+impl<'a> JtagDtm<'a> {
+    /// Reads the DTM configuration and checks that the DTM implements version 0.13.
+    pub fn new(jtag: &'a mut dyn JTAGAccess) -> Result<Self, RiscvError> {
+        jtag.write_ir(DTMCS, IR_LENGTH)?;
+        let dtmcs = Dtmcs(jtag.write_dr(0, 32, 0)? as u32);
+
+        log::debug!("DTMCS: {:?}", dtmcs);
+
+        if dtmcs.version() != 1 {
+            return Err(RiscvError::UnsupportedDebugTransportVersion(
+                dtmcs.version() as u8,
+            ));
+        }
+
+        let dtm = JtagDtm {
+            jtag,
+            abits: dtmcs.abits() as u8,
+            idle_cycles: dtmcs.idle() as u8,
+        };
+
+        dtm.jtag.write_ir(DMI, IR_LENGTH)?;
+
+        Ok(dtm)
+    }
+
+    /// Uses a DTM which was read with `new` before, without reading its configuration again.
+    pub fn reattach(jtag: &'a mut dyn JTAGAccess, state: DtmState) -> Result<Self, RiscvError> {
+        jtag.write_ir(DMI, IR_LENGTH)?;
+
+        Ok(JtagDtm {
+            jtag,
+            abits: state.abits,
+            idle_cycles: state.idle_cycles,
+        })
+    }
+
+    /// Returns the configuration of the DTM, including the idle cycles learned so far.
+    pub fn state(&self) -> DtmState {
+        DtmState {
+            abits: self.abits,
+            idle_cycles: self.idle_cycles,
+        }
+    }
+
+    /// Clears a sticky error or busy condition of the DMI.
+    fn reset_dmi(&mut self) -> Result<(), RiscvError> {
+        let mut dtmcs = Dtmcs(0);
+        dtmcs.set_dmireset(true);
+
+        self.jtag.write_ir(DTMCS, IR_LENGTH)?;
+        self.jtag.write_dr(u64::from(dtmcs.0), 32, 0)?;
+        self.jtag.write_ir(DMI, IR_LENGTH)?;
+
+        Ok(())
+    }
+
+    /// Performs a single DMI scan and returns the status and data of the previous operation.
+    fn scan(&mut self, address: u8, data: u32, op: u64) -> Result<(DmiStatus, u32), RiscvError> {
+        let length = self.abits + 34;
+        let value = (u64::from(address) << 34) | (u64::from(data) << 2) | op;
+
+        let result = self.jtag.write_dr(value, length, self.idle_cycles)?;
+
+        Ok((DmiStatus::from(result), (result >> 2) as u32))
+    }
+
+    /// Issues an operation and fetches its result with a following NOP scan.
+    fn operation(&mut self, address: u8, data: u32, op: u64) -> Result<u32, RiscvError> {
+        for _ in 0..BUSY_RETRIES {
+            self.scan(address, data, op)?;
+
+            match self.scan(0, 0, DMI_OP_NOP)? {
+                (DmiStatus::Success, value) => return Ok(value),
+                (DmiStatus::Busy, _) => {
+                    // Give the debug module more time for the following accesses.
+                    self.idle_cycles = self.idle_cycles.saturating_add(1).min(63);
+                    log::debug!("DMI busy, increasing idle cycles to {}", self.idle_cycles);
+                    self.reset_dmi()?;
+                }
+                (DmiStatus::Failed, _) => {
+                    self.reset_dmi()?;
+                    return Err(RiscvError::DmiFailed(address));
+                }
+            }
+        }
+
+        Err(RiscvError::Timeout)
+    }
+}
+
+impl<'a> DmiAccess for JtagDtm<'a> {
+    fn read_dmi(&mut self, address: u8) -> Result<u32, RiscvError> {
+        self.operation(address, 0, DMI_OP_READ)
+    }
+
+    fn write_dmi(&mut self, address: u8, value: u32) -> Result<(), RiscvError> {
+        self.operation(address, value, DMI_OP_WRITE)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! Support for RISC-V cores, implementing the
+//! RISC-V External Debug Support Version 0.13.
+//!
+//! The Debug Module is accessed through a JTAG Debug Transport Module,
+//! so the probe has to be attached in JTAG mode.
+
+pub mod dm;
+pub mod dtm;
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::{
+    BasicRegisterAddresses, Core, CoreInformation, CoreRegisterAddress, RegisterDescription,
+    RegisterGroup,
+};
+
+use dm::{regno, DebugModule};
+use dtm::JtagDtm;
+
+use std::error::Error;
+use std::fmt;
+
+/// Access to the registers of a Debug Module through the Debug Module Interface (DMI).
+pub trait DmiAccess {
+    fn read_dmi(&mut self, address: u8) -> Result<u32, RiscvError>;
+
+    fn write_dmi(&mut self, address: u8, value: u32) -> Result<(), RiscvError>;
+}
+
+#[derive(Debug)]
+pub enum RiscvError {
+    DebugProbe(Box<DebugProbeError>),
+    /// The DTM does not implement version 0.13 of the debug specification.
+    UnsupportedDebugTransportVersion(u8),
+    /// The Debug Module does not implement version 0.13 of the debug specification.
+    UnsupportedDebugModuleVersion(u8),
+    /// The debugger is not authenticated to the Debug Module.
+    NotAuthenticated,
+    /// A DMI access to the contained address failed.
+    DmiFailed(u8),
+    /// An abstract command failed with the contained `cmderr` value.
+    AbstractCommand(u8),
+    ProgramBufferTooSmall,
+    UnalignedAccess(u32),
+    NoFreeTrigger,
+    InvalidRegister(u8),
+    Timeout,
+}
+
+impl Error for RiscvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RiscvError::DebugProbe(ref e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RiscvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RiscvError::*;
+
+        match self {
+            DebugProbe(ref e) => e.fmt(f),
+            UnsupportedDebugTransportVersion(version) => write!(
+                f,
+                "The debug transport module version {} is not supported.",
+                version
+            ),
+            UnsupportedDebugModuleVersion(version) => {
+                write!(f, "The debug module version {} is not supported.", version)
+            }
+            NotAuthenticated => write!(f, "The debugger is not authenticated to the debug module."),
+            DmiFailed(address) => write!(f, "The DMI access to address {:#04x} failed.", address),
+            AbstractCommand(cmderr) => {
+                write!(f, "The abstract command failed with error {}.", cmderr)
+            }
+            ProgramBufferTooSmall => {
+                write!(f, "The program buffer is too small for memory access.")
+            }
+            UnalignedAccess(address) => write!(
+                f,
+                "The memory access at {:#010x} is not word aligned.",
+                address
+            ),
+            NoFreeTrigger => write!(f, "No free trigger is available for the breakpoint."),
+            InvalidRegister(address) => write!(f, "The register {} does not exist.", address),
+            Timeout => write!(f, "Timeout while waiting for the debug module."),
+        }
+    }
+}
+
+impl From<DebugProbeError> for RiscvError {
+    fn from(error: DebugProbeError) -> Self {
+        RiscvError::DebugProbe(Box::new(error))
+    }
+}
+
+impl From<RiscvError> for DebugProbeError {
+    fn from(error: RiscvError) -> Self {
+        match error {
+            RiscvError::DebugProbe(e) => *e,
+            RiscvError::Timeout => DebugProbeError::Timeout,
+            e => DebugProbeError::RiscvError(e),
+        }
+    }
+}
+
+impl From<RiscvError> for AccessPortError {
+    fn from(error: RiscvError) -> Self {
+        match error {
+            RiscvError::UnalignedAccess(_) => AccessPortError::MemoryNotAligned,
+            e => AccessPortError::DebugModuleError(e.to_string()),
+        }
+    }
+}
+
+/// The program counter, which is read from the `dpc` CSR while the hart is halted.
+const PC: u8 = 32;
+/// The `dcsr` CSR.
+const DCSR: u8 = 33;
+
+/// The basic registers, mapped to their closest RISC-V counterparts.
+///
+/// The arguments are passed in `a0` to `a4`, and there is no program status register,
+/// so `XPSR` is mapped to `dcsr`.
+pub const REGISTERS: BasicRegisterAddresses = BasicRegisterAddresses {
+    R0: CoreRegisterAddress(10),
+    R1: CoreRegisterAddress(11),
+    R2: CoreRegisterAddress(12),
+    R3: CoreRegisterAddress(13),
+    R4: CoreRegisterAddress(14),
+    R9: CoreRegisterAddress(9),
+    PC: CoreRegisterAddress(PC),
+    SP: CoreRegisterAddress(2),
+    LR: CoreRegisterAddress(1),
+    XPSR: CoreRegisterAddress(DCSR),
+};
+
+/// All registers of a RV32 hart. The general purpose registers use their ABI names.
+pub const REGISTER_FILE: &[RegisterDescription] = &[
+    RegisterDescription::new("zero", RegisterGroup::General, 0),
+    RegisterDescription::new("ra", RegisterGroup::General, 1),
+    RegisterDescription::new("sp", RegisterGroup::General, 2),
+    RegisterDescription::new("gp", RegisterGroup::General, 3),
+    RegisterDescription::new("tp", RegisterGroup::General, 4),
+    RegisterDescription::new("t0", RegisterGroup::General, 5),
+    RegisterDescription::new("t1", RegisterGroup::General, 6),
+    RegisterDescription::new("t2", RegisterGroup::General, 7),
+    RegisterDescription::new("s0", RegisterGroup::General, 8),
+    RegisterDescription::new("s1", RegisterGroup::General, 9),
+    RegisterDescription::new("a0", RegisterGroup::General, 10),
+    RegisterDescription::new("a1", RegisterGroup::General, 11),
+    RegisterDescription::new("a2", RegisterGroup::General, 12),
+    RegisterDescription::new("a3", RegisterGroup::General, 13),
+    RegisterDescription::new("a4", RegisterGroup::General, 14),
+    RegisterDescription::new("a5", RegisterGroup::General, 15),
+    RegisterDescription::new("a6", RegisterGroup::General, 16),
+    RegisterDescription::new("a7", RegisterGroup::General, 17),
+    RegisterDescription::new("s2", RegisterGroup::General, 18),
+    RegisterDescription::new("s3", RegisterGroup::General, 19),
+    RegisterDescription::new("s4", RegisterGroup::General, 20),
+    RegisterDescription::new("s5", RegisterGroup::General, 21),
+    RegisterDescription::new("s6", RegisterGroup::General, 22),
+    RegisterDescription::new("s7", RegisterGroup::General, 23),
+    RegisterDescription::new("s8", RegisterGroup::General, 24),
+    RegisterDescription::new("s9", RegisterGroup::General, 25),
+    RegisterDescription::new("s10", RegisterGroup::General, 26),
+    RegisterDescription::new("s11", RegisterGroup::General, 27),
+    RegisterDescription::new("t3", RegisterGroup::General, 28),
+    RegisterDescription::new("t4", RegisterGroup::General, 29),
+    RegisterDescription::new("t5", RegisterGroup::General, 30),
+    RegisterDescription::new("t6", RegisterGroup::General, 31),
+    RegisterDescription::new("pc", RegisterGroup::General, PC),
+    RegisterDescription::new("dcsr", RegisterGroup::Special, DCSR),
+];
+
+/// Maps a core register address to the register number of the abstract commands.
+fn abstract_regno(addr: CoreRegisterAddress) -> Result<u16, RiscvError> {
+    match addr.0 {
+        n @ 0..=31 => Ok(regno::GPR_BASE + u16::from(n)),
+        PC => Ok(regno::DPC),
+        DCSR => Ok(regno::DCSR),
+        n => Err(RiscvError::InvalidRegister(n)),
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Riscv;
+
+impl Riscv {
+    /// Runs `f` with the Debug Module of the target.
+    ///
+    /// The DTM and the Debug Module are checked and activated on the first access only,
+    /// afterwards the DTM configuration is kept in the probe for the rest of the session.
+    pub(crate) fn with_debug_module<T>(
+        mi: &mut MasterProbe,
+        f: impl FnOnce(&mut DebugModule<JtagDtm<'_>>) -> Result<T, RiscvError>,
+    ) -> Result<T, RiscvError> {
+        let cached = mi.riscv_dtm;
+        let jtag = mi.jtag_access()?;
+
+        let mut dm = match cached {
+            Some(state) => DebugModule::reattach(JtagDtm::reattach(jtag, state)?),
+            None => DebugModule::new(JtagDtm::new(jtag)?)?,
+        };
+
+        let result = f(&mut dm);
+
+        let state = dm.into_dmi().state();
+        mi.riscv_dtm = Some(state);
+
+        result
+    }
+
+    fn core_information(dm: &mut DebugModule<JtagDtm<'_>>) -> Result<CoreInformation, RiscvError> {
+        let pc = dm.read_register(regno::DPC)?;
+
+        Ok(CoreInformation { pc })
+    }
+}
+
+impl Core for Riscv {
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| dm.wait_for_halted())?)
+    }
+
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.halt()?;
+            Self::core_information(dm)
+        })?)
+    }
+
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| dm.resume())?)
+    }
+
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| dm.reset(false))?)
+    }
+
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.reset(true)?;
+            Self::core_information(dm)
+        })?)
+    }
+
+    fn step(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.step()?;
+            Self::core_information(dm)
+        })?)
+    }
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError> {
+        let regno = abstract_regno(addr)?;
+
+        Ok(Self::with_debug_module(mi, |dm| dm.read_register(regno))?)
+    }
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let regno = abstract_regno(addr)?;
+
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.write_register(regno, value)
+        })?)
+    }
+
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.available_breakpoint_units()
+        })?)
+    }
+
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.enable_software_breakpoints(state)
+        })?)
+    }
+
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| dm.set_breakpoint(addr))?)
+    }
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError> {
+        Ok(Self::with_debug_module(mi, |dm| {
+            dm.read_memory8(address, data)
+        })?)
+    }
+
+    fn select_port(&self, mi: &mut MasterProbe, _ap: u8, _scs_base: u32) {
+        // There is no memory AP, the memory is accessed through the Debug Module.
+        mi.select_debug_module_memory();
+    }
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses {
+        &REGISTERS
+    }
+
+    fn register_file(&self) -> &'static [RegisterDescription] {
+        REGISTER_FILE
+    }
+}
@@ -68,3 +68,46 @@ define_ap_register!(
     },
     if value.APPROTECTSTATUS { 1 } else { 0 }
 );
+
+// Mdm-Ap
+// The Miscellaneous Debug Module Access Port (MDM-AP) of NXP Kinetis chips controls the reset and
+// mass erase of the chip and reports its security state, even if the chip is secured.
+define_ap!(MdmAP);
+
+define_ap_register!(
+    /// Status of the chip as seen by the MDM-AP
+    MdmAP,
+    MDMSTATUS,
+    0x000,
+    [
+        (MASS_ERASE_ACK: bool),
+        (FLASH_READY: bool),
+        (SYSTEM_SECURITY: bool),
+        (MASS_ERASE_ENABLE: bool),
+    ],
+    value,
+    MDMSTATUS {
+        MASS_ERASE_ACK: value & 0x01 != 0,
+        FLASH_READY: value & 0x02 != 0,
+        SYSTEM_SECURITY: value & 0x04 != 0,
+        MASS_ERASE_ENABLE: value & 0x20 != 0,
+    },
+    u32::from(value.MASS_ERASE_ACK)
+        | u32::from(value.FLASH_READY) << 1
+        | u32::from(value.SYSTEM_SECURITY) << 2
+        | u32::from(value.MASS_ERASE_ENABLE) << 5
+);
+
+define_ap_register!(
+    /// Starts a mass erase and holds the chip in reset
+    MdmAP,
+    MDMCONTROL,
+    0x004,
+    [(MASS_ERASE_IN_PROGRESS: bool), (SYSTEM_RESET_REQUEST: bool),],
+    value,
+    MDMCONTROL {
+        MASS_ERASE_IN_PROGRESS: value & 0x01 != 0,
+        SYSTEM_RESET_REQUEST: value & 0x08 != 0,
+    },
+    u32::from(value.MASS_ERASE_IN_PROGRESS) | u32::from(value.SYSTEM_RESET_REQUEST) << 3
+);
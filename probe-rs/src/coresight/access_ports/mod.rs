@@ -19,6 +19,7 @@ pub enum AccessPortError {
     RegisterWriteError { addr: u8, name: &'static str },
     OutOfBoundsError,
     CtrlAPNotFound,
+    DebugModuleError(String),
 }
 
 impl Error for AccessPortError {}
@@ -42,6 +43,13 @@ impl fmt::Display for AccessPortError {
             ),
             OutOfBoundsError => write!(f, "Out of bounds access"),
             CtrlAPNotFound => write!(f, "Could not find Nordic's CTRL-AP"),
+            DebugModuleError(message) => {
+                write!(
+                    f,
+                    "Memory access through the debug module failed: {}",
+                    message
+                )
+            }
         }
     }
 }
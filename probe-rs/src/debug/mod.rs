@@ -83,8 +83,7 @@ impl Registers {
         for i in 0..16 {
             registers[i as usize] = Some(
                 session
-                    .target
-                    .core
+                    .core()
                     .read_core_reg(&mut session.probe, i.into())
                     .unwrap(),
             );
@@ -206,8 +205,7 @@ impl<'a> Iterator for StackFrameIterator<'a> {
                     let addr = i64::from(current_cfa.unwrap()) + o;
                     let mut buff = [0u8; 4];
                     self.session
-                        .target
-                        .core
+                        .core()
                         .read_block8(&mut self.session.probe, addr as u32, &mut buff)
                         .unwrap();
 
@@ -744,8 +742,7 @@ fn get_piece_value(session: &mut Session, p: &gimli::Piece<DwarfReader>) -> Opti
         Location::Value { value } => Some(value.to_u64(0xff_ff_ff_ff).unwrap() as u32),
         Location::Register { register } => {
             let val = session
-                .target
-                .core
+                .core()
                 .read_core_reg(&mut session.probe, (register.0 as u8).into())
                 .expect("Failed to read register from target");
             Some(val)
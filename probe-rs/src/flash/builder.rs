@@ -1,6 +1,10 @@
 use super::flasher::{Flasher, FlasherError};
-use super::FlashProgress;
-use crate::config::memory::{PageInfo, SectorInfo};
+use super::{DownloadOptions, FlashProgress, RegionReport};
+use crate::config::memory::{FlashRegion, PageInfo, SectorInfo};
+
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
 
 /// A struct to hold all the information about one page of flash.
 #[derive(Derivative, Clone)]
@@ -112,6 +116,64 @@ pub enum FlashBuilderError {
     MaxPageCountExceeded(usize),
     ProgramPage(u32, u32),
     Flasher(FlasherError),
+    Verify(Vec<u32>), // Contains the first faulty address of each page which failed verification.
+}
+
+impl Error for FlashBuilderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FlashBuilderError::Flasher(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlashBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlashBuilderError::*;
+
+        match self {
+            AddressBeforeFlashStart(address) => write!(
+                f,
+                "The address {:#010x} lies before the start of the flash.",
+                address
+            ),
+            DataOverlap(address) => write!(
+                f,
+                "The data at address {:#010x} overlaps with other data.",
+                address
+            ),
+            InvalidFlashAddress(address) => write!(
+                f,
+                "The address {:#010x} is not a valid flash address.",
+                address
+            ),
+            DuplicateDataEntry(address) => {
+                write!(f, "Data for address {:#010x} was added twice.", address)
+            }
+            PageSizeDoesNotMatch(page_size, sector_page_size) => write!(
+                f,
+                "The page size {} does not match the page size {} of the sector.",
+                page_size, sector_page_size
+            ),
+            MaxPageCountExceeded(count) => {
+                write!(f, "The sector cannot hold more than {} pages.", count)
+            }
+            ProgramPage(address, code) => write!(
+                f,
+                "Programming the page at {:#010x} failed with error code {}.",
+                address, code
+            ),
+            Flasher(ref e) => e.fmt(f),
+            Verify(addresses) => {
+                write!(f, "Verification failed at the addresses")?;
+                for address in addresses {
+                    write!(f, " {:#010x}", address)?;
+                }
+                write!(f, ".")
+            }
+        }
+    }
 }
 
 impl From<FlasherError> for FlashBuilderError {
@@ -172,67 +234,138 @@ impl<'a> FlashBuilder<'a> {
 
     /// Program a binary into the flash.
     ///
-    /// If `restore_unwritten_bytes` is `true`, all bytes of a sector,
+    /// If `options.keep_unwritten` is `true`, all bytes of a sector,
     /// that are not to be written during flashing will be read from the flash first
     /// and written again once the sector is erased.
+    ///
+    /// If `options.verify` is `true`, all programmed pages are checked against the staged data afterwards.
+    ///
+    /// If `options.dry_run` is `true`, the flash is only read, and nothing is erased or programmed.
+    /// The flash algorithm is not loaded for a dry run, and the core keeps running.
+    ///
+    /// Returns the statistics of the programmed region.
     pub fn program(
         &self,
         mut flash: Flasher,
-        mut do_chip_erase: bool,
-        restore_unwritten_bytes: bool,
+        options: &DownloadOptions,
         progress: &FlashProgress,
-    ) -> Result<(), FlashBuilderError> {
+    ) -> Result<RegionReport, FlashBuilderError> {
+        let region = flash.region().clone();
+        let mut report = RegionReport::new(region.range.clone(), &flash.flash_algorithm().name);
+
         if self.flash_write_data.is_empty() {
             // Nothing to do.
-            return Ok(());
+            return Ok(report);
         }
 
+        progress.started_region(region.range.clone(), &report.algorithm);
+
         let mut sectors = vec![];
 
         // Convert the list of flash operations into flash sectors and pages.
-        self.build_sectors_and_pages(&mut flash, &mut sectors, restore_unwritten_bytes)?;
+        self.build_sectors_and_pages(&mut flash, &mut sectors, options.keep_unwritten)?;
+
+        // If the flash algo doesn't support erase all, disable chip erase.
+        let do_chip_erase = options.do_chip_erase && flash.flash_algorithm().pc_erase_all.is_some();
+
+        // A chip erase wipes all sectors, so only single sectors can be skipped.
+        let skipped = if options.skip_unchanged && !do_chip_erase {
+            Self::skip_unchanged_sectors(&mut flash, &mut sectors)?
+        } else {
+            vec![]
+        };
 
         let num_pages = sectors.iter().map(|s| s.pages.len()).sum();
-        let sizes = sectors.first().map(|s| (s.size, s.page_size));
-        let (sector_size, page_size) = sizes.unwrap_or((0, 0));
-        progress.initialized(sectors.len(), num_pages, sector_size, page_size);
+        let total_sector_size = sectors.iter().map(|s| s.size).sum();
+        let page_size = sectors.first().map(|s| s.page_size).unwrap_or(0);
+        progress.initialized(sectors.len(), num_pages, total_sector_size, page_size);
+
+        for (address, size) in skipped {
+            progress.sector_skipped(address, size);
+            report.bytes_skipped += u64::from(size);
+        }
 
         // Check if there is even sectors to flash.
         if sectors.is_empty() || sectors[0].pages.is_empty() {
             // Nothing to do.
-            return Ok(());
+            Self::finish_all(options, progress);
+            report.algorithm_calls = flash.algorithm_calls();
+            return Ok(report);
         }
 
-        // If the flash algo doesn't support erase all, disable chip erase.
-        if flash.flash_algorithm().pc_erase_all.is_none() {
-            do_chip_erase = false;
+        if options.dry_run {
+            for sector in &sectors {
+                log::info!(
+                    "Dry run: would erase sector 0x{:08x} and program {} pages.",
+                    sector.address,
+                    sector.pages.len()
+                );
+            }
+            Self::finish_all(options, progress);
+            report.algorithm_calls = flash.algorithm_calls();
+            return Ok(report);
         }
 
+        let double_buffering = flash.double_buffering_supported() && self.enable_double_buffering;
         log::debug!("Full Chip Erase enabled: {:?}", do_chip_erase);
-        log::debug!(
-            "Double Buffering enabled: {:?}",
-            self.enable_double_buffering
-        );
+        log::debug!("Double Buffering enabled: {:?}", double_buffering);
+
+        let page_bytes: u64 = Self::pages(&sectors)
+            .iter()
+            .map(|p| u64::from(p.size))
+            .sum();
 
         // Erase all necessary sectors.
         progress.started_erasing();
+        let start = Instant::now();
 
         if do_chip_erase {
             self.chip_erase(&mut flash, &sectors, progress)?;
+            report.bytes_erased = u64::from(region.range.end - region.range.start);
         } else {
             self.sector_erase(&mut flash, &sectors, progress)?;
+            report.bytes_erased = sectors
+                .iter()
+                .filter(|s| !s.pages.is_empty())
+                .map(|s| u64::from(s.size))
+                .sum();
         }
+        report.chip_erase = do_chip_erase;
+        report.erase_time = start.elapsed();
 
         // Flash all necessary pages.
         progress.started_flashing();
+        let start = Instant::now();
 
-        if flash.double_buffering_supported() && self.enable_double_buffering {
+        if double_buffering {
             self.program_double_buffer(&mut flash, &sectors, progress)?;
         } else {
             self.program_simple(&mut flash, &sectors, progress)?;
         };
+        report.double_buffering = double_buffering;
+        report.bytes_programmed = page_bytes;
+        report.program_time = start.elapsed();
+
+        if options.verify {
+            progress.started_verifying();
+            let start = Instant::now();
+            self.verify(&mut flash, &sectors, progress)?;
+            report.bytes_verified = page_bytes;
+            report.verify_time = start.elapsed();
+        }
 
-        Ok(())
+        report.algorithm_calls = flash.algorithm_calls();
+
+        Ok(report)
+    }
+
+    /// Reports all phases as finished, when nothing has to be flashed.
+    fn finish_all(options: &DownloadOptions, progress: &FlashProgress) {
+        progress.finished_erasing();
+        progress.finished_programming();
+        if options.verify {
+            progress.finished_verifying();
+        }
     }
 
     /// Layouts an entire flash memory.
@@ -246,107 +379,148 @@ impl<'a> FlashBuilder<'a> {
         sectors: &mut Vec<FlashSector>,
         restore_unwritten_bytes: bool,
     ) -> Result<(), FlashBuilderError> {
-        for op in &self.flash_write_data {
+        let region = flash.region().clone();
+
+        *sectors = Self::layout_sectors(&region, &self.flash_write_data, |page, end| {
+            Self::fill_page(flash, page, end, restore_unwritten_bytes)
+        })?;
+
+        log::debug!("Sectors are:");
+        for sector in sectors {
+            log::debug!("{:#?}", sector);
+        }
+
+        Ok(())
+    }
+
+    /// Splits the data of all flash operations into the sectors and pages of `region`.
+    ///
+    /// The bytes of a page, which are not written by any operation, are filled in with `fill`.
+    /// It is called with the page and the length up to which its data has to be filled.
+    fn layout_sectors(
+        region: &FlashRegion,
+        operations: &[FlashWriteData],
+        mut fill: impl FnMut(&mut FlashPage, usize) -> Result<(), FlashBuilderError>,
+    ) -> Result<Vec<FlashSector>, FlashBuilderError> {
+        let mut sectors: Vec<FlashSector> = vec![];
+
+        for op in operations {
             let mut pos = 0;
             while pos < op.data.len() {
-                // Check if the operation is in another sector.
                 let flash_address = op.address + pos as u32;
-                if let Some(sector) = sectors.last_mut() {
-                    // If the address is not in the sector, add a new sector.
-                    if flash_address >= sector.address + sector.size {
-                        let sector_info = flash.region().sector_info(flash_address);
-                        if let Some(sector_info) = sector_info {
-                            let new_sector = FlashSector::new(&sector_info);
-                            sectors.push(new_sector);
-                            log::trace!(
-                                "Added Sector (0x{:08x}..0x{:08x})",
-                                sector_info.base_address,
-                                sector_info.base_address + sector_info.size
-                            );
-                        } else {
-                            return Err(FlashBuilderError::InvalidFlashAddress(flash_address));
-                        }
-                        continue;
-                    } else if let Some(page) = sector.pages.last_mut() {
-                        // If the current page does not contain the address.
-                        if flash_address >= page.address + page.size {
-                            // Fill any gap at the end of the current page before switching to a new page.
-                            Self::fill_page(flash, page, restore_unwritten_bytes)?;
-
-                            let page_info = flash.region().page_info(flash_address);
-                            if let Some(page_info) = page_info {
-                                let new_page = FlashPage::new(&page_info);
-                                sector.add_page(new_page)?;
-                                log::trace!(
-                                    "Added Page (0x{:08x}..0x{:08x})",
-                                    page_info.base_address,
-                                    page_info.base_address + page_info.size
-                                );
-                            } else {
-                                return Err(FlashBuilderError::InvalidFlashAddress(flash_address));
-                            }
-                            continue;
-                        } else {
-                            let space_left_in_page = page.size - page.data.len() as u32;
-                            let space_left_in_data = op.data.len() - pos;
-                            let amount =
-                                usize::min(space_left_in_page as usize, space_left_in_data);
-
-                            page.data.extend(&op.data[pos..pos + amount]);
-                            log::trace!("Added {} bytes to current page", amount);
-                            pos += amount;
-                        }
-                    } else {
-                        // If no page is on the sector yet.
-                        let page_info = flash.region().page_info(flash_address);
-                        if let Some(page_info) = page_info {
-                            let new_page = FlashPage::new(&page_info);
-                            sector.add_page(new_page.clone())?;
-                            log::trace!(
-                                "Added Page (0x{:08x}..0x{:08x})",
-                                page_info.base_address,
-                                page_info.base_address + page_info.size
-                            );
-                        } else {
-                            return Err(FlashBuilderError::InvalidFlashAddress(flash_address));
-                        }
-                        continue;
+
+                // If the address is not in the current sector, add a new sector.
+                let in_sector = match sectors.last() {
+                    Some(s) => flash_address < s.address + s.size,
+                    None => false,
+                };
+                if !in_sector {
+                    // Complete the last page of the previous sector.
+                    if let Some(page) = sectors.last_mut().and_then(|s| s.pages.last_mut()) {
+                        let size = page.size as usize;
+                        fill(page, size)?;
                     }
-                } else {
-                    // If no sector exists, create a new one.
-                    let sector_info = flash.region().sector_info(flash_address);
-                    if let Some(sector_info) = sector_info {
-                        let new_sector = FlashSector::new(&sector_info);
-                        sectors.push(new_sector);
-                        log::trace!(
-                            "Added Sector (0x{:08x}..0x{:08x})",
-                            sector_info.base_address,
-                            sector_info.base_address + sector_info.size
-                        );
-                    } else {
-                        return Err(FlashBuilderError::InvalidFlashAddress(flash_address));
+
+                    let sector_info = region
+                        .sector_info(flash_address)
+                        .ok_or(FlashBuilderError::InvalidFlashAddress(flash_address))?;
+                    sectors.push(FlashSector::new(&sector_info));
+                    log::trace!(
+                        "Added Sector (0x{:08x}..0x{:08x})",
+                        sector_info.base_address,
+                        sector_info.base_address + sector_info.size
+                    );
+                }
+                let sector_index = sectors.len() - 1;
+                let sector = &mut sectors[sector_index];
+
+                // If the address is not in the current page, add a new page.
+                let in_page = match sector.pages.last() {
+                    Some(p) => flash_address < p.address + p.size,
+                    None => false,
+                };
+                if !in_page {
+                    // Fill any gap at the end of the current page before switching to a new page.
+                    if let Some(page) = sector.pages.last_mut() {
+                        let size = page.size as usize;
+                        fill(page, size)?;
                     }
-                    continue;
+
+                    let page_info = region
+                        .page_info(flash_address)
+                        .ok_or(FlashBuilderError::InvalidFlashAddress(flash_address))?;
+                    sector.add_page(FlashPage::new(&page_info))?;
+                    log::trace!(
+                        "Added Page (0x{:08x}..0x{:08x})",
+                        page_info.base_address,
+                        page_info.base_address + page_info.size
+                    );
                 }
+                let page_index = sector.pages.len() - 1;
+                let page = &mut sector.pages[page_index];
+
+                // Fill any gap between the data of the page and the address.
+                let offset = (flash_address - page.address) as usize;
+                if offset > page.data.len() {
+                    fill(page, offset)?;
+                }
+
+                let space_left_in_page = page.size as usize - offset;
+                let space_left_in_data = op.data.len() - pos;
+                let amount = usize::min(space_left_in_page, space_left_in_data);
+
+                page.data.extend(&op.data[pos..pos + amount]);
+                log::trace!("Added {} bytes to current page", amount);
+                pos += amount;
             }
         }
 
         // Fill the page gap if there is one.
-        if let Some(sector) = sectors.last_mut() {
-            if let Some(page) = sector.pages.last_mut() {
-                Self::fill_page(flash, page, restore_unwritten_bytes)?;
-            }
+        if let Some(page) = sectors.last_mut().and_then(|s| s.pages.last_mut()) {
+            let size = page.size as usize;
+            fill(page, size)?;
         }
 
-        log::debug!("Sectors are:");
-        for sector in sectors {
-            log::debug!("{:#?}", sector);
+        Ok(sectors)
+    }
+
+    /// Removes all sectors from `sectors` whose flash contents already match the data to be flashed.
+    ///
+    /// The flash contents are read through the memory interface, without running the flash algorithm.
+    /// Returns the address and size of every removed sector.
+    fn skip_unchanged_sectors(
+        flash: &mut Flasher,
+        sectors: &mut Vec<FlashSector>,
+    ) -> Result<Vec<(u32, u32)>, FlashBuilderError> {
+        let mut skipped = vec![];
+        let mut unchanged = Vec::with_capacity(sectors.len());
+        for sector in sectors.iter() {
+            let mut matches = !sector.pages.is_empty();
+            for page in &sector.pages {
+                if !matches {
+                    break;
+                }
+                let mut data = vec![0; page.data.len()];
+                flash.read_block8(page.address, data.as_mut_slice())?;
+                matches = data == page.data;
+            }
+            unchanged.push(matches);
         }
 
-        Ok(())
+        let mut unchanged = unchanged.into_iter();
+        sectors.retain(|sector| {
+            let skip = unchanged.next().unwrap_or(false);
+            if skip {
+                log::info!("Skipping unchanged sector at 0x{:08x}.", sector.address);
+                skipped.push((sector.address, sector.size));
+            }
+            !skip
+        });
+
+        Ok(skipped)
     }
 
-    /// Fills all the bytes of `current_page`.
+    /// Fills the data of `current_page` up to the length `end`.
     ///
     /// If `restore_unwritten_bytes` is `true`, all bytes of the page,
     /// that are not to be written during flashing will be read from the flash first
@@ -354,20 +528,19 @@ impl<'a> FlashBuilder<'a> {
     fn fill_page(
         flash: &mut Flasher,
         current_page: &mut FlashPage,
+        end: usize,
         restore_unwritten_bytes: bool,
     ) -> Result<(), FlashBuilderError> {
-        // The remaining bytes to be filled in at the end of the page.
-        let remaining_bytes = current_page.size as usize - current_page.data.len();
-        if current_page.data.len() != current_page.size as usize {
+        if current_page.data.len() < end {
+            // The bytes to be filled in up to `end`.
+            let remaining_bytes = end - current_page.data.len();
             let address_remaining_start = current_page.address + current_page.data.len() as u32;
 
             // Fill up the page with current page bytes until it's full.
             let old_data = if restore_unwritten_bytes {
                 // Read all the remaining old bytes from flash to restore them later.
                 let mut data = vec![0; remaining_bytes];
-                flash.run_verify(|active| {
-                    active.read_block8(address_remaining_start, data.as_mut_slice())
-                })?;
+                flash.read_block8(address_remaining_start, data.as_mut_slice())?;
                 data
             } else {
                 // Set all the remaining bytes to their default erased value.
@@ -385,15 +558,19 @@ impl<'a> FlashBuilder<'a> {
         sectors: &[FlashSector],
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
-        let mut t = std::time::Instant::now();
+        let mut t = Instant::now();
         let result = flash
             .run_erase(|active| active.erase_all())
             .map_err(From::from);
-        for sector in sectors {
-            progress.sector_erased(sector.page_size, t.elapsed().as_millis());
-            t = std::time::Instant::now();
+        if result.is_ok() {
+            for sector in sectors {
+                progress.sector_erased(sector.address, sector.size, t.elapsed().as_millis());
+                t = Instant::now();
+            }
+            progress.finished_erasing();
+        } else {
+            progress.failed_erasing();
         }
-        progress.finished_erasing();
         result
     }
 
@@ -404,19 +581,66 @@ impl<'a> FlashBuilder<'a> {
         sectors: &[FlashSector],
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
-        let mut t = std::time::Instant::now();
+        let mut t = Instant::now();
         let result = flash.run_program(|active| {
             for page in Self::pages(sectors) {
                 active.program_page(page.address, page.data.as_slice())?;
-                progress.page_programmed(page.size, t.elapsed().as_millis());
-                t = std::time::Instant::now();
+                progress.page_programmed(page.address, page.size, t.elapsed().as_millis());
+                t = Instant::now();
             }
             Ok(())
         });
-        progress.finished_programming();
+        if result.is_ok() {
+            progress.finished_programming();
+        } else {
+            progress.failed_programming();
+        }
         result
     }
 
+    /// Verify all pages in `sectors` against the flash contents.
+    ///
+    /// All pages are checked, and every page which does not match is reported.
+    fn verify(
+        &self,
+        flash: &mut Flasher,
+        sectors: &[FlashSector],
+        progress: &FlashProgress,
+    ) -> Result<(), FlashBuilderError> {
+        let mut t = Instant::now();
+        let result: Result<Vec<u32>, FlashBuilderError> = flash.run_verify(|active| {
+            let mut mismatches = vec![];
+            for page in Self::pages(sectors) {
+                if let Some(address) = active.verify_page(page.address, page.data.as_slice())? {
+                    log::warn!(
+                        "Verification of page 0x{:08x} failed at 0x{:08x}.",
+                        page.address,
+                        address
+                    );
+                    mismatches.push(address);
+                }
+                progress.page_verified(page.address, page.size, t.elapsed().as_millis());
+                t = Instant::now();
+            }
+            Ok(mismatches)
+        });
+
+        match result {
+            Ok(mismatches) if mismatches.is_empty() => {
+                progress.finished_verifying();
+                Ok(())
+            }
+            Ok(mismatches) => {
+                progress.failed_verifying();
+                Err(FlashBuilderError::Verify(mismatches))
+            }
+            Err(e) => {
+                progress.failed_verifying();
+                Err(e)
+            }
+        }
+    }
+
     /// Perform an erase of all sectors given in `sectors` which contain pages.
     fn sector_erase(
         &self,
@@ -424,20 +648,23 @@ impl<'a> FlashBuilder<'a> {
         sectors: &[FlashSector],
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
-        let mut t = std::time::Instant::now();
+        let mut t = Instant::now();
         let r: R = flash.run_erase(|active| {
             for sector in sectors {
                 if !sector.pages.is_empty() {
                     active.erase_sector(sector.address)?;
-                    progress.sector_erased(sector.page_size, t.elapsed().as_millis());
-                    t = std::time::Instant::now();
+                    progress.sector_erased(sector.address, sector.size, t.elapsed().as_millis());
+                    t = Instant::now();
                 }
             }
             Ok(())
         });
-        r?;
-        progress.finished_erasing();
-        Ok(())
+        if r.is_ok() {
+            progress.finished_erasing();
+        } else {
+            progress.failed_erasing();
+        }
+        r
     }
 
     /// Flash a program using double buffering.
@@ -450,7 +677,7 @@ impl<'a> FlashBuilder<'a> {
         progress: &FlashProgress,
     ) -> Result<(), FlashBuilderError> {
         let mut current_buf = 0;
-        let mut t = std::time::Instant::now();
+        let mut t = Instant::now();
         let result = flash.run_program(|active| {
             for page in Self::pages(sectors) {
                 // At the start of each loop cycle load the next page buffer into RAM.
@@ -458,28 +685,98 @@ impl<'a> FlashBuilder<'a> {
 
                 // Then wait for the active RAM -> Flash copy process to finish.
                 // Also check if it finished properly. If it didn't, return an error.
-                let result = active.wait_for_completion();
-                progress.page_programmed(page.size, t.elapsed().as_millis());
-                t = std::time::Instant::now();
-                if let Ok(0) = result {
-                } else {
+                let timeout = active.flash_algorithm().program_page_timeout;
+                let result = active.wait_for_completion(timeout)?;
+                progress.page_programmed(page.address, page.size, t.elapsed().as_millis());
+                t = Instant::now();
+                if result != 0 {
                     return Err(FlashBuilderError::ProgramPage(page.address, 0));
                 }
 
                 // Start the next copy process.
-                active.start_program_page_with_buffer(current_buf, page.address)?;
+                active.start_program_page_with_buffer(page.address, current_buf)?;
 
-                // Swap the buffers
-                if current_buf == 1 {
-                    current_buf = 0;
-                } else {
-                    current_buf = 1;
-                }
+                // Go on with the next buffer, so all buffers of the algorithm are used in turn.
+                current_buf =
+                    (current_buf + 1) % active.flash_algorithm().page_buffers.len() as u32;
             }
 
             Ok(())
         });
-        progress.finished_programming();
+        if result.is_ok() {
+            progress.finished_programming();
+        } else {
+            progress.failed_programming();
+        }
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{FlashBuilder, FlashWriteData};
+    use crate::config::memory::{FlashRegion, SectorDescription};
+
+    #[test]
+    fn layout_mixed_sectors() {
+        let region = FlashRegion {
+            range: 0x0800_0000..0x0804_0000,
+            is_boot_memory: true,
+            sectors: vec![
+                SectorDescription {
+                    offset: 0x0,
+                    size: 0x4000,
+                },
+                SectorDescription {
+                    offset: 0x1_0000,
+                    size: 0x1_0000,
+                },
+            ],
+            page_size: 0x400,
+            erased_byte_value: 0xFF,
+        };
+
+        // The data starts in the middle of a page and crosses into the larger sectors.
+        let first = vec![0x11; 0x100];
+        let second = vec![0x22; 0xC280];
+        let operations = [
+            FlashWriteData::new(0x0800_3E00, &first),
+            FlashWriteData::new(0x0800_3F80, &second),
+        ];
+
+        let sectors = FlashBuilder::layout_sectors(&region, &operations, |page, end| {
+            page.data.resize(end, 0xFF);
+            Ok(())
+        })
+        .unwrap();
+
+        let layout = sectors
+            .iter()
+            .map(|s| (s.address, s.size, s.pages.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layout,
+            vec![
+                (0x0800_0000, 0x4000, 1),
+                (0x0800_4000, 0x4000, 16),
+                (0x0800_8000, 0x4000, 16),
+                (0x0800_C000, 0x4000, 16),
+                (0x0801_0000, 0x1_0000, 1),
+            ]
+        );
+
+        // The data is placed at its offset in the page, gaps are filled in.
+        let page = &sectors[0].pages[0];
+        assert_eq!(page.address, 0x0800_3C00);
+        assert_eq!(page.data.len(), 0x400);
+        assert!(page.data[..0x200].iter().all(|b| *b == 0xFF));
+        assert!(page.data[0x200..0x300].iter().all(|b| *b == 0x11));
+        assert!(page.data[0x300..0x380].iter().all(|b| *b == 0xFF));
+        assert!(page.data[0x380..].iter().all(|b| *b == 0x22));
+
+        let page = &sectors[4].pages[0];
+        assert_eq!(page.address, 0x0801_0000);
+        assert!(page.data[..0x200].iter().all(|b| *b == 0x22));
+        assert!(page.data[0x200..].iter().all(|b| *b == 0xFF));
+    }
+}
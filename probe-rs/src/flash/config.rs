@@ -0,0 +1,511 @@
+use crate::config::config_region::{ConfigField, ConfigRegion, ConfigStep};
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+use crate::probe::DebugProbeError;
+use crate::session::Session;
+
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// No config field with the given name exists.
+    FieldNotFound(String),
+    /// Several config regions have a field with the given name.
+    AmbiguousField(String),
+    /// The value does not fit into the field. Contains the field name and the value.
+    ValueTooLarge(String, u32),
+    /// The field is dangerous to write and the write was not forced. Contains the field name.
+    Dangerous(String),
+    /// Bits have to be set in a region which can't be erased. Contains the region name.
+    EraseNotSupported(String),
+    /// A register did not reach the expected state in time. Contains the register address.
+    Timeout(u32),
+    /// The field does not hold the written value.
+    /// Contains the field name, the expected and the actual value.
+    Verify(String, u32, u32),
+    AccessPort(AccessPortError),
+    DebugProbe(DebugProbeError),
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::AccessPort(ref e) => Some(e),
+            ConfigError::DebugProbe(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigError::*;
+
+        match self {
+            FieldNotFound(name) => write!(f, "The target has no config field called {}.", name),
+            AmbiguousField(name) => write!(
+                f,
+                "Several config regions have a field called {}. Use <region>.<field> to select one.",
+                name
+            ),
+            ValueTooLarge(name, value) => write!(
+                f,
+                "The value {:#x} does not fit into the config field {}.",
+                value, name
+            ),
+            Dangerous(name) => write!(
+                f,
+                "Writing the config field {} can lock out the debugger, possibly for good. The write has to be forced.",
+                name
+            ),
+            EraseNotSupported(name) => write!(
+                f,
+                "Setting bits in the config region {} requires an erase, which is not supported.",
+                name
+            ),
+            Timeout(address) => write!(
+                f,
+                "The register at {:#010x} did not reach the expected state in time.",
+                address
+            ),
+            Verify(name, expected, actual) => write!(
+                f,
+                "The config field {} is {:#x} instead of {:#x} after writing it.",
+                name, actual, expected
+            ),
+            AccessPort(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for ConfigError {
+    fn from(error: AccessPortError) -> ConfigError {
+        ConfigError::AccessPort(error)
+    }
+}
+
+impl From<DebugProbeError> for ConfigError {
+    fn from(error: DebugProbeError) -> ConfigError {
+        ConfigError::DebugProbe(error)
+    }
+}
+
+/// The value of erased words in regions which require an erase.
+const ERASED_WORD: u32 = 0xFFFF_FFFF;
+
+/// Looks up a config field by its name, optionally prefixed by its region, e.g. `UICR.APPROTECT`.
+///
+/// The comparison is case insensitive.
+pub fn find_field<'a>(
+    regions: &'a [ConfigRegion],
+    name: &str,
+) -> Result<(&'a ConfigRegion, &'a ConfigField), ConfigError> {
+    let (region_name, field_name) = match name.find('.') {
+        Some(index) => (Some(&name[..index]), &name[index + 1..]),
+        None => (None, name),
+    };
+
+    let mut matches = regions
+        .iter()
+        .filter(|region| match region_name {
+            Some(region_name) => region.name.eq_ignore_ascii_case(region_name),
+            None => true,
+        })
+        .filter_map(|region| region.field(field_name).map(|field| (region, field)));
+
+    let found = matches
+        .next()
+        .ok_or_else(|| ConfigError::FieldNotFound(name.to_owned()))?;
+    if matches.next().is_some() {
+        return Err(ConfigError::AmbiguousField(name.to_owned()));
+    }
+
+    Ok(found)
+}
+
+/// Reads the value of the config field called `name`.
+pub fn read_field(session: &mut Session, name: &str) -> Result<u32, ConfigError> {
+    let (_, field) = find_field(&session.target.config_regions, name)?;
+    let word = session.probe.read32(field.address)?;
+
+    Ok(field.extract(word))
+}
+
+/// Looks up the fields of the `values` and checks that the values can be written to them.
+///
+/// Fields marked as dangerous are only written if `force` is set.
+fn find_changes<'a>(
+    regions: &'a [ConfigRegion],
+    values: &[(&str, u32)],
+    force: bool,
+) -> Result<Vec<(&'a ConfigRegion, &'a ConfigField, u32)>, ConfigError> {
+    let mut changes = vec![];
+    for &(name, value) in values {
+        let (region, field) = find_field(regions, name)?;
+        if !field.fits(value) {
+            return Err(ConfigError::ValueTooLarge(name.to_owned(), value));
+        }
+        if field.dangerous && !force {
+            return Err(ConfigError::Dangerous(name.to_owned()));
+        }
+        changes.push((region, field, value));
+    }
+
+    Ok(changes)
+}
+
+/// Writes the given values to the config fields with the given names and verifies them.
+///
+/// All other bits of the config regions are kept. If bits have to be set in a region
+/// which requires an erase, the region is erased and its previous contents are restored.
+/// The main core is halted before anything is written.
+///
+/// Fields marked as dangerous, such as the readout protection, are only written if `force` is set.
+pub fn write_fields(
+    session: &mut Session,
+    values: &[(&str, u32)],
+    force: bool,
+) -> Result<(), ConfigError> {
+    let regions = session.target.config_regions.clone();
+    let changes = find_changes(&regions, values, force)?;
+
+    session.select_main_core_port();
+    let result = session
+        .target
+        .main_core()
+        .core
+        .halt(&mut session.probe)
+        .map_err(ConfigError::from)
+        .and_then(|_| {
+            let mut written: Vec<&str> = vec![];
+            for (region, _, _) in &changes {
+                if written.contains(&region.name.as_str()) {
+                    continue;
+                }
+                let region_changes = changes
+                    .iter()
+                    .filter(|(r, _, _)| r.name == region.name)
+                    .map(|&(_, field, value)| (field, value))
+                    .collect::<Vec<_>>();
+
+                write_region(&mut session.probe, region, &region_changes)?;
+                written.push(&region.name);
+            }
+            Ok(())
+        });
+    session.restore_core_selection();
+
+    result
+}
+
+/// Writes the `changes` to the fields of `region` and verifies them.
+fn write_region<M: MI>(
+    mi: &mut M,
+    region: &ConfigRegion,
+    changes: &[(&ConfigField, u32)],
+) -> Result<(), ConfigError> {
+    // The current value, the new value and the changed bits of every word of the region.
+    let mut data = vec![0; ((region.range.end - region.range.start) / 4) as usize];
+    mi.read_block32(region.range.start, &mut data)?;
+    let mut words = data
+        .iter()
+        .enumerate()
+        .map(|(i, &word)| (region.range.start + 4 * i as u32, word, word, 0))
+        .collect::<Vec<_>>();
+
+    for &(field, value) in changes {
+        let index = match words.iter().position(|w| w.0 == field.address) {
+            Some(index) => index,
+            None => {
+                let word = mi.read32(field.address)?;
+                words.push((field.address, word, word, 0));
+                words.len() - 1
+            }
+        };
+        let word = &mut words[index];
+        word.2 = field.insert(word.2, value);
+        word.3 |= field.mask();
+    }
+
+    let sets_bits = words.iter().any(|&(_, old, new, _)| new & !old != 0);
+
+    if region.requires_erase && sets_bits {
+        if region.erase.is_empty() {
+            return Err(ConfigError::EraseNotSupported(region.name.clone()));
+        }
+
+        // Erase the region and restore all words which are not erased.
+        run_steps(mi, &region.erase, None)?;
+        for &(address, _, new, _) in &words {
+            if new != ERASED_WORD {
+                run_steps(mi, &region.program, Some((address, new, 0xFFFF_FFFF)))?;
+            }
+        }
+    } else {
+        for &(address, old, new, mask) in &words {
+            if old != new {
+                run_steps(mi, &region.program, Some((address, new, mask)))?;
+            }
+        }
+    }
+
+    for &(field, value) in changes {
+        let actual = field.extract(mi.read32(field.address)?);
+        if actual != value {
+            return Err(ConfigError::Verify(field.name.clone(), value, actual));
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs an erase or program sequence.
+///
+/// When programming, `word` holds the address of the programmed word, its new value
+/// and the mask of the bits which are written.
+fn run_steps<M: MI>(
+    mi: &mut M,
+    steps: &[ConfigStep],
+    word: Option<(u32, u32, u32)>,
+) -> Result<(), ConfigError> {
+    for step in steps {
+        match *step {
+            ConfigStep::Write { address, value } => mi.write32(address, value)?,
+            ConfigStep::Set { address, mask } => {
+                let value = mi.read32(address)?;
+                mi.write32(address, value | mask)?;
+            }
+            ConfigStep::WriteWord => {
+                if let Some((address, value, mask)) = word {
+                    // The word is read again, as earlier steps might have changed it, e.g. by unlocking.
+                    let current = mi.read32(address)?;
+                    mi.write32(address, (current & !mask) | (value & mask))?;
+                }
+            }
+            ConfigStep::Wait {
+                address,
+                mask,
+                value,
+                timeout,
+            } => {
+                let timeout = Duration::from_millis(u64::from(timeout));
+                let start = Instant::now();
+                while mi.read32(address)? & mask != value {
+                    if start.elapsed() > timeout {
+                        return Err(ConfigError::Timeout(address));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_changes, find_field, write_region, ConfigError};
+    use crate::config::config_region::{ConfigField, ConfigRegion, ConfigStep};
+    use crate::coresight::{access_ports::AccessPortError, memory::MI};
+    use std::collections::HashMap;
+
+    const UICR: u32 = 0x1000_1000;
+    const NVMC_READY: u32 = 0x4001_E400;
+    const NVMC_CONFIG: u32 = 0x4001_E504;
+    const NVMC_ERASEUICR: u32 = 0x4001_E514;
+
+    /// A UICR which, like flash, can only clear bits when programmed.
+    struct FakeUicr {
+        memory: HashMap<u32, u32>,
+        erased: bool,
+    }
+
+    impl FakeUicr {
+        fn new() -> Self {
+            let mut memory = HashMap::new();
+            memory.insert(NVMC_READY, 1);
+            Self {
+                memory,
+                erased: false,
+            }
+        }
+    }
+
+    impl MI for FakeUicr {
+        fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
+            Ok(*self.memory.get(&address).unwrap_or(&0xFFFF_FFFF))
+        }
+
+        fn read8(&mut self, _address: u32) -> Result<u8, AccessPortError> {
+            unimplemented!()
+        }
+
+        fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
+            for (i, word) in data.iter_mut().enumerate() {
+                *word = self.read32(address + 4 * i as u32)?;
+            }
+            Ok(())
+        }
+
+        fn read_block8(&mut self, _address: u32, _data: &mut [u8]) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write32(&mut self, address: u32, data: u32) -> Result<(), AccessPortError> {
+            if address == NVMC_ERASEUICR {
+                self.memory
+                    .retain(|&a, _| !(UICR..UICR + 0x1000).contains(&a));
+                self.erased = true;
+            } else if (UICR..UICR + 0x1000).contains(&address) {
+                let word = self.read32(address)?;
+                self.memory.insert(address, word & data);
+            } else {
+                self.memory.insert(address, data);
+            }
+            Ok(())
+        }
+
+        fn write8(&mut self, _address: u32, _data: u8) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write_block32(&mut self, _address: u32, _data: &[u32]) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+
+        fn write_block8(&mut self, _address: u32, _data: &[u8]) -> Result<(), AccessPortError> {
+            unimplemented!()
+        }
+    }
+
+    fn field(name: &str, address: u32, bit_width: u8) -> ConfigField {
+        ConfigField {
+            name: name.to_owned(),
+            address,
+            bit_offset: 0,
+            bit_width,
+            dangerous: false,
+        }
+    }
+
+    fn uicr() -> ConfigRegion {
+        let ready = ConfigStep::Wait {
+            address: NVMC_READY,
+            mask: 1,
+            value: 1,
+            timeout: 100,
+        };
+
+        ConfigRegion {
+            name: "UICR".to_owned(),
+            range: UICR..UICR + 0x210,
+            requires_erase: true,
+            erase: vec![
+                ConfigStep::Write {
+                    address: NVMC_CONFIG,
+                    value: 2,
+                },
+                ConfigStep::Write {
+                    address: NVMC_ERASEUICR,
+                    value: 1,
+                },
+                ready.clone(),
+                ConfigStep::Write {
+                    address: NVMC_CONFIG,
+                    value: 0,
+                },
+            ],
+            program: vec![
+                ConfigStep::Write {
+                    address: NVMC_CONFIG,
+                    value: 1,
+                },
+                ConfigStep::WriteWord,
+                ready,
+                ConfigStep::Write {
+                    address: NVMC_CONFIG,
+                    value: 0,
+                },
+            ],
+            fields: vec![
+                field("CUSTOMER0", UICR + 0x80, 32),
+                ConfigField {
+                    dangerous: true,
+                    ..field("APPROTECT", UICR + 0x208, 8)
+                },
+                field("NFCPINS", UICR + 0x20C, 1),
+            ],
+        }
+    }
+
+    #[test]
+    fn clear_bits_without_erase() {
+        let region = uicr();
+        let mut mi = FakeUicr::new();
+
+        write_region(&mut mi, &region, &[(&region.fields[1], 0x00)]).unwrap();
+
+        assert!(!mi.erased);
+        assert_eq!(mi.read32(UICR + 0x208).unwrap(), 0xFFFF_FF00);
+        assert_eq!(mi.read32(NVMC_CONFIG).unwrap(), 0);
+    }
+
+    #[test]
+    fn set_bits_with_erase() {
+        let region = uicr();
+        let mut mi = FakeUicr::new();
+        mi.write32(UICR + 0x80, 0x1234_5678).unwrap();
+        mi.write32(UICR + 0x20C, 0xFFFF_FFFE).unwrap();
+
+        write_region(&mut mi, &region, &[(&region.fields[2], 1)]).unwrap();
+
+        // The region was erased and the other words were restored.
+        assert!(mi.erased);
+        assert_eq!(mi.read32(UICR + 0x20C).unwrap(), 0xFFFF_FFFF);
+        assert_eq!(mi.read32(UICR + 0x80).unwrap(), 0x1234_5678);
+
+        // Without an erase sequence, bits can't be set.
+        let region = ConfigRegion {
+            erase: vec![],
+            ..region
+        };
+        mi.write32(UICR + 0x20C, 0xFFFF_FFFE).unwrap();
+        assert!(matches!(
+            write_region(&mut mi, &region, &[(&region.fields[2], 1)]),
+            Err(ConfigError::EraseNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn find_fields_by_name() {
+        let regions = vec![uicr()];
+
+        let (region, field) = find_field(&regions, "approtect").unwrap();
+        assert_eq!(region.name, "UICR");
+        assert_eq!(field.name, "APPROTECT");
+        assert!(find_field(&regions, "UICR.NFCPINS").is_ok());
+        assert!(matches!(
+            find_field(&regions, "OTP.NFCPINS"),
+            Err(ConfigError::FieldNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn dangerous_fields_have_to_be_forced() {
+        let regions = vec![uicr()];
+
+        assert!(find_changes(&regions, &[("NFCPINS", 0)], false).is_ok());
+        assert!(matches!(
+            find_changes(&regions, &[("NFCPINS", 0), ("APPROTECT", 0)], false),
+            Err(ConfigError::Dangerous(_))
+        ));
+        assert_eq!(
+            find_changes(&regions, &[("APPROTECT", 0)], true)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -9,7 +9,11 @@ use std::path::Path;
 
 use super::*;
 use crate::config::memory::{MemoryRange, MemoryRegion};
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+use crate::probe::{DebugProbeError, MasterProbe};
+use crate::target::Core;
 
+#[derive(Debug, Default, Copy, Clone)]
 pub struct BinOptions {
     /// The address in memory where the binary will be put at.
     base_address: Option<u32>,
@@ -17,18 +21,160 @@ pub struct BinOptions {
     skip: u32,
 }
 
+impl BinOptions {
+    /// Creates new options for a binary file.
+    ///
+    /// If no `base_address` is given, the binary is put at the start of the boot flash region.
+    pub fn new(base_address: Option<u32>, skip: u32) -> Self {
+        Self { base_address, skip }
+    }
+
+    /// Creates options for a binary file which is put at `base_address`.
+    pub fn with_base_address(base_address: u32) -> Self {
+        Self::new(Some(base_address), 0)
+    }
+
+    pub fn base_address(&self) -> Option<u32> {
+        self.base_address
+    }
+
+    pub fn skip(&self) -> u32 {
+        self.skip
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Uf2Options {
+    /// The family ID of the blocks which will be flashed.
+    family_id: Option<u32>,
+}
+
+impl Uf2Options {
+    /// Creates new options for an UF2 file.
+    ///
+    /// If a `family_id` is given, only the blocks of that family are flashed.
+    pub fn new(family_id: Option<u32>) -> Self {
+        Self { family_id }
+    }
+
+    pub fn family_id(&self) -> Option<u32> {
+        self.family_id
+    }
+}
+
+/// Options which control how a file is flashed.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DownloadOptions {
+    /// Erase the entire chip before flashing, if the flash algorithm supports it.
+    /// Otherwise only the touched sectors are erased.
+    pub do_chip_erase: bool,
+    /// Restore the bytes of erased sectors which are not part of the flashed data.
+    pub keep_unwritten: bool,
+    /// Check all programmed pages against the flashed data.
+    pub verify: bool,
+    /// Determine which sectors would be flashed, without erasing or programming anything.
+    pub dry_run: bool,
+    /// Read the flash first, and leave out sectors which already contain the data.
+    /// This has no effect together with `do_chip_erase`.
+    pub skip_unchanged: bool,
+}
+
+/// Parses a number given either in hexadecimal with a `0x` prefix, or in decimal.
+///
+/// The tools use it for addresses and other numbers of the download options,
+/// e.g. `0x0800_0000` or `4096`.
+pub fn parse_u32(value: &str) -> Result<u32, std::num::ParseIntError> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse::<u32>()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub enum Format {
     Bin(BinOptions),
     Hex,
     Elf,
+    SRecord,
+    Uf2(Uf2Options),
+    /// Detect the format from the contents and the extension of the file.
+    Auto,
+}
+
+impl Format {
+    /// Detects the format of a file from its first `bytes`.
+    ///
+    /// ELF, UF2, Intel HEX and S-record files are recognized by their contents.
+    /// Otherwise the extension of `path` is used, and unknown files are treated as binary.
+    pub fn detect(path: &Path, bytes: &[u8]) -> Format {
+        const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+        if bytes.starts_with(ELF_MAGIC) {
+            return Format::Elf;
+        }
+        if uf2::is_uf2(bytes) {
+            return Format::Uf2(Uf2Options::default());
+        }
+
+        // Text formats might start with a byte order mark or whitespace.
+        let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        let text = match text.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(start) => &text[start..],
+            None => &[],
+        };
+        match text {
+            [b':', digit, ..] if digit.is_ascii_hexdigit() => return Format::Hex,
+            [b'S', digit, ..] if digit.is_ascii_digit() => return Format::SRecord,
+            _ => (),
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_ref().map(|extension| &extension[..]) {
+            Some("elf") | Some("axf") => Format::Elf,
+            Some("hex") | Some("ihex") => Format::Hex,
+            Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => Format::SRecord,
+            Some("uf2") => Format::Uf2(Uf2Options::default()),
+            _ => Format::Bin(BinOptions::default()),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    /// Parses the name of a format. Binary and UF2 formats use the default options.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "bin" => Ok(Format::Bin(BinOptions::default())),
+            "hex" => Ok(Format::Hex),
+            "elf" => Ok(Format::Elf),
+            "srec" => Ok(Format::SRecord),
+            "uf2" => Ok(Format::Uf2(Uf2Options::default())),
+            "auto" => Ok(Format::Auto),
+            _ => Err(format!(
+                "'{}' is not a valid format. Choose from [bin, hex, elf, srec, uf2, auto].",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum FileDownloadError {
     FlashLoader(FlashLoaderError),
     IhexRead(ihex::reader::ReaderError),
+    SRecord(srec::SRecordError),
+    Uf2(uf2::Uf2Error),
     IO(std::io::Error),
     Object(&'static str),
+    NoBootMemory,
+    ImageTooLarge(u32, usize), // Contains the base address and the size of the image.
+    NotInRam(u32, usize),      // Contains the address and the size of the segment.
+    DebugProbe(DebugProbeError),
 }
 
 impl Error for FileDownloadError {}
@@ -40,8 +186,25 @@ impl fmt::Display for FileDownloadError {
         match self {
             FlashLoader(ref e) => e.fmt(f),
             IhexRead(ref e) => e.fmt(f),
+            SRecord(ref e) => e.fmt(f),
+            Uf2(ref e) => e.fmt(f),
             IO(ref e) => e.fmt(f),
             Object(ref s) => write!(f, "Object Error: {}.", s),
+            NoBootMemory => write!(
+                f,
+                "The target has no boot flash region to put the binary at."
+            ),
+            ImageTooLarge(address, size) => write!(
+                f,
+                "The image of {} bytes does not fit into the flash at address {:#010x}.",
+                size, address
+            ),
+            NotInRam(address, size) => write!(
+                f,
+                "The segment of {} bytes at address {:#010x} is not located in RAM.",
+                size, address
+            ),
+            DebugProbe(ref e) => e.fmt(f),
         }
     }
 }
@@ -58,41 +221,68 @@ impl From<ihex::reader::ReaderError> for FileDownloadError {
     }
 }
 
+impl From<srec::SRecordError> for FileDownloadError {
+    fn from(error: srec::SRecordError) -> FileDownloadError {
+        FileDownloadError::SRecord(error)
+    }
+}
+
+impl From<uf2::Uf2Error> for FileDownloadError {
+    fn from(error: uf2::Uf2Error) -> FileDownloadError {
+        FileDownloadError::Uf2(error)
+    }
+}
+
 impl From<std::io::Error> for FileDownloadError {
     fn from(error: std::io::Error) -> FileDownloadError {
         FileDownloadError::IO(error)
     }
 }
 
+impl From<DebugProbeError> for FileDownloadError {
+    fn from(error: DebugProbeError) -> FileDownloadError {
+        FileDownloadError::DebugProbe(error)
+    }
+}
+
+impl From<AccessPortError> for FileDownloadError {
+    fn from(error: AccessPortError) -> FileDownloadError {
+        FileDownloadError::DebugProbe(error.into())
+    }
+}
+
 impl From<&'static str> for FileDownloadError {
     fn from(error: &'static str) -> FileDownloadError {
         FileDownloadError::Object(error)
     }
 }
 
-/// Downloads a file at `path` into flash.
+/// Downloads a file at `path` into flash and returns the statistics of the programming.
 pub fn download_file_with_progress_reporting(
     session: &mut Session,
     path: &Path,
     format: Format,
     memory_map: &[MemoryRegion],
+    options: &DownloadOptions,
     progress: &FlashProgress,
-) -> Result<(), FileDownloadError> {
-    download_file_internal(session, path, format, memory_map, progress)
+) -> Result<FlashReport, FileDownloadError> {
+    download_file_internal(session, path, format, memory_map, options, progress)
 }
 
-/// Downloads a file at `path` into flash.
+/// Downloads a file at `path` into flash and returns the statistics of the programming.
 pub fn download_file(
     session: &mut Session,
     path: &Path,
     format: Format,
     memory_map: &[MemoryRegion],
-) -> Result<(), FileDownloadError> {
+    options: &DownloadOptions,
+) -> Result<FlashReport, FileDownloadError> {
     download_file_internal(
         session,
         path,
         format,
         memory_map,
+        options,
         &FlashProgress::new(|_| {}),
     )
 }
@@ -103,8 +293,9 @@ fn download_file_internal(
     path: &Path,
     format: Format,
     memory_map: &[MemoryRegion],
+    options: &DownloadOptions,
     progress: &FlashProgress,
-) -> Result<(), FileDownloadError> {
+) -> Result<FlashReport, FileDownloadError> {
     let mut file = match File::open(path) {
         Ok(file) => file,
         Err(e) => return Err(FileDownloadError::IO(e)),
@@ -112,25 +303,45 @@ fn download_file_internal(
     let mut buffer = vec![];
     let mut buffer_vec = vec![];
     // IMPORTANT: Change this to an actual memory map of a real chip
-    let mut loader = FlashLoader::new(memory_map, false);
+    let mut loader = FlashLoader::new(memory_map);
 
     match format {
-        Format::Bin(options) => download_bin(&mut buffer, &mut file, &mut loader, options),
+        Format::Bin(options) => {
+            download_bin(&mut buffer, &mut file, &mut loader, memory_map, options)
+        }
         Format::Elf => download_elf(&mut buffer, &mut file, &mut loader),
         Format::Hex => download_hex(&mut buffer_vec, &mut file, &mut loader),
+        Format::SRecord => download_srec(&mut buffer_vec, &mut file, &mut loader),
+        Format::Uf2(options) => download_uf2(&mut buffer_vec, &mut file, &mut loader, options),
+        Format::Auto => {
+            let format = detect_format(path, &mut file)?;
+            log::debug!("Detected the format {:?} for {}.", format, path.display());
+
+            return download_file_internal(session, path, format, memory_map, options, progress);
+        }
     }?;
 
     loader
-        // TODO: hand out chip erase flag
-        .commit(session, progress, false)
+        .commit(session, progress, options)
         .map_err(FileDownloadError::FlashLoader)
 }
 
+/// Detects the format of `file` from its first bytes.
+fn detect_format<T: Read + Seek>(path: &Path, file: &mut T) -> Result<Format, FileDownloadError> {
+    // An UF2 block is the largest header which is checked.
+    let mut header = vec![];
+    file.take(512).read_to_end(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(Format::detect(path, &header))
+}
+
 /// Starts the download of a binary file.
 fn download_bin<'b, T: Read + Seek>(
     buffer: &'b mut Vec<u8>,
     file: &'b mut T,
     loader: &mut FlashLoader<'_, 'b>,
+    memory_map: &[MemoryRegion],
     options: BinOptions,
 ) -> Result<(), FileDownloadError> {
     // Skip the specified bytes.
@@ -138,20 +349,67 @@ fn download_bin<'b, T: Read + Seek>(
 
     file.read_to_end(buffer)?;
 
-    loader.add_data(
-        if let Some(address) = options.base_address {
-            address
-        } else {
-            // If no base address is specified use the start of the boot memory.
-            // TODO: Implement this as soon as we know targets.
-            0
-        },
-        buffer.as_slice(),
-    )?;
+    let address = match options.base_address {
+        Some(address) => address,
+        // If no base address is specified use the start of the boot memory.
+        None => boot_memory_start(memory_map).ok_or(FileDownloadError::NoBootMemory)?,
+    };
+
+    if !fits_into_flash(memory_map, address, buffer.len()) {
+        return Err(FileDownloadError::ImageTooLarge(address, buffer.len()));
+    }
+
+    loader.add_data(address, buffer.as_slice())?;
 
     Ok(())
 }
 
+/// Returns the start address of the boot flash region.
+fn boot_memory_start(memory_map: &[MemoryRegion]) -> Option<u32> {
+    memory_map.iter().find_map(|region| match region {
+        MemoryRegion::Flash(r) if r.is_boot_memory => Some(r.range.start),
+        _ => None,
+    })
+}
+
+/// Checks that `size` bytes at `address` are covered by contiguous flash regions.
+fn fits_into_flash(memory_map: &[MemoryRegion], address: u32, size: usize) -> bool {
+    fits_into(memory_map, address, size, |region| match region {
+        MemoryRegion::Flash(r) => Some(&r.range),
+        _ => None,
+    })
+}
+
+/// Checks that `size` bytes at `address` are covered by contiguous RAM regions.
+fn fits_into_ram(memory_map: &[MemoryRegion], address: u32, size: usize) -> bool {
+    fits_into(memory_map, address, size, |region| match region {
+        MemoryRegion::Ram(r) => Some(&r.range),
+        _ => None,
+    })
+}
+
+/// Checks that `size` bytes at `address` are covered by contiguous regions,
+/// whose ranges are selected by `range_of`.
+fn fits_into<F>(memory_map: &[MemoryRegion], address: u32, size: usize, range_of: F) -> bool
+where
+    F: Fn(&MemoryRegion) -> Option<&core::ops::Range<u32>>,
+{
+    let end = u64::from(address) + size as u64;
+    let mut address = u64::from(address);
+
+    while address < end {
+        let range = memory_map
+            .iter()
+            .filter_map(&range_of)
+            .find(|range| range.contains(&(address as u32)));
+        match range {
+            Some(range) => address = u64::from(range.end),
+            None => return false,
+        }
+    }
+    true
+}
+
 /// Starts the download of a hex file.
 fn download_hex<'b, T: Read + Seek>(
     buffer: &'b mut Vec<(u32, Vec<u8>)>,
@@ -188,6 +446,41 @@ fn download_hex<'b, T: Read + Seek>(
     Ok(())
 }
 
+/// Starts the download of a Motorola S-record file.
+fn download_srec<'b, T: Read + Seek>(
+    buffer: &'b mut Vec<(u32, Vec<u8>)>,
+    file: &mut T,
+    loader: &mut FlashLoader<'_, 'b>,
+) -> Result<(), FileDownloadError> {
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+
+    *buffer = srec::parse(&data)?;
+
+    for (address, data) in buffer {
+        loader.add_data(*address, data.as_slice())?;
+    }
+    Ok(())
+}
+
+/// Starts the download of an UF2 file.
+fn download_uf2<'b, T: Read + Seek>(
+    buffer: &'b mut Vec<(u32, Vec<u8>)>,
+    file: &mut T,
+    loader: &mut FlashLoader<'_, 'b>,
+    options: Uf2Options,
+) -> Result<(), FileDownloadError> {
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+
+    *buffer = uf2::parse(&data, options.family_id)?;
+
+    for (address, data) in buffer {
+        loader.add_data(*address, data.as_slice())?;
+    }
+    Ok(())
+}
+
 /// Starts the download of a elf file.
 fn download_elf<'b, T: Read + Seek>(
     buffer: &'b mut Vec<u8>,
@@ -228,3 +521,289 @@ fn download_elf<'b, T: Read + Seek>(
     }
     Ok(())
 }
+
+/// The largest binary image `elf_to_bin` creates, to catch segments which are far apart.
+const MAX_BIN_IMAGE_SIZE: u64 = 0x1000_0000;
+
+/// Converts the ELF file at `path` into a binary image, like `objcopy -O binary`.
+///
+/// The loadable segments are put at their offset from the lowest segment, and the gaps
+/// between them are filled with `0xFF`, the value of erased flash.
+/// Returns the address of the lowest segment and the image.
+pub fn elf_to_bin(path: &Path) -> Result<(u32, Vec<u8>), FileDownloadError> {
+    use goblin::elf::program_header::*;
+
+    let mut buffer = vec![];
+    File::open(path)?.read_to_end(&mut buffer)?;
+
+    let binary = goblin::elf::Elf::parse(buffer.as_slice())
+        .map_err(|_| FileDownloadError::Object("Failed to parse the ELF file"))?;
+    let mut segments = vec![];
+    for ph in &binary.program_headers {
+        if ph.p_type == PT_LOAD && ph.p_filesz > 0 {
+            let data = buffer
+                .get(ph.p_offset as usize..)
+                .and_then(|data| data.get(..ph.p_filesz as usize))
+                .ok_or("A segment exceeds the ELF file")?;
+            segments.push((ph.p_paddr as u32, data));
+        }
+    }
+
+    flatten_segments(&segments)
+}
+
+/// Puts the segments into one image, see `elf_to_bin`.
+fn flatten_segments(segments: &[(u32, &[u8])]) -> Result<(u32, Vec<u8>), FileDownloadError> {
+    let start = segments
+        .iter()
+        .map(|(address, _)| *address)
+        .min()
+        .ok_or("The ELF file has no loadable segments")?;
+    let end = segments
+        .iter()
+        .map(|(address, data)| u64::from(*address) + data.len() as u64)
+        .max()
+        .unwrap_or_else(|| u64::from(start));
+    if end - u64::from(start) > MAX_BIN_IMAGE_SIZE {
+        return Err(FileDownloadError::Object(
+            "The segments are too far apart for a binary image",
+        ));
+    }
+
+    let mut image = vec![0xFF; (end - u64::from(start)) as usize];
+    for (address, data) in segments {
+        let offset = (address - start) as usize;
+        image[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    Ok((start, image))
+}
+
+/// The Vector Table Offset Register of a Cortex-M core.
+const VTOR: u32 = 0xE000_ED08;
+
+/// Downloads the ELF file at `path` into the RAM of the target and runs it.
+///
+/// All loadable segments have to be located in RAM regions of `memory_map`.
+/// The vector table is expected at the start of the lowest segment. The main core is
+/// reset and halted, the segments are written, and the core is started with the
+/// initial stack pointer and the reset vector of the vector table.
+pub fn run_from_ram(
+    session: &mut Session,
+    path: &Path,
+    memory_map: &[MemoryRegion],
+) -> Result<(), FileDownloadError> {
+    let mut buffer = vec![];
+    File::open(path)?.read_to_end(&mut buffer)?;
+
+    let binary = goblin::elf::Elf::parse(buffer.as_slice())
+        .map_err(|_| FileDownloadError::Object("Failed to parse the ELF file"))?;
+    let segments = ram_segments(&binary, &buffer, memory_map)?;
+    let (vector_table, stack_pointer, reset_vector) =
+        vector_table(&segments).ok_or("The image does not start with a vector table")?;
+
+    // The image always runs on the main core of the target.
+    session.select_main_core_port();
+    let core = session.target.main_core().core.clone();
+    let result = start_from_ram(
+        &*core,
+        &mut session.probe,
+        &segments,
+        vector_table,
+        stack_pointer,
+        reset_vector,
+    );
+    session.restore_core_selection();
+
+    result
+}
+
+/// Writes the segments and starts the core at `reset_vector`.
+fn start_from_ram(
+    core: &dyn Core,
+    probe: &mut MasterProbe,
+    segments: &[(u32, &[u8])],
+    vector_table: u32,
+    stack_pointer: u32,
+    reset_vector: u32,
+) -> Result<(), FileDownloadError> {
+    core.reset_and_halt(probe)?;
+
+    for (address, data) in segments {
+        log::debug!("Writing {} bytes to {:#010x}", data.len(), address);
+        probe.write_block8(*address, data)?;
+    }
+
+    log::debug!(
+        "Starting at {:#010x} with the vector table at {:#010x}",
+        reset_vector,
+        vector_table
+    );
+    probe.write32(VTOR, vector_table)?;
+
+    let msp = core
+        .register_by_name("MSP")
+        .ok_or("The core has no main stack pointer")?;
+    core.write_register(probe, msp, stack_pointer)?;
+
+    let registers = core.registers();
+    // The Thumb bit of the reset vector is moved into the XPSR.
+    core.write_core_reg(probe, registers.PC, reset_vector & !1)?;
+    core.write_core_reg(probe, registers.XPSR, 1 << 24)?;
+
+    core.run(probe)?;
+
+    Ok(())
+}
+
+/// Returns the loadable segments of an ELF file ordered by their address,
+/// and checks that all of them are located in RAM.
+fn ram_segments<'b>(
+    binary: &goblin::elf::Elf,
+    buffer: &'b [u8],
+    memory_map: &[MemoryRegion],
+) -> Result<Vec<(u32, &'b [u8])>, FileDownloadError> {
+    use goblin::elf::program_header::*;
+
+    let mut segments = vec![];
+    for ph in &binary.program_headers {
+        if ph.p_type == PT_LOAD && ph.p_memsz > 0 {
+            let address = ph.p_paddr as u32;
+            // The part of the segment which is not in the file, like `.bss`, has to fit as well.
+            let size = ph.p_memsz as usize;
+            if !fits_into_ram(memory_map, address, size) {
+                return Err(FileDownloadError::NotInRam(address, size));
+            }
+
+            let data = buffer
+                .get(ph.p_offset as usize..)
+                .and_then(|data| data.get(..ph.p_filesz as usize))
+                .ok_or("A segment exceeds the ELF file")?;
+            if !data.is_empty() {
+                segments.push((address, data));
+            }
+        }
+    }
+    segments.sort_by_key(|(address, _)| *address);
+
+    Ok(segments)
+}
+
+/// Returns the address of the vector table at the start of the lowest segment,
+/// together with its initial stack pointer and reset vector.
+fn vector_table(segments: &[(u32, &[u8])]) -> Option<(u32, u32, u32)> {
+    let (address, data) = segments.first()?;
+    if data.len() < 8 {
+        return None;
+    }
+    let word = |offset: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    };
+
+    Some((*address, word(0), word(4)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        boot_memory_start, fits_into_flash, fits_into_ram, flatten_segments, parse_u32,
+        vector_table, Format,
+    };
+    use crate::config::memory::{FlashRegion, MemoryRegion, RamRegion, SectorDescription};
+    use std::path::Path;
+
+    fn flash(start: u32, end: u32, is_boot_memory: bool) -> MemoryRegion {
+        MemoryRegion::Flash(FlashRegion {
+            range: start..end,
+            is_boot_memory,
+            sectors: vec![SectorDescription {
+                offset: 0x0,
+                size: 0x400,
+            }],
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+        })
+    }
+
+    #[test]
+    fn binary_placement() {
+        let memory_map = vec![
+            flash(0x1000_0000, 0x1000_1000, false),
+            flash(0x0800_0000, 0x0800_8000, true),
+            flash(0x0800_8000, 0x0801_0000, false),
+        ];
+
+        assert_eq!(boot_memory_start(&memory_map), Some(0x0800_0000));
+
+        // Contiguous regions can be crossed, gaps and the end of the flash can't.
+        assert!(fits_into_flash(&memory_map, 0x0800_0000, 0x1_0000));
+        assert!(!fits_into_flash(&memory_map, 0x0800_0000, 0x1_0001));
+        assert!(!fits_into_flash(&memory_map, 0x0700_0000, 0x10));
+    }
+
+    #[test]
+    fn ram_placement() {
+        let memory_map = vec![
+            flash(0x0000_0000, 0x0010_0000, true),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2001_0000,
+                is_boot_memory: false,
+            }),
+        ];
+
+        assert!(fits_into_ram(&memory_map, 0x2000_0000, 0x1_0000));
+        assert!(!fits_into_ram(&memory_map, 0x2000_fff0, 0x20));
+        // Flash regions don't count as RAM.
+        assert!(!fits_into_ram(&memory_map, 0x0000_0000, 0x10));
+
+        let image = [0x00, 0x00, 0x01, 0x20, 0x41, 0x01, 0x00, 0x20, 0xFF];
+        let segments = [(0x2000_0000, &image[..]), (0x2000_1000, &image[..4])];
+        assert_eq!(
+            vector_table(&segments),
+            Some((0x2000_0000, 0x2001_0000, 0x2000_0141))
+        );
+        assert_eq!(vector_table(&segments[1..]), None);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_u32("0x0800ff00"), Ok(0x0800_FF00));
+        assert_eq!(parse_u32("0XA"), Ok(10));
+        assert_eq!(parse_u32("4096"), Ok(4096));
+        assert!(parse_u32("0x").is_err());
+        assert!(parse_u32("ff").is_err());
+        assert!(parse_u32("0x1_0000_0000").is_err());
+    }
+
+    #[test]
+    fn flatten_elf_segments() {
+        let segments = [(0x0800_0100, &[1, 2][..]), (0x0800_0000, &[3, 4, 5][..])];
+        let (address, image) = flatten_segments(&segments).unwrap();
+        assert_eq!(address, 0x0800_0000);
+        assert_eq!(image.len(), 0x102);
+        assert_eq!(&image[..4], &[3, 4, 5, 0xFF]);
+        assert_eq!(&image[0x100..], &[1, 2]);
+
+        // Flash and RAM segments don't make up a sensible image.
+        let segments = [(0x0800_0000, &[1][..]), (0x2000_0000, &[2][..])];
+        assert!(flatten_segments(&segments).is_err());
+        assert!(flatten_segments(&[]).is_err());
+    }
+
+    #[test]
+    fn detect_format() {
+        let detect =
+            |path: &str, bytes: &[u8]| format!("{:?}", Format::detect(Path::new(path), bytes));
+
+        assert_eq!(detect("firmware", b"\x7fELF\x01\x01\x01"), "Elf");
+        assert_eq!(detect("firmware.bin", b":020000040800F2\r\n"), "Hex");
+        assert_eq!(detect("firmware", b"\r\nS00F000068656C6C6F"), "SRecord");
+        assert!(detect("firmware", b"\x55\x46\x32\x0A\x57\x51\x5D\x9E").starts_with("Uf2"));
+
+        // Unknown contents are detected by the extension, and are binary by default.
+        assert_eq!(detect("firmware.s19", b""), "SRecord");
+        assert!(detect("firmware.img", b"\x00\x20\x00\x20").starts_with("Bin"));
+    }
+}
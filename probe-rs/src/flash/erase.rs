@@ -0,0 +1,341 @@
+use crate::config::memory::{FlashRegion, MemoryRegion, SectorInfo};
+use crate::session::Session;
+use core::ops::Range;
+use std::error::Error;
+use std::fmt;
+
+use super::flasher::{Flasher, FlasherError};
+use super::FlashProgress;
+
+#[derive(Debug)]
+pub enum EraseError {
+    NoSuitableFlash(u32), // Contains the faulty address.
+    EmptyRange,
+    NoFlashLoaderAlgorithmAttached,
+    Flasher(FlasherError),
+}
+
+impl Error for EraseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EraseError::Flasher(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EraseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EraseError::*;
+
+        match self {
+            NoSuitableFlash(addr) => {
+                write!(f, "No flash memory was found at address {:#08x}.", addr)
+            }
+            EmptyRange => write!(f, "The range to erase is empty."),
+            NoFlashLoaderAlgorithmAttached => write!(
+                f,
+                "Trying to erase flash, but no flash loader algorithm is attached."
+            ),
+            Flasher(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<FlasherError> for EraseError {
+    fn from(error: FlasherError) -> EraseError {
+        EraseError::Flasher(error)
+    }
+}
+
+/// Erases the entire flash of the target.
+///
+/// A chip erase is done with every flash algorithm which supports it.
+/// The sectors of all other flash regions are erased one by one.
+pub fn erase_all(session: &mut Session, progress: &FlashProgress) -> Result<(), EraseError> {
+    let regions = flash_regions(&session.target.memory_map);
+
+    let sectors = regions
+        .iter()
+        .flat_map(|r| r.sector_infos())
+        .collect::<Vec<_>>();
+    let total_sector_size = sectors.iter().map(|s| s.size).sum();
+    let page_size = regions.first().map(|r| r.page_size).unwrap_or(0);
+    progress.initialized(sectors.len(), 0, total_sector_size, page_size);
+    progress.started_erasing();
+
+    let result = run_on_main_core(session, |session| {
+        let target = &session.target;
+        // The names of the algorithms which already erased their chip.
+        let mut erased_chips: Vec<&str> = vec![];
+
+        for region in &regions {
+            let algo = target
+                .flash_algorithm_for_region(region)
+                .ok_or(EraseError::NoFlashLoaderAlgorithmAttached)?;
+            let region_sectors = region.sector_infos();
+            progress.started_region(region.range.clone(), &algo.name);
+
+            let mut t = std::time::Instant::now();
+            let mut flasher = Flasher::new(target, &mut session.probe, algo, region);
+
+            if algo.pc_erase_all.is_some() {
+                // The erase all routine wipes all regions of the algorithm at once.
+                if !erased_chips.contains(&algo.name.as_str()) {
+                    flasher.run_erase(|active| active.erase_all())?;
+                    erased_chips.push(&algo.name);
+                }
+                for sector in &region_sectors {
+                    progress.sector_erased(
+                        sector.base_address,
+                        sector.size,
+                        t.elapsed().as_millis(),
+                    );
+                }
+            } else {
+                flasher.run_erase(|active| {
+                    for sector in &region_sectors {
+                        active.erase_sector(sector.base_address)?;
+                        progress.sector_erased(
+                            sector.base_address,
+                            sector.size,
+                            t.elapsed().as_millis(),
+                        );
+                        t = std::time::Instant::now();
+                    }
+                    Ok::<_, EraseError>(())
+                })?;
+            }
+        }
+        Ok(())
+    });
+    if result.is_ok() {
+        progress.finished_erasing();
+    } else {
+        progress.failed_erasing();
+    }
+
+    result
+}
+
+/// Erases all sectors which overlap with `range`.
+///
+/// The range is rounded to sector boundaries, so more than the given range might be erased.
+pub fn erase_range(
+    session: &mut Session,
+    range: Range<u32>,
+    progress: &FlashProgress,
+) -> Result<(), EraseError> {
+    if range.start >= range.end {
+        return Err(EraseError::EmptyRange);
+    }
+
+    let regions = flash_regions(&session.target.memory_map);
+
+    // Make sure the entire range is covered by flash.
+    let mut address = range.start;
+    while address < range.end {
+        let region = regions
+            .iter()
+            .find(|r| r.range.contains(&address))
+            .ok_or(EraseError::NoSuitableFlash(address))?;
+        address = region.range.end;
+    }
+
+    let sectors = regions
+        .iter()
+        .flat_map(|r| sectors_in_range(r, &range))
+        .collect::<Vec<_>>();
+
+    erase(session, &sectors, progress)
+}
+
+/// Erases the sectors which contain the given addresses.
+pub fn erase_sectors(
+    session: &mut Session,
+    addresses: &[u32],
+    progress: &FlashProgress,
+) -> Result<(), EraseError> {
+    let regions = flash_regions(&session.target.memory_map);
+
+    let mut sectors: Vec<SectorInfo> = vec![];
+    for &address in addresses {
+        let sector = regions
+            .iter()
+            .find_map(|r| r.sector_info(address))
+            .ok_or(EraseError::NoSuitableFlash(address))?;
+
+        // Erase every sector only once.
+        if !sectors
+            .iter()
+            .any(|s| s.base_address == sector.base_address)
+        {
+            sectors.push(sector);
+        }
+    }
+    sectors.sort_by_key(|s| s.base_address);
+
+    erase(session, &sectors, progress)
+}
+
+/// Erases all `sectors` one by one.
+fn erase(
+    session: &mut Session,
+    sectors: &[SectorInfo],
+    progress: &FlashProgress,
+) -> Result<(), EraseError> {
+    let regions = flash_regions(&session.target.memory_map);
+
+    let total_sector_size = sectors.iter().map(|s| s.size).sum();
+    let page_size = sectors.first().map(|s| s.page_size).unwrap_or(0);
+    progress.initialized(sectors.len(), 0, total_sector_size, page_size);
+    progress.started_erasing();
+
+    let result = run_on_main_core(session, |session| {
+        let target = &session.target;
+
+        for region in &regions {
+            let region_sectors = sectors
+                .iter()
+                .filter(|s| region.range.contains(&s.base_address))
+                .collect::<Vec<_>>();
+
+            if region_sectors.is_empty() {
+                continue;
+            }
+
+            let algo = target
+                .flash_algorithm_for_region(region)
+                .ok_or(EraseError::NoFlashLoaderAlgorithmAttached)?;
+            progress.started_region(region.range.clone(), &algo.name);
+
+            let mut t = std::time::Instant::now();
+            Flasher::new(target, &mut session.probe, algo, region).run_erase(|active| {
+                for sector in region_sectors {
+                    active.erase_sector(sector.base_address)?;
+                    progress.sector_erased(
+                        sector.base_address,
+                        sector.size,
+                        t.elapsed().as_millis(),
+                    );
+                    t = std::time::Instant::now();
+                }
+                Ok::<_, EraseError>(())
+            })?;
+        }
+        Ok(())
+    });
+    if result.is_ok() {
+        progress.finished_erasing();
+    } else {
+        progress.failed_erasing();
+    }
+
+    result
+}
+
+/// Runs `f` with the main core selected, as the flash algorithm always runs on the main core.
+fn run_on_main_core<T>(
+    session: &mut Session,
+    f: impl FnOnce(&mut Session) -> Result<T, EraseError>,
+) -> Result<T, EraseError> {
+    session.select_main_core_port();
+    let result = f(session);
+    session.restore_core_selection();
+
+    result
+}
+
+fn flash_regions(memory_map: &[MemoryRegion]) -> Vec<FlashRegion> {
+    memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Flash(r) => Some(r.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns all sectors of `region` which overlap with `range`.
+fn sectors_in_range(region: &FlashRegion, range: &Range<u32>) -> Vec<SectorInfo> {
+    let start = u32::max(range.start, region.range.start);
+    let end = u32::min(range.end, region.range.end);
+
+    let mut sectors = vec![];
+    let mut address = start;
+    while address < end {
+        if let Some(sector) = region.sector_info(address) {
+            address = sector.base_address + sector.size;
+            sectors.push(sector);
+        } else {
+            break;
+        }
+    }
+    sectors
+}
+
+#[cfg(test)]
+mod test {
+    use super::sectors_in_range;
+    use crate::config::memory::{FlashRegion, SectorDescription};
+
+    #[test]
+    fn range_is_rounded_to_sectors() {
+        let region = FlashRegion {
+            range: 0x0000..0x1_0000,
+            is_boot_memory: true,
+            sectors: vec![SectorDescription {
+                offset: 0x0,
+                size: 0x1000,
+            }],
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+        };
+
+        let sectors = sectors_in_range(&region, &(0x0800..0x3001));
+        let addresses = sectors.iter().map(|s| s.base_address).collect::<Vec<_>>();
+        assert_eq!(addresses, vec![0x0000, 0x1000, 0x2000, 0x3000]);
+
+        // Only the part of the range inside the region is erased.
+        let sectors = sectors_in_range(&region, &(0xF000..0x2_0000));
+        assert_eq!(sectors.len(), 1);
+    }
+
+    #[test]
+    fn range_is_rounded_to_mixed_sectors() {
+        let region = FlashRegion {
+            range: 0x0800_0000..0x0804_0000,
+            is_boot_memory: true,
+            sectors: vec![
+                SectorDescription {
+                    offset: 0x0,
+                    size: 0x4000,
+                },
+                SectorDescription {
+                    offset: 0x1_0000,
+                    size: 0x1_0000,
+                },
+                SectorDescription {
+                    offset: 0x2_0000,
+                    size: 0x2_0000,
+                },
+            ],
+            page_size: 0x400,
+            erased_byte_value: 0xFF,
+        };
+
+        let sectors = sectors_in_range(&region, &(0x0800_C000..0x0802_0001));
+        let sectors = sectors
+            .iter()
+            .map(|s| (s.base_address, s.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sectors,
+            vec![
+                (0x0800_C000, 0x4000),
+                (0x0801_0000, 0x1_0000),
+                (0x0802_0000, 0x2_0000)
+            ]
+        );
+    }
+}
@@ -1,5 +1,5 @@
-use super::builder::FlashBuilder;
-use super::FlashProgress;
+use super::builder::{FlashBuilder, FlashBuilderError};
+use super::{DownloadOptions, FlashProgress, RegionReport};
 use crate::config::{
     flash_algorithm::FlashAlgorithm,
     memory::{FlashRegion, MemoryRange},
@@ -8,6 +8,15 @@ use crate::config::{
 use crate::coresight::{access_ports::AccessPortError, memory::MI};
 use crate::probe::{DebugProbeError, MasterProbe};
 
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The address of the Configurable Fault Status Register.
+const CFSR: u32 = 0xE000_ED28;
+/// The address of the HardFault Status Register.
+const HFSR: u32 = 0xE000_ED2C;
+
 pub trait Operation {
     fn operation() -> u32;
     fn operation_name(&self) -> &str {
@@ -62,6 +71,132 @@ pub enum FlasherError {
     AccessPort(AccessPortError),
     DebugProbe(DebugProbeError),
     AddressNotInRegion(u32, FlashRegion),
+    /// The flash algorithm read back from RAM differs from the one which was written.
+    /// Contains the address of the first mismatching word and its expected and actual value.
+    AlgorithmVerification {
+        address: u32,
+        expected: u32,
+        actual: u32,
+    },
+    /// A routine of the flash algorithm did not return in time.
+    /// Contains the PC at which the core was stopped.
+    Timeout {
+        timeout: Duration,
+        pc: u32,
+    },
+    /// The core stopped somewhere else than at the return breakpoint of a routine.
+    /// Contains the PC, the active exception number and the fault status registers.
+    UnexpectedHalt {
+        pc: u32,
+        exception: u32,
+        cfsr: u32,
+        hfsr: u32,
+    },
+}
+
+impl Error for FlasherError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FlasherError::AccessPort(ref e) => Some(e),
+            FlasherError::DebugProbe(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FlasherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FlasherError::*;
+
+        match self {
+            Init(code) => write!(
+                f,
+                "The flash algorithm init routine failed with error code {}.",
+                code
+            ),
+            Uninit(code) => write!(
+                f,
+                "The flash algorithm uninit routine failed with error code {}.",
+                code
+            ),
+            EraseAll(code) => write!(
+                f,
+                "Erasing the entire chip failed with error code {}.",
+                code
+            ),
+            EraseAllNotSupported => write!(
+                f,
+                "The flash algorithm does not support erasing the entire chip."
+            ),
+            EraseSector(code, address) => write!(
+                f,
+                "Erasing the sector at {:#010x} failed with error code {}.",
+                address, code
+            ),
+            ProgramPage(code, address) => write!(
+                f,
+                "Programming the page at {:#010x} failed with error code {}.",
+                address, code
+            ),
+            InvalidBufferNumber(n, max) => write!(
+                f,
+                "Page buffer {} does not exist, the flash algorithm has {} buffers.",
+                n, max
+            ),
+            UnalignedFlashWriteAddress => write!(f, "The flash write address is not aligned."),
+            UnalignedPhraseLength => write!(f, "The phrase length is not aligned."),
+            ProgramPhrase(code, address) => write!(
+                f,
+                "Programming the phrase at {:#010x} failed with error code {}.",
+                address, code
+            ),
+            AnalyzerNotSupported => write!(f, "The flash analyzer is not supported."),
+            SizeNotPowerOf2 => write!(f, "The size is not a power of 2."),
+            AddressNotMultipleOfSize => write!(f, "The address is not a multiple of the size."),
+            AccessPort(ref e) => e.fmt(f),
+            DebugProbe(ref e) => e.fmt(f),
+            AddressNotInRegion(address, region) => write!(
+                f,
+                "The address {:#010x} is not in the flash region {:#010x}..{:#010x}.",
+                address, region.range.start, region.range.end
+            ),
+            AlgorithmVerification {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The flash algorithm was not loaded into RAM correctly. The word at {:#010x} is {:#010x} instead of {:#010x}.",
+                address, actual, expected
+            ),
+            Timeout { timeout, pc } => write!(
+                f,
+                "The flash algorithm did not finish within {:?} and was stopped at {:#010x}.",
+                timeout, pc
+            ),
+            UnexpectedHalt {
+                pc,
+                exception,
+                cfsr,
+                hfsr,
+            } => {
+                if *exception != 0 {
+                    write!(
+                        f,
+                        "The flash algorithm ran into exception {} at {:#010x}",
+                        exception, pc
+                    )?;
+                } else {
+                    write!(
+                        f,
+                        "The flash algorithm stopped unexpectedly at {:#010x}",
+                        pc
+                    )?;
+                }
+                write!(f, " (CFSR: {:#010x}, HFSR: {:#010x}).", cfsr, hfsr)
+            }
+        }
+    }
 }
 
 impl From<DebugProbeError> for FlasherError {
@@ -82,6 +217,7 @@ pub struct Flasher<'a> {
     flash_algorithm: &'a FlashAlgorithm,
     region: &'a FlashRegion,
     double_buffering_supported: bool,
+    algorithm_calls: u32,
 }
 
 impl<'a> Flasher<'a> {
@@ -97,6 +233,7 @@ impl<'a> Flasher<'a> {
             flash_algorithm,
             region,
             double_buffering_supported: false,
+            algorithm_calls: 0,
         }
     }
 
@@ -112,6 +249,19 @@ impl<'a> Flasher<'a> {
         self.double_buffering_supported
     }
 
+    /// Returns the number of flash algorithm routines which were called so far.
+    pub fn algorithm_calls(&self) -> u32 {
+        self.algorithm_calls
+    }
+
+    /// Reads flash memory through the memory interface of the probe.
+    ///
+    /// The flash algorithm is neither loaded nor run, and the core is not halted.
+    pub fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), FlasherError> {
+        self.probe.read_block8(address, data)?;
+        Ok(())
+    }
+
     pub fn init<'b, 's: 'b, O: Operation>(
         &'s mut self,
         mut address: Option<u32>,
@@ -121,37 +271,7 @@ impl<'a> Flasher<'a> {
         let flasher = self;
         let algo = flasher.flash_algorithm;
 
-        use capstone::arch::*;
-        let cs = capstone::Capstone::new()
-            .arm()
-            .mode(arm::ArchMode::Thumb)
-            .endian(capstone::Endian::Little)
-            .build()
-            .unwrap();
-        let i = algo
-            .instructions
-            .iter()
-            .map(|i| {
-                [
-                    *i as u8,
-                    (*i >> 8) as u8,
-                    (*i >> 16) as u8,
-                    (*i >> 24) as u8,
-                ]
-            })
-            .collect::<Vec<[u8; 4]>>()
-            .iter()
-            .flatten()
-            .copied()
-            .collect::<Vec<u8>>();
-
-        let instructions = cs
-            .disasm_all(i.as_slice(), u64::from(algo.load_address))
-            .unwrap();
-
-        for instruction in instructions.iter() {
-            log::debug!("{}", instruction);
-        }
+        log_disassembly(algo);
 
         if address.is_none() {
             address = Some(flasher.region.flash_info().rom_start);
@@ -159,14 +279,19 @@ impl<'a> Flasher<'a> {
 
         // TODO: Halt & reset target.
         log::debug!("Halting core.");
-        let cpu_info = flasher.target.core.halt(&mut flasher.probe);
-        log::debug!("PC = 0x{:08x}", cpu_info.unwrap().pc);
+        let cpu_info = flasher.target.main_core().core.halt(flasher.probe)?;
+        log::debug!("PC = 0x{:08x}", cpu_info.pc);
         flasher
             .target
+            .main_core()
             .core
-            .wait_for_core_halted(&mut flasher.probe)?;
+            .wait_for_core_halted(flasher.probe)?;
         log::debug!("Reset and halt");
-        flasher.target.core.reset_and_halt(&mut flasher.probe)?;
+        flasher
+            .target
+            .main_core()
+            .core
+            .reset_and_halt(flasher.probe)?;
 
         // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
 
@@ -182,22 +307,7 @@ impl<'a> Flasher<'a> {
         let mut data = vec![0; algo.instructions.len()];
         flasher.probe.read_block32(algo.load_address, &mut data)?;
 
-        for (offset, (original, read_back)) in algo.instructions.iter().zip(data.iter()).enumerate()
-        {
-            if original != read_back {
-                eprintln!(
-                    "Failed to verify flash algorithm. Data mismatch at address {:#08x}",
-                    algo.load_address + (4 * offset) as u32
-                );
-                eprintln!("Original instruction: {:#08x}", original);
-                eprintln!("Readback instruction: {:#08x}", read_back);
-
-                eprintln!("Original: {:x?}", &algo.instructions);
-                eprintln!("Readback: {:x?}", &data);
-
-                panic!("Flash algorithm not written to flash correctly.");
-            }
-        }
+        verify_algorithm(algo.load_address, &algo.instructions, &data)?;
 
         log::debug!("RAM contents match flashing algo blob.");
 
@@ -213,6 +323,7 @@ impl<'a> Flasher<'a> {
             flash_algorithm: flasher.flash_algorithm,
             region: flasher.region,
             double_buffering_supported: flasher.double_buffering_supported,
+            algorithm_calls: &mut flasher.algorithm_calls,
             _operation: core::marker::PhantomData,
         };
 
@@ -261,24 +372,23 @@ impl<'a> Flasher<'a> {
         progress: &FlashProgress,
         do_chip_erase: bool,
         _fast_verify: bool,
-    ) -> Result<(), FlasherError> {
+    ) -> Result<RegionReport, FlashBuilderError> {
         if !self
             .region
             .range
             .contains_range(&(address..address + data.len() as u32))
         {
-            return Err(FlasherError::AddressNotInRegion(
-                address,
-                self.region.clone(),
-            ));
+            return Err(FlasherError::AddressNotInRegion(address, self.region.clone()).into());
         }
 
         let mut fb = FlashBuilder::new();
-        fb.add_data(address, data).expect("Add Data failed");
-        fb.program(self, do_chip_erase, true, progress)
-            .expect("Add Data failed");
-
-        Ok(())
+        fb.add_data(address, data)?;
+        let options = DownloadOptions {
+            do_chip_erase,
+            keep_unwritten: true,
+            ..DownloadOptions::default()
+        };
+        fb.program(self, &options, progress)
     }
 }
 
@@ -288,6 +398,7 @@ pub struct ActiveFlasher<'a, O: Operation> {
     flash_algorithm: &'a FlashAlgorithm,
     region: &'a FlashRegion,
     double_buffering_supported: bool,
+    algorithm_calls: &'a mut u32,
     _operation: core::marker::PhantomData<O>,
 }
 
@@ -305,6 +416,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
                 Some(O::operation()),
                 None,
                 true,
+                FlashAlgorithm::INIT_TIMEOUT,
             )?;
 
             if result != 0 {
@@ -339,6 +451,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
                 None,
                 None,
                 false,
+                FlashAlgorithm::INIT_TIMEOUT,
             )?;
 
             if result != 0 {
@@ -352,9 +465,12 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
             flash_algorithm: self.flash_algorithm,
             region: self.region,
             double_buffering_supported: self.double_buffering_supported,
+            algorithm_calls: *self.algorithm_calls,
         })
     }
 
+    /// Calls the routine at `pc` and waits for its result, for at most `timeout` milliseconds.
+    #[allow(clippy::too_many_arguments)]
     fn call_function_and_wait(
         &mut self,
         pc: u32,
@@ -363,9 +479,10 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         r2: Option<u32>,
         r3: Option<u32>,
         init: bool,
+        timeout: u32,
     ) -> Result<u32, FlasherError> {
         self.call_function(pc, r0, r1, r2, r3, init)?;
-        self.wait_for_completion()
+        self.wait_for_completion(timeout)
     }
 
     fn call_function(
@@ -388,7 +505,7 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         );
 
         let algo = &self.flash_algorithm;
-        let regs = self.target.core.registers();
+        let regs = self.target.main_core().core.registers();
 
         [
             (regs.PC, Some(pc)),
@@ -404,12 +521,16 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         .map(|(addr, value)| {
             if let Some(v) = value {
                 self.target
+                    .main_core()
                     .core
-                    .write_core_reg(&mut self.probe, *addr, *v)?;
+                    .write_core_reg(self.probe, *addr, *v)?;
                 log::debug!(
                     "content of {:#x}: 0x{:08x} should be: 0x{:08x}",
                     addr.0,
-                    self.target.core.read_core_reg(&mut self.probe, *addr)?,
+                    self.target
+                        .main_core()
+                        .core
+                        .read_core_reg(self.probe, *addr)?,
                     *v
                 );
                 Ok(())
@@ -420,23 +541,54 @@ impl<'a, O: Operation> ActiveFlasher<'a, O> {
         .collect::<Result<Vec<()>, DebugProbeError>>()?;
 
         // Resume target operation.
-        self.target.core.run(&mut self.probe)?;
+        self.target.main_core().core.run(self.probe)?;
+        *self.algorithm_calls += 1;
 
         Ok(())
     }
 
-    pub fn wait_for_completion(&mut self) -> Result<u32, FlasherError> {
+    /// Waits until the running routine returns and reads its result.
+    ///
+    /// If the routine does not return within `timeout` milliseconds, the core is halted
+    /// and `FlasherError::Timeout` is returned. If the core stops anywhere else than at
+    /// the return breakpoint, e.g. in a fault handler, `FlasherError::UnexpectedHalt` is returned.
+    pub fn wait_for_completion(&mut self, timeout: u32) -> Result<u32, FlasherError> {
         log::debug!("Waiting for routine call completion.");
-        let regs = self.target.core.registers();
+        let core = &self.target.main_core().core;
+        let regs = core.registers();
+        let timeout = Duration::from_millis(u64::from(timeout));
+        let start = Instant::now();
+
+        loop {
+            match core.wait_for_core_halted(self.probe) {
+                Ok(()) => break,
+                Err(DebugProbeError::Timeout) if start.elapsed() < timeout => (),
+                Err(DebugProbeError::Timeout) => {
+                    // Stop the routine to find out where it hangs.
+                    let pc = core.halt(self.probe)?.pc;
+                    return Err(FlasherError::Timeout { timeout, pc });
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
 
-        while self
-            .target
-            .core
-            .wait_for_core_halted(&mut self.probe)
-            .is_err()
-        {}
+        // Every routine returns to the breakpoint at the start of the algorithm blob.
+        let pc = core.read_core_reg(self.probe, regs.PC)?;
+        if pc != self.flash_algorithm.load_address {
+            let exception = core.read_core_reg(self.probe, regs.XPSR)? & 0x1FF;
+            // ARMv6-M cores don't implement the fault status registers.
+            let cfsr = self.probe.read32(CFSR).unwrap_or(0);
+            let hfsr = self.probe.read32(HFSR).unwrap_or(0);
+
+            return Err(FlasherError::UnexpectedHalt {
+                pc,
+                exception,
+                cfsr,
+                hfsr,
+            });
+        }
 
-        let r = self.target.core.read_core_reg(&mut self.probe, regs.R0)?;
+        let r = core.read_core_reg(self.probe, regs.R0)?;
         Ok(r)
     }
 
@@ -458,8 +610,15 @@ impl<'a> ActiveFlasher<'a, Erase> {
         let algo = flasher.flash_algorithm;
 
         if let Some(pc_erase_all) = algo.pc_erase_all {
-            let result =
-                flasher.call_function_and_wait(pc_erase_all, None, None, None, None, false)?;
+            let result = flasher.call_function_and_wait(
+                pc_erase_all,
+                None,
+                None,
+                None,
+                None,
+                false,
+                algo.erase_all_timeout,
+            )?;
 
             if result != 0 {
                 Err(FlasherError::EraseAll(result))
@@ -484,6 +643,7 @@ impl<'a> ActiveFlasher<'a, Erase> {
             None,
             None,
             false,
+            algo.erase_sector_timeout,
         )?;
         log::info!(
             "Done erasing sector. Result is {}. This took {:?}",
@@ -516,6 +676,7 @@ impl<'a> ActiveFlasher<'a, Program> {
             Some(algo.begin_data),
             None,
             false,
+            algo.program_page_timeout,
         )?;
         log::info!("Flashing took: {:?}", t1.elapsed());
 
@@ -535,7 +696,7 @@ impl<'a> ActiveFlasher<'a, Program> {
         let algo = flasher.flash_algorithm;
 
         // Check the buffer number.
-        if buffer_number < algo.page_buffers.len() as u32 {
+        if buffer_number >= algo.page_buffers.len() as u32 {
             return Err(FlasherError::InvalidBufferNumber(
                 buffer_number,
                 algo.page_buffers.len() as u32,
@@ -564,7 +725,7 @@ impl<'a> ActiveFlasher<'a, Program> {
         let algo = flasher.flash_algorithm;
 
         // Check the buffer number.
-        if buffer_number < algo.page_buffers.len() as u32 {
+        if buffer_number >= algo.page_buffers.len() as u32 {
             return Err(FlasherError::InvalidBufferNumber(
                 buffer_number,
                 algo.page_buffers.len() as u32,
@@ -581,3 +742,136 @@ impl<'a> ActiveFlasher<'a, Program> {
         Ok(())
     }
 }
+
+impl<'a> ActiveFlasher<'a, Verify> {
+    /// Verifies one programmed page against `bytes`.
+    ///
+    /// The `Verify()` entry point of the flash algorithm is used if it has one,
+    /// otherwise the page is read back and compared.
+    /// Returns the first mismatching address, if there is one.
+    pub fn verify_page(&mut self, address: u32, bytes: &[u8]) -> Result<Option<u32>, FlasherError> {
+        let flasher = self;
+        let algo = flasher.flash_algorithm;
+
+        if let Some(pc_verify) = algo.pc_verify {
+            // Transfer the expected bytes to RAM.
+            flasher.probe.write_block8(algo.begin_data, bytes)?;
+            let result = flasher.call_function_and_wait(
+                pc_verify,
+                Some(address),
+                Some(bytes.len() as u32),
+                Some(algo.begin_data),
+                None,
+                false,
+                algo.program_page_timeout,
+            )?;
+
+            // On success, `Verify()` returns the address right after the verified block.
+            let end = address + bytes.len() as u32;
+            Ok(if result == end { None } else { Some(result) })
+        } else {
+            let mut data = vec![0; bytes.len()];
+            flasher.read_block8(address, &mut data)?;
+
+            Ok(first_mismatch(address, bytes, &data))
+        }
+    }
+}
+
+/// Logs the disassembled instructions of the flash algorithm.
+///
+/// This is only a debugging aid, so a failing disassembler does not stop the flashing.
+fn log_disassembly(algo: &FlashAlgorithm) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+
+    use capstone::arch::*;
+    let cs = match capstone::Capstone::new()
+        .arm()
+        .mode(arm::ArchMode::Thumb)
+        .endian(capstone::Endian::Little)
+        .build()
+    {
+        Ok(cs) => cs,
+        Err(e) => {
+            log::warn!("Failed to set up the disassembler: {}", e);
+            return;
+        }
+    };
+    let bytes = algo
+        .instructions
+        .iter()
+        .flat_map(|i| i.to_le_bytes().to_vec())
+        .collect::<Vec<u8>>();
+
+    let instructions = cs.disasm_all(&bytes, u64::from(algo.load_address));
+    match instructions {
+        Ok(instructions) => {
+            for instruction in instructions.iter() {
+                log::debug!("{}", instruction);
+            }
+        }
+        Err(e) => log::warn!("Failed to disassemble the flash algorithm: {}", e),
+    }
+}
+
+/// Checks that the flash algorithm `actual`ly loaded at `address` matches the `expected` instructions.
+fn verify_algorithm(address: u32, expected: &[u32], actual: &[u32]) -> Result<(), FlasherError> {
+    match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(offset) => {
+            log::debug!("Original: {:x?}", expected);
+            log::debug!("Readback: {:x?}", actual);
+
+            Err(FlasherError::AlgorithmVerification {
+                address: address + 4 * offset as u32,
+                expected: expected[offset],
+                actual: actual[offset],
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Returns the address of the first byte which differs between `expected` and `actual`.
+fn first_mismatch(address: u32, expected: &[u8], actual: &[u8]) -> Option<u32> {
+    expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .map(|offset| address + offset as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{first_mismatch, verify_algorithm, FlasherError};
+
+    #[test]
+    fn first_mismatch_reports_address() {
+        let expected = [0x01, 0x02, 0x03, 0x04];
+
+        assert_eq!(
+            first_mismatch(0x1000, &expected, &[0x01, 0x02, 0x03, 0x04]),
+            None
+        );
+        assert_eq!(
+            first_mismatch(0x1000, &expected, &[0x01, 0x02, 0xFF, 0xFF]),
+            Some(0x1002)
+        );
+    }
+
+    #[test]
+    fn verify_algorithm_reports_mismatch() {
+        let expected = [0xE00A_BE00, 0x062D_780D, 0x2408_4068];
+
+        assert!(verify_algorithm(0x2000_0000, &expected, &expected).is_ok());
+        assert!(matches!(
+            verify_algorithm(0x2000_0000, &expected, &[0xE00A_BE00, 0x062D_780D, 0x0]),
+            Err(FlasherError::AlgorithmVerification {
+                address: 0x2000_0008,
+                expected: 0x2408_4068,
+                actual: 0x0,
+            })
+        ));
+    }
+}
@@ -2,10 +2,11 @@ use crate::session::Session;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 
-use super::builder::FlashBuilder;
+use super::builder::{FlashBuilder, FlashBuilderError};
 use super::flasher::Flasher;
-use super::FlashProgress;
+use super::{DownloadOptions, FlashProgress, FlashReport, RegionReport};
 use crate::config::memory::{FlashRegion, MemoryRegion};
 
 /// `FlashLoader` is a struct which manages the flashing of any chunks of data onto any sections of flash.
@@ -16,7 +17,6 @@ use crate::config::memory::{FlashRegion, MemoryRegion};
 pub struct FlashLoader<'a, 'b> {
     memory_map: &'a [MemoryRegion],
     builders: HashMap<FlashRegion, FlashBuilder<'b>>,
-    keep_unwritten: bool,
 }
 
 #[derive(Debug)]
@@ -24,9 +24,17 @@ pub enum FlashLoaderError {
     NoSuitableFlash(u32),      // Contains the faulty address.
     MemoryRegionNotFlash(u32), // Contains the faulty address.
     NoFlashLoaderAlgorithmAttached,
+    FlashBuilder(FlashBuilderError),
 }
 
-impl Error for FlashLoaderError {}
+impl Error for FlashLoaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FlashLoaderError::FlashBuilder(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for FlashLoaderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -36,16 +44,26 @@ impl fmt::Display for FlashLoaderError {
             NoSuitableFlash(addr) => write!(f, "No flash memory was found at address {:#08x}.", addr),
             MemoryRegionNotFlash(addr) => write!(f, "Trying to access flash at address {:#08x}, which is not inside any defined flash region.", addr),
             NoFlashLoaderAlgorithmAttached => write!(f, "Trying to write flash, but no flash loader algorithm is attached."),
+            FlashBuilder(ref e) => e.fmt(f),
         }
     }
 }
 
+impl From<FlashBuilderError> for FlashLoaderError {
+    fn from(error: FlashBuilderError) -> FlashLoaderError {
+        FlashLoaderError::FlashBuilder(error)
+    }
+}
+
 impl<'a, 'b> FlashLoader<'a, 'b> {
-    pub fn new(memory_map: &'a [MemoryRegion], keep_unwritten: bool) -> Self {
+    /// Creates a loader for the flash regions of `memory_map`.
+    ///
+    /// How the data is flashed, e.g. whether unwritten bytes are kept, is given
+    /// by the `DownloadOptions` passed to `commit`.
+    pub fn new(memory_map: &'a [MemoryRegion]) -> Self {
         Self {
             memory_map,
             builders: HashMap::new(),
-            keep_unwritten,
         }
     }
     /// Stages a junk of data to be programmed.
@@ -59,19 +77,14 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
             let possible_region = Self::get_region_for_address(self.memory_map, address);
             // If we found a corresponding region, create a builder.
             if let Some(MemoryRegion::Flash(region)) = possible_region {
-                // Get our builder instance.
-                if !self.builders.contains_key(region) {
-                    self.builders.insert(region.clone(), FlashBuilder::new());
-                };
-
                 // Determine how much more data can be contained by this region.
-                let program_length =
-                    usize::min(remaining, (region.range.end - address + 1) as usize);
+                let program_length = usize::min(remaining, (region.range.end - address) as usize);
 
                 // Add as much data to the builder as can be contained by this region.
                 self.builders
-                    .get_mut(&region)
-                    .map(|r| r.add_data(address, &data[size - remaining..program_length]));
+                    .entry(region.clone())
+                    .or_default()
+                    .add_data(address, &data[size - remaining..][..program_length])?;
 
                 // Advance the cursors.
                 remaining -= program_length;
@@ -104,39 +117,110 @@ impl<'a, 'b> FlashLoader<'a, 'b> {
     ///
     /// Requires a session with an attached target that has a known flash algorithm.
     ///
-    /// If `do_chip_erase` is `true` the entire flash will be erased.
+    /// See `DownloadOptions` for the available options.
+    /// A mismatch during verification is reported as `FlashBuilderError::Verify`.
+    ///
+    /// Returns the statistics of all programmed regions.
     pub fn commit(
         &mut self,
         session: &mut Session,
         progress: &FlashProgress,
-        do_chip_erase: bool,
-    ) -> Result<(), FlashLoaderError> {
+        options: &DownloadOptions,
+    ) -> Result<FlashReport, FlashLoaderError> {
+        let start = Instant::now();
+
+        // The flash algorithm always runs on the main core of the target.
+        session.select_main_core_port();
+        let result = self.program(session, progress, options);
+        session.restore_core_selection();
+
+        Ok(FlashReport {
+            regions: result?,
+            total_time: start.elapsed(),
+        })
+    }
+
+    fn program(
+        &mut self,
+        session: &mut Session,
+        progress: &FlashProgress,
+        options: &DownloadOptions,
+    ) -> Result<Vec<RegionReport>, FlashLoaderError> {
         let target = &session.target;
         let probe = &mut session.probe;
 
-        // If the session target has a flash algorithm attached, initiate the download.
-        if let Some(flash_algorithm) = target.flash_algorithm.as_ref() {
-            // Iterate over builders we've created and program the data.
-            for (region, builder) in &self.builders {
-                log::debug!(
-                    "Using builder for region (0x{:08x}..0x{:08x})",
-                    region.range.start,
-                    region.range.end
-                );
-                // Program the data.
-                builder
-                    .program(
-                        Flasher::new(target, probe, flash_algorithm, region),
-                        do_chip_erase,
-                        self.keep_unwritten,
-                        progress,
-                    )
-                    .unwrap();
-            }
+        // Every region is programmed with its own flash algorithm.
+        // Make sure all of them are present before anything is written.
+        let mut builders = self
+            .builders
+            .iter()
+            .map(|(region, builder)| {
+                target
+                    .flash_algorithm_for_region(region)
+                    .map(|algorithm| (region, algorithm, builder))
+                    .ok_or(FlashLoaderError::NoFlashLoaderAlgorithmAttached)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        builders.sort_by_key(|(region, _, _)| region.range.start);
 
-            Ok(())
-        } else {
-            Err(FlashLoaderError::NoFlashLoaderAlgorithmAttached)
+        // Iterate over builders we've created and program the data.
+        let mut reports = vec![];
+        for (region, flash_algorithm, builder) in builders {
+            log::debug!(
+                "Using builder for region (0x{:08x}..0x{:08x}) with algorithm {}",
+                region.range.start,
+                region.range.end,
+                flash_algorithm.name
+            );
+            // Program the data.
+            reports.push(builder.program(
+                Flasher::new(target, probe, flash_algorithm, region),
+                options,
+                progress,
+            )?);
         }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashLoader, FlashLoaderError};
+    use crate::config::memory::{FlashRegion, MemoryRegion, SectorDescription};
+
+    fn flash(start: u32, end: u32) -> MemoryRegion {
+        MemoryRegion::Flash(FlashRegion {
+            range: start..end,
+            is_boot_memory: false,
+            sectors: vec![SectorDescription {
+                offset: 0x0,
+                size: 0x400,
+            }],
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+        })
+    }
+
+    #[test]
+    fn add_data_across_regions() {
+        let memory_map = vec![flash(0x0, 0x1000), flash(0x1000, 0x2000)];
+        let data = vec![0u8; 0x800];
+
+        let mut loader = FlashLoader::new(&memory_map);
+        loader.add_data(0xC00, &data).unwrap();
+
+        assert_eq!(loader.builders.len(), 2);
+
+        // Data which does not fit into the contiguous regions is rejected.
+        assert!(matches!(
+            loader.add_data(0x1C00, &data),
+            Err(FlashLoaderError::NoSuitableFlash(0x2000))
+        ));
+        // Overlapping data is reported by the builder.
+        assert!(matches!(
+            loader.add_data(0xC00, &data[..0x10]),
+            Err(FlashLoaderError::FlashBuilder(_))
+        ));
     }
 }
@@ -3,13 +3,22 @@
 // Copyright (c) for that code 2015-2019 Arm Limited under the the Apache 2.0 license.
 
 pub mod builder;
+pub mod config;
 pub mod download;
+pub mod erase;
 pub mod flasher;
 pub mod loader;
 pub mod progress;
+pub mod readback;
+pub mod report;
+pub mod srec;
+pub mod uf2;
 
 pub use builder::*;
 pub use download::*;
+pub use erase::*;
 pub use flasher::*;
 pub use loader::*;
 pub use progress::*;
+pub use readback::*;
+pub use report::*;
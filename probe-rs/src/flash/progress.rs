@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 pub struct FlashProgress {
     handler: Box<dyn Fn(ProgressEvent)>,
 }
@@ -17,17 +19,24 @@ impl FlashProgress {
         &self,
         total_sectors: usize,
         total_pages: usize,
-        sector_size: u32,
+        total_sector_size: u32,
         page_size: u32,
     ) {
         self.emit(ProgressEvent::Initialized {
             total_sectors,
             total_pages,
-            sector_size,
+            total_sector_size,
             page_size,
         });
     }
 
+    pub fn started_region(&self, range: Range<u32>, algorithm: &str) {
+        self.emit(ProgressEvent::StartedRegion {
+            range,
+            algorithm: algorithm.to_owned(),
+        });
+    }
+
     pub fn started_flashing(&self) {
         self.emit(ProgressEvent::StartedFlashing);
     }
@@ -36,12 +45,36 @@ impl FlashProgress {
         self.emit(ProgressEvent::StartedErasing);
     }
 
-    pub fn page_programmed(&self, size: u32, time: u128) {
-        self.emit(ProgressEvent::PageFlashed { size, time });
+    pub fn started_verifying(&self) {
+        self.emit(ProgressEvent::StartedVerifying);
+    }
+
+    pub fn page_programmed(&self, address: u32, size: u32, time: u128) {
+        self.emit(ProgressEvent::PageFlashed {
+            address,
+            size,
+            time,
+        });
+    }
+
+    pub fn sector_erased(&self, address: u32, size: u32, time: u128) {
+        self.emit(ProgressEvent::SectorErased {
+            address,
+            size,
+            time,
+        });
+    }
+
+    pub fn page_verified(&self, address: u32, size: u32, time: u128) {
+        self.emit(ProgressEvent::PageVerified {
+            address,
+            size,
+            time,
+        });
     }
 
-    pub fn sector_erased(&self, size: u32, time: u128) {
-        self.emit(ProgressEvent::SectorErased { size, time });
+    pub fn sector_skipped(&self, address: u32, size: u32) {
+        self.emit(ProgressEvent::SectorSkipped { address, size });
     }
 
     pub fn finished_programming(&self) {
@@ -51,25 +84,106 @@ impl FlashProgress {
     pub fn finished_erasing(&self) {
         self.emit(ProgressEvent::FinishedErasing);
     }
+
+    pub fn finished_verifying(&self) {
+        self.emit(ProgressEvent::FinishedVerifying);
+    }
+
+    pub fn failed_programming(&self) {
+        self.emit(ProgressEvent::FailedProgramming);
+    }
+
+    pub fn failed_erasing(&self) {
+        self.emit(ProgressEvent::FailedErasing);
+    }
+
+    pub fn failed_verifying(&self) {
+        self.emit(ProgressEvent::FailedVerifying);
+    }
 }
 
+/// An event which reports the progress of flashing or erasing.
+///
+/// Events are serialized with their name in the `event` field,
+/// e.g. `{"event":"sector_erased","address":0,"size":4096,"time":21}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
 pub enum ProgressEvent {
     Initialized {
         total_pages: usize,
         total_sectors: usize,
-        sector_size: u32,
+        /// The sum of the sizes of all sectors, as sectors may differ in size.
+        total_sector_size: u32,
         page_size: u32,
     },
+    /// The flash region `range` is about to be processed with the flash algorithm `algorithm`.
+    StartedRegion {
+        range: Range<u32>,
+        algorithm: String,
+    },
     StartedFlashing,
     StartedErasing,
+    StartedVerifying,
     PageFlashed {
+        address: u32,
         size: u32,
+        /// The time the operation took in milliseconds.
         time: u128,
     },
     SectorErased {
+        address: u32,
+        size: u32,
+        /// The time the operation took in milliseconds.
+        time: u128,
+    },
+    PageVerified {
+        address: u32,
         size: u32,
+        /// The time the operation took in milliseconds.
         time: u128,
     },
+    /// A sector already contains the data to be flashed and is neither erased nor programmed.
+    SectorSkipped {
+        address: u32,
+        size: u32,
+    },
     FinishedProgramming,
     FinishedErasing,
+    FinishedVerifying,
+    /// Programming failed. The error is returned by the operation.
+    FailedProgramming,
+    /// Erasing failed. The error is returned by the operation.
+    FailedErasing,
+    /// Verifying failed, either because of an error or because the flash contents do not match.
+    FailedVerifying,
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProgressEvent;
+
+    #[test]
+    fn serialize_events() {
+        let json = |event: ProgressEvent| serde_json::to_string(&event).unwrap();
+
+        assert_eq!(
+            json(ProgressEvent::SectorErased {
+                address: 0x800_0000,
+                size: 0x4000,
+                time: u128::from(u64::MAX) + 1,
+            }),
+            r#"{"event":"sector_erased","address":134217728,"size":16384,"time":18446744073709551616}"#
+        );
+        assert_eq!(
+            json(ProgressEvent::StartedRegion {
+                range: 0x1000_1000..0x1000_2000,
+                algorithm: "uicr".to_owned(),
+            }),
+            r#"{"event":"started_region","range":{"start":268439552,"end":268443648},"algorithm":"uicr"}"#
+        );
+        assert_eq!(
+            json(ProgressEvent::FailedVerifying),
+            r#"{"event":"failed_verifying"}"#
+        );
+    }
 }
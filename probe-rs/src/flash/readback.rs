@@ -0,0 +1,410 @@
+use crate::config::memory::MemoryRegion;
+use crate::coresight::{access_ports::AccessPortError, memory::MI};
+use crate::session::Session;
+use core::ops::Range;
+use ihex::record::Record;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+/// The number of bytes which are read from the target at once.
+const CHUNK_SIZE: usize = 4096;
+
+/// The byte which fills the gaps between segments in a raw binary.
+const BIN_FILL_BYTE: u8 = 0xFF;
+
+/// A contiguous block of memory which was read from the target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemorySegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// The file formats memory can be written to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// Raw binary, gaps between segments are filled with `0xFF`.
+    Bin,
+    /// Intel HEX.
+    Hex,
+    /// An ELF file with one section and one loadable segment per memory segment.
+    Elf,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "bin" => Ok(OutputFormat::Bin),
+            "hex" => Ok(OutputFormat::Hex),
+            "elf" => Ok(OutputFormat::Elf),
+            _ => Err(format!(
+                "'{}' is not a valid format. Choose from [bin, hex, elf].",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadbackError {
+    AccessPort(AccessPortError),
+    IO(std::io::Error),
+    IhexWrite(ihex::writer::WriterError),
+}
+
+impl Error for ReadbackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use ReadbackError::*;
+
+        match self {
+            AccessPort(ref e) => Some(e),
+            IO(ref e) => Some(e),
+            IhexWrite(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ReadbackError::*;
+
+        match self {
+            AccessPort(ref e) => e.fmt(f),
+            IO(ref e) => e.fmt(f),
+            IhexWrite(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<AccessPortError> for ReadbackError {
+    fn from(error: AccessPortError) -> ReadbackError {
+        ReadbackError::AccessPort(error)
+    }
+}
+
+impl From<std::io::Error> for ReadbackError {
+    fn from(error: std::io::Error) -> ReadbackError {
+        ReadbackError::IO(error)
+    }
+}
+
+impl From<ihex::writer::WriterError> for ReadbackError {
+    fn from(error: ihex::writer::WriterError) -> ReadbackError {
+        ReadbackError::IhexWrite(error)
+    }
+}
+
+/// Returns the address ranges of all flash regions in `memory_map`.
+pub fn flash_ranges(memory_map: &[MemoryRegion]) -> Vec<Range<u32>> {
+    memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Flash(r) => Some(r.range.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads all `ranges` from the memory of the target.
+///
+/// The memory is read through the main core of the target.
+/// `progress` is called with the number of bytes read after every block.
+pub fn read_memory(
+    session: &mut Session,
+    ranges: &[Range<u32>],
+    mut progress: impl FnMut(usize),
+) -> Result<Vec<MemorySegment>, ReadbackError> {
+    session.select_main_core_port();
+
+    let mut read = || {
+        let mut segments = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let mut data = vec![0; (range.end - range.start) as usize];
+            for (i, chunk) in data.chunks_mut(CHUNK_SIZE).enumerate() {
+                let address = range.start + (i * CHUNK_SIZE) as u32;
+                session.probe.read_block8(address, chunk)?;
+                progress(chunk.len());
+            }
+            segments.push(MemorySegment {
+                address: range.start,
+                data,
+            });
+        }
+        Ok(segments)
+    };
+    let result = read();
+
+    session.restore_core_selection();
+
+    result
+}
+
+/// Writes `segments` to `writer` in the given `format`.
+pub fn write_segments(
+    writer: &mut impl Write,
+    segments: &[MemorySegment],
+    format: OutputFormat,
+) -> Result<(), ReadbackError> {
+    match format {
+        OutputFormat::Bin => write_bin(writer, segments),
+        OutputFormat::Hex => write_hex(writer, segments),
+        OutputFormat::Elf => write_elf(writer, segments),
+    }
+}
+
+/// Writes all segments as one raw binary, which starts at the lowest segment address.
+fn write_bin(writer: &mut impl Write, segments: &[MemorySegment]) -> Result<(), ReadbackError> {
+    let mut sorted = segments.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|s| s.address);
+
+    let mut position = match sorted.first() {
+        Some(segment) => segment.address,
+        None => return Ok(()),
+    };
+
+    for segment in sorted {
+        if segment.address > position {
+            let gap = vec![BIN_FILL_BYTE; (segment.address - position) as usize];
+            writer.write_all(&gap)?;
+        }
+        writer.write_all(&segment.data)?;
+        position = segment.address + segment.data.len() as u32;
+    }
+
+    Ok(())
+}
+
+/// Writes all segments as Intel HEX records.
+fn write_hex(writer: &mut impl Write, segments: &[MemorySegment]) -> Result<(), ReadbackError> {
+    let mut records = vec![];
+    let mut upper_address = None;
+
+    for segment in segments {
+        let mut offset = 0;
+        while offset < segment.data.len() {
+            let address = segment.address + offset as u32;
+
+            // A data record can't cross a 64 KiB boundary.
+            let upper = (address >> 16) as u16;
+            if upper_address != Some(upper) {
+                records.push(Record::ExtendedLinearAddress(upper));
+                upper_address = Some(upper);
+            }
+            let space_in_block = 0x1_0000 - (address & 0xFFFF) as usize;
+            let length = usize::min(16, segment.data.len() - offset).min(space_in_block);
+
+            records.push(Record::Data {
+                offset: address as u16,
+                value: segment.data[offset..offset + length].to_vec(),
+            });
+            offset += length;
+        }
+    }
+    records.push(Record::EndOfFile);
+
+    let hex = ihex::writer::create_object_file_representation(&records)?;
+    writer.write_all(hex.as_bytes())?;
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+const ELF_HEADER_SIZE: u32 = 52;
+const ELF_PROGRAM_HEADER_SIZE: u32 = 32;
+const ELF_SECTION_HEADER_SIZE: u32 = 40;
+
+const EM_ARM: u16 = 40;
+const PT_LOAD: u32 = 1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+const PF_X: u32 = 0x1;
+const PF_R: u32 = 0x4;
+
+/// Writes all segments as a 32 bit little endian ELF file.
+///
+/// Every segment is stored in its own `.flash<n>` section and described by its own
+/// loadable program header, so the file can be downloaded again.
+fn write_elf(writer: &mut impl Write, segments: &[MemorySegment]) -> Result<(), ReadbackError> {
+    // The section names, starting with the empty name of the null section.
+    let mut strtab = vec![0u8];
+    let mut name_offsets = vec![];
+    for i in 0..segments.len() {
+        name_offsets.push(strtab.len() as u32);
+        strtab.extend(format!(".flash{}", i).as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab_name = strtab.len() as u32;
+    strtab.extend(b".shstrtab\0");
+
+    let phnum = segments.len() as u32;
+    let data_offset = ELF_HEADER_SIZE + phnum * ELF_PROGRAM_HEADER_SIZE;
+
+    let mut offsets = vec![];
+    let mut offset = data_offset;
+    for segment in segments {
+        offsets.push(offset);
+        offset += segment.data.len() as u32;
+    }
+    let strtab_offset = offset;
+    // Section headers are word aligned.
+    let shoff = (strtab_offset + strtab.len() as u32 + 3) & !0x3;
+    let shnum = segments.len() as u32 + 2;
+
+    let mut out = Vec::with_capacity(shoff as usize + (shnum * ELF_SECTION_HEADER_SIZE) as usize);
+
+    // ELF header
+    out.extend(&[0x7F, b'E', b'L', b'F', 1, 1, 1, 0]);
+    out.extend(&[0; 8]);
+    out.extend(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    out.extend(&EM_ARM.to_le_bytes());
+    out.extend(&1u32.to_le_bytes()); // e_version
+    out.extend(&0u32.to_le_bytes()); // e_entry
+    out.extend(&ELF_HEADER_SIZE.to_le_bytes()); // e_phoff
+    out.extend(&shoff.to_le_bytes());
+    out.extend(&0u32.to_le_bytes()); // e_flags
+    out.extend(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    out.extend(&(ELF_PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    out.extend(&(phnum as u16).to_le_bytes());
+    out.extend(&(ELF_SECTION_HEADER_SIZE as u16).to_le_bytes());
+    out.extend(&(shnum as u16).to_le_bytes());
+    out.extend(&((shnum - 1) as u16).to_le_bytes()); // e_shstrndx
+
+    // Program headers
+    for (segment, offset) in segments.iter().zip(&offsets) {
+        let size = segment.data.len() as u32;
+        for value in &[
+            PT_LOAD,
+            *offset,
+            segment.address,
+            segment.address,
+            size,
+            size,
+            PF_R | PF_X,
+            1,
+        ] {
+            out.extend(&value.to_le_bytes());
+        }
+    }
+
+    // Section data
+    for segment in segments {
+        out.extend(&segment.data);
+    }
+    out.extend(&strtab);
+    out.resize(shoff as usize, 0);
+
+    // Section headers, starting with the null section.
+    out.extend(&[0; ELF_SECTION_HEADER_SIZE as usize]);
+    for ((segment, offset), name) in segments.iter().zip(&offsets).zip(&name_offsets) {
+        for value in &[
+            *name,
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            segment.address,
+            *offset,
+            segment.data.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ] {
+            out.extend(&value.to_le_bytes());
+        }
+    }
+    for value in &[
+        shstrtab_name,
+        SHT_STRTAB,
+        0,
+        0,
+        strtab_offset,
+        strtab.len() as u32,
+        0,
+        0,
+        1,
+        0,
+    ] {
+        out.extend(&value.to_le_bytes());
+    }
+
+    writer.write_all(&out)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segments() -> Vec<MemorySegment> {
+        vec![
+            MemorySegment {
+                address: 0x0800_0000,
+                data: (0..40).collect(),
+            },
+            MemorySegment {
+                address: 0x0800_FFF8,
+                data: vec![0xAA; 16],
+            },
+        ]
+    }
+
+    #[test]
+    fn bin_fills_gaps() {
+        let segments = vec![
+            MemorySegment {
+                address: 0x100,
+                data: vec![1, 2],
+            },
+            MemorySegment {
+                address: 0x104,
+                data: vec![3],
+            },
+        ];
+
+        let mut out = vec![];
+        write_segments(&mut out, &segments, OutputFormat::Bin).unwrap();
+        assert_eq!(out, vec![1, 2, 0xFF, 0xFF, 3]);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let mut out = vec![];
+        write_segments(&mut out, &segments(), OutputFormat::Hex).unwrap();
+
+        let hex = String::from_utf8(out).unwrap();
+        let mut upper = 0;
+        let mut data = vec![];
+        for record in ihex::reader::Reader::new(&hex) {
+            match record.unwrap() {
+                Record::ExtendedLinearAddress(address) => upper = u32::from(address) << 16,
+                Record::Data { offset, value } => data.push((upper | u32::from(offset), value)),
+                _ => (),
+            }
+        }
+
+        // The second segment is split at the 64 KiB boundary.
+        assert_eq!(data.last().unwrap().0, 0x0801_0000);
+        let bytes: usize = data.iter().map(|(_, v)| v.len()).sum();
+        assert_eq!(bytes, 56);
+    }
+
+    #[test]
+    fn elf_contains_segments() {
+        let mut out = vec![];
+        write_segments(&mut out, &segments(), OutputFormat::Elf).unwrap();
+
+        let elf = goblin::elf::Elf::parse(&out).unwrap();
+        assert_eq!(elf.program_headers.len(), 2);
+        for (ph, segment) in elf.program_headers.iter().zip(segments()) {
+            assert_eq!(ph.p_paddr as u32, segment.address);
+            let range = ph.p_offset as usize..(ph.p_offset + ph.p_filesz) as usize;
+            assert_eq!(&out[range], segment.data.as_slice());
+        }
+        assert_eq!(elf.section_headers.len(), 4);
+        assert_eq!(&elf.shdr_strtab[elf.section_headers[1].sh_name], ".flash0");
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Serializer;
+
+use std::ops::Range;
+use std::time::Duration;
+
+/// Serializes a duration as fractional milliseconds.
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+/// Returns the throughput in bytes per second, or 0 if no time was spent.
+fn throughput(bytes: u64, time: Duration) -> f64 {
+    let seconds = time.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// The statistics of programming one flash region.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RegionReport {
+    /// The address range of the flash region.
+    pub range: Range<u32>,
+    /// The name of the flash algorithm which programmed the region.
+    pub algorithm: String,
+    /// The number of bytes which were erased.
+    pub bytes_erased: u64,
+    /// The number of bytes which were programmed.
+    pub bytes_programmed: u64,
+    /// The number of bytes of sectors which already contained the data and were skipped.
+    pub bytes_skipped: u64,
+    /// The number of bytes which were verified.
+    pub bytes_verified: u64,
+    /// Whether the region was erased with a chip erase instead of single sectors.
+    pub chip_erase: bool,
+    /// Whether the pages were programmed with double buffering.
+    pub double_buffering: bool,
+    /// The number of calls of flash algorithm routines, including init and uninit.
+    pub algorithm_calls: u32,
+    /// The time spent erasing.
+    #[serde(rename = "erase_time_ms", serialize_with = "serialize_millis")]
+    pub erase_time: Duration,
+    /// The time spent programming.
+    #[serde(rename = "program_time_ms", serialize_with = "serialize_millis")]
+    pub program_time: Duration,
+    /// The time spent verifying.
+    #[serde(rename = "verify_time_ms", serialize_with = "serialize_millis")]
+    pub verify_time: Duration,
+}
+
+impl RegionReport {
+    /// Creates an empty report for the region `range`, which is programmed with `algorithm`.
+    pub fn new(range: Range<u32>, algorithm: &str) -> Self {
+        Self {
+            range,
+            algorithm: algorithm.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the erased bytes per second.
+    pub fn erase_throughput(&self) -> f64 {
+        throughput(self.bytes_erased, self.erase_time)
+    }
+
+    /// Returns the programmed bytes per second.
+    pub fn program_throughput(&self) -> f64 {
+        throughput(self.bytes_programmed, self.program_time)
+    }
+
+    /// Returns the verified bytes per second.
+    pub fn verify_throughput(&self) -> f64 {
+        throughput(self.bytes_verified, self.verify_time)
+    }
+}
+
+/// The statistics of a `FlashLoader::commit`, with a breakdown per flash region.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlashReport {
+    /// The reports of all programmed regions, ordered by their address.
+    pub regions: Vec<RegionReport>,
+    /// The time of the entire commit, including the preparation of the data.
+    #[serde(rename = "total_time_ms", serialize_with = "serialize_millis")]
+    pub total_time: Duration,
+}
+
+impl FlashReport {
+    fn sum<T: std::iter::Sum<T>>(&self, f: impl Fn(&RegionReport) -> T) -> T {
+        self.regions.iter().map(f).sum()
+    }
+
+    /// Returns the number of erased bytes of all regions.
+    pub fn bytes_erased(&self) -> u64 {
+        self.sum(|r| r.bytes_erased)
+    }
+
+    /// Returns the number of programmed bytes of all regions.
+    pub fn bytes_programmed(&self) -> u64 {
+        self.sum(|r| r.bytes_programmed)
+    }
+
+    /// Returns the number of skipped bytes of all regions.
+    pub fn bytes_skipped(&self) -> u64 {
+        self.sum(|r| r.bytes_skipped)
+    }
+
+    /// Returns the number of verified bytes of all regions.
+    pub fn bytes_verified(&self) -> u64 {
+        self.sum(|r| r.bytes_verified)
+    }
+
+    /// Returns the number of flash algorithm calls of all regions.
+    pub fn algorithm_calls(&self) -> u32 {
+        self.sum(|r| r.algorithm_calls)
+    }
+
+    /// Returns the time spent erasing all regions.
+    pub fn erase_time(&self) -> Duration {
+        self.sum(|r| r.erase_time)
+    }
+
+    /// Returns the time spent programming all regions.
+    pub fn program_time(&self) -> Duration {
+        self.sum(|r| r.program_time)
+    }
+
+    /// Returns the time spent verifying all regions.
+    pub fn verify_time(&self) -> Duration {
+        self.sum(|r| r.verify_time)
+    }
+
+    /// Returns the erased bytes per second of all regions.
+    pub fn erase_throughput(&self) -> f64 {
+        throughput(self.bytes_erased(), self.erase_time())
+    }
+
+    /// Returns the programmed bytes per second of all regions.
+    pub fn program_throughput(&self) -> f64 {
+        throughput(self.bytes_programmed(), self.program_time())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashReport, RegionReport};
+    use std::time::Duration;
+
+    #[test]
+    fn sums_and_throughput() {
+        let region = |bytes: u64, millis: u64| RegionReport {
+            bytes_programmed: bytes,
+            program_time: Duration::from_millis(millis),
+            algorithm_calls: 3,
+            ..RegionReport::new(0x0..0x1000, "algo")
+        };
+        let report = FlashReport {
+            regions: vec![region(0x400, 250), region(0x400, 750)],
+            total_time: Duration::from_secs(2),
+        };
+
+        assert_eq!(report.bytes_programmed(), 0x800);
+        assert_eq!(report.algorithm_calls(), 6);
+        assert_eq!(report.program_time(), Duration::from_secs(1));
+        assert!((report.program_throughput() - 2048.0).abs() < 1e-9);
+        assert!((report.regions[0].program_throughput() - 4096.0).abs() < 1e-9);
+        // Nothing was erased, so there is no throughput.
+        assert_eq!(report.erase_throughput(), 0.0);
+    }
+}
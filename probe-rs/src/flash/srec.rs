@@ -0,0 +1,138 @@
+//! A parser for Motorola S-record files.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum SRecordError {
+    /// The record is not a valid S-record. Contains the line number.
+    InvalidRecord(usize),
+    /// The checksum of the record does not match. Contains the line number.
+    InvalidChecksum(usize),
+    /// The record type is unknown. Contains the line number and the type.
+    UnknownRecordType(usize, char),
+}
+
+impl Error for SRecordError {}
+
+impl fmt::Display for SRecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SRecordError::*;
+
+        match self {
+            InvalidRecord(line) => write!(f, "Invalid S-record in line {}.", line),
+            InvalidChecksum(line) => {
+                write!(f, "Invalid checksum of the S-record in line {}.", line)
+            }
+            UnknownRecordType(line, record_type) => write!(
+                f,
+                "Unknown S-record type S{} in line {}.",
+                record_type, line
+            ),
+        }
+    }
+}
+
+/// Parses S-records and returns the contained data blocks with their addresses.
+///
+/// Header, count and termination records are checked but don't carry data.
+pub fn parse(input: &str) -> Result<Vec<(u32, Vec<u8>)>, SRecordError> {
+    let mut blocks = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(SRecordError::InvalidRecord(line_number));
+        }
+        let record_type = chars
+            .next()
+            .ok_or(SRecordError::InvalidRecord(line_number))?;
+
+        let address_size = match record_type {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            other => return Err(SRecordError::UnknownRecordType(line_number, other)),
+        };
+
+        let bytes = decode_hex(&line[2..]).ok_or(SRecordError::InvalidRecord(line_number))?;
+
+        // The count covers the address, the data and the checksum.
+        let count = *bytes
+            .first()
+            .ok_or(SRecordError::InvalidRecord(line_number))? as usize;
+        if count != bytes.len() - 1 || count < address_size + 1 {
+            return Err(SRecordError::InvalidRecord(line_number));
+        }
+
+        let sum = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if !sum != bytes[bytes.len() - 1] {
+            return Err(SRecordError::InvalidChecksum(line_number));
+        }
+
+        if let '1' | '2' | '3' = record_type {
+            let address = bytes[1..=address_size]
+                .iter()
+                .fold(0u32, |address, b| (address << 8) | u32::from(*b));
+            let data = bytes[address_size + 1..bytes.len() - 1].to_vec();
+
+            blocks.push((address, data));
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Decodes a string of hex digit pairs. An odd number of digits is invalid.
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    (0..input.len())
+        .step_by(2)
+        .map(|i| {
+            input
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, SRecordError};
+
+    #[test]
+    fn parse_data_records() {
+        let input = "S00F000068656C6C6F202020202000003C\n\
+                     S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+                     S208010000112233444C\n\
+                     S5030002FA\n\
+                     S9030000FC\n";
+
+        let blocks = parse(input).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0, 0x0000);
+        assert_eq!(blocks[0].1.len(), 28);
+        assert_eq!(blocks[1], (0x01_0000, vec![0x11, 0x22, 0x33, 0x44]));
+    }
+
+    #[test]
+    fn report_malformed_records() {
+        assert_eq!(
+            parse("S9030000FC\nS208010000112233444D\n"),
+            Err(SRecordError::InvalidChecksum(2))
+        );
+        assert_eq!(
+            parse("S4030000FC\n"),
+            Err(SRecordError::UnknownRecordType(1, '4'))
+        );
+        assert_eq!(parse("S1050000\n"), Err(SRecordError::InvalidRecord(1)));
+    }
+}
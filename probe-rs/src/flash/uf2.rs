@@ -0,0 +1,198 @@
+//! A parser for UF2 files as specified in https://github.com/microsoft/uf2.
+
+use std::error::Error;
+use std::fmt;
+
+const BLOCK_SIZE: usize = 512;
+const HEADER_SIZE: usize = 32;
+const MAX_PAYLOAD_SIZE: usize = 476;
+
+const MAGIC_START0: u32 = 0x0A32_4655;
+const MAGIC_START1: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+
+/// The block is not meant for the main flash and has to be skipped.
+const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// The file size field contains a family ID.
+const FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+
+#[derive(Debug, PartialEq)]
+pub enum Uf2Error {
+    /// The file size is not a multiple of the block size.
+    Truncated,
+    /// A magic number of the block is wrong. Contains the block index in the file.
+    InvalidMagic(usize),
+    /// The payload of the block is too large. Contains the block index in the file.
+    InvalidPayloadSize(usize),
+    /// The block belongs to an unexpected family.
+    /// Contains the block index in the file and the family ID of the block.
+    UnexpectedFamily(usize, u32),
+    /// The file does not contain any data for the main flash.
+    NoData,
+}
+
+impl Error for Uf2Error {}
+
+impl fmt::Display for Uf2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Uf2Error::*;
+
+        match self {
+            Truncated => write!(
+                f,
+                "The UF2 file size is not a multiple of {} bytes.",
+                BLOCK_SIZE
+            ),
+            InvalidMagic(block) => write!(f, "Invalid magic number in UF2 block {}.", block),
+            InvalidPayloadSize(block) => {
+                write!(f, "Invalid payload size in UF2 block {}.", block)
+            }
+            UnexpectedFamily(block, family_id) => write!(
+                f,
+                "UF2 block {} has the unexpected family ID {:#010x}.",
+                block, family_id
+            ),
+            NoData => write!(f, "The UF2 file does not contain any data for the flash."),
+        }
+    }
+}
+
+/// Checks whether `bytes` start with the magic numbers of an UF2 block.
+pub fn is_uf2(bytes: &[u8]) -> bool {
+    bytes.len() >= 8
+        && bytes[..4] == MAGIC_START0.to_le_bytes()
+        && bytes[4..8] == MAGIC_START1.to_le_bytes()
+}
+
+/// Parses UF2 blocks and returns the contained data with their addresses.
+///
+/// If a `family_id` is given, blocks of other families are skipped.
+/// Otherwise all blocks which carry a family ID have to belong to the same family.
+pub fn parse(input: &[u8], family_id: Option<u32>) -> Result<Vec<(u32, Vec<u8>)>, Uf2Error> {
+    let input_blocks = input.chunks_exact(BLOCK_SIZE);
+    if !input_blocks.remainder().is_empty() {
+        return Err(Uf2Error::Truncated);
+    }
+
+    let mut blocks = vec![];
+    let mut file_family_id = None;
+
+    for (index, block) in input_blocks.enumerate() {
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                block[offset],
+                block[offset + 1],
+                block[offset + 2],
+                block[offset + 3],
+            ])
+        };
+
+        if word(0) != MAGIC_START0 || word(4) != MAGIC_START1 || word(BLOCK_SIZE - 4) != MAGIC_END {
+            return Err(Uf2Error::InvalidMagic(index));
+        }
+
+        let flags = word(8);
+        let target_address = word(12);
+        let payload_size = word(16) as usize;
+
+        if payload_size > MAX_PAYLOAD_SIZE {
+            return Err(Uf2Error::InvalidPayloadSize(index));
+        }
+
+        if flags & FLAG_NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+
+        if flags & FLAG_FAMILY_ID_PRESENT != 0 {
+            let block_family_id = word(28);
+
+            match family_id {
+                // A file may contain images for several families. Only the expected one is flashed.
+                Some(family_id) if family_id != block_family_id => continue,
+                Some(_) => (),
+                None => match file_family_id {
+                    Some(file_family_id) if file_family_id != block_family_id => {
+                        return Err(Uf2Error::UnexpectedFamily(index, block_family_id))
+                    }
+                    _ => file_family_id = Some(block_family_id),
+                },
+            }
+        }
+
+        blocks.push((
+            target_address,
+            block[HEADER_SIZE..HEADER_SIZE + payload_size].to_vec(),
+        ));
+    }
+
+    if blocks.is_empty() {
+        return Err(Uf2Error::NoData);
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block(flags: u32, address: u32, data: &[u8], family_id: u32) -> Vec<u8> {
+        let mut block = vec![0; BLOCK_SIZE];
+        let words = [
+            MAGIC_START0,
+            MAGIC_START1,
+            flags,
+            address,
+            data.len() as u32,
+            0,
+            1,
+            family_id,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            block[i * 4..][..4].copy_from_slice(&word.to_le_bytes());
+        }
+        block[HEADER_SIZE..][..data.len()].copy_from_slice(data);
+        block[BLOCK_SIZE - 4..].copy_from_slice(&MAGIC_END.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn parse_blocks() {
+        let mut input = block(0, 0x2000, &[1, 2, 3, 4], 0);
+        input.extend(block(FLAG_NOT_MAIN_FLASH, 0x1000_0000, &[5, 6], 0));
+        input.extend(block(FLAG_FAMILY_ID_PRESENT, 0x2100, &[7, 8], 0xADA5_2840));
+
+        let blocks = parse(&input, None).unwrap();
+
+        assert_eq!(
+            blocks,
+            vec![(0x2000, vec![1, 2, 3, 4]), (0x2100, vec![7, 8])]
+        );
+    }
+
+    #[test]
+    fn validate_family_ids() {
+        let mut input = block(FLAG_FAMILY_ID_PRESENT, 0x2000, &[1, 2], 0xADA5_2840);
+        input.extend(block(FLAG_FAMILY_ID_PRESENT, 0x2000, &[3, 4], 0x5791_3FBD));
+
+        assert_eq!(
+            parse(&input, None),
+            Err(Uf2Error::UnexpectedFamily(1, 0x5791_3FBD))
+        );
+        assert_eq!(
+            parse(&input, Some(0x5791_3FBD)).unwrap(),
+            vec![(0x2000, vec![3, 4])]
+        );
+        assert_eq!(parse(&input, Some(0x1234_5678)), Err(Uf2Error::NoData));
+    }
+
+    #[test]
+    fn report_malformed_blocks() {
+        let mut input = block(0, 0x2000, &[1, 2], 0);
+        input.extend(block(0, 0x2100, &[3, 4], 0));
+        input[BLOCK_SIZE + 4] = 0;
+
+        assert_eq!(parse(&input, None), Err(Uf2Error::InvalidMagic(1)));
+        assert_eq!(parse(&input[..100], None), Err(Uf2Error::Truncated));
+    }
+}
@@ -0,0 +1,102 @@
+/// Implementation of the DAP_JTAG_Sequence command
+///
+use super::super::{Category, Error, Request, Response, Result, Status};
+
+/// A single JTAG sequence, which clocks up to 64 cycles with a constant TMS value.
+#[derive(Clone, Copy, Debug)]
+pub struct Sequence {
+    /// The number of TCK cycles, between 1 and 64.
+    cycles: u8,
+    /// The TMS value during all cycles.
+    tms: bool,
+    /// Capture TDO during the sequence.
+    tdo_capture: bool,
+    /// The TDI data, LSB first.
+    tdi: u64,
+}
+
+impl Sequence {
+    pub(crate) fn new(cycles: u8, tms: bool, tdo_capture: bool, tdi: u64) -> Result<Self> {
+        if cycles == 0 || cycles > 64 {
+            return Err(Error::TooMuchData);
+        }
+
+        Ok(Sequence {
+            cycles,
+            tms,
+            tdo_capture,
+            tdi,
+        })
+    }
+
+    /// The number of bytes needed to hold the TDI or TDO data of this sequence.
+    pub(crate) fn byte_count(&self) -> usize {
+        (usize::from(self.cycles) + 7) >> 3
+    }
+
+    pub(crate) fn tdo_capture(&self) -> bool {
+        self.tdo_capture
+    }
+}
+
+pub struct SequenceRequest {
+    sequences: Vec<Sequence>,
+}
+
+impl SequenceRequest {
+    pub(crate) fn new(sequences: Vec<Sequence>) -> Result<SequenceRequest> {
+        if sequences.is_empty() || sequences.len() > 255 {
+            return Err(Error::TooMuchData);
+        }
+
+        Ok(SequenceRequest { sequences })
+    }
+}
+
+impl Request for SequenceRequest {
+    const CATEGORY: Category = Category(0x14);
+
+    fn to_bytes(&self, buffer: &mut [u8], offset: usize) -> Result<usize> {
+        buffer[offset] = self.sequences.len() as u8;
+
+        let mut position = offset + 1;
+
+        for sequence in &self.sequences {
+            let byte_count = sequence.byte_count();
+
+            if position + 1 + byte_count > buffer.len() {
+                return Err(Error::NotEnoughSpace);
+            }
+
+            // A cycle count of zero means that 64 cycles are clocked.
+            let mut info = sequence.cycles & 0x3F;
+            if sequence.tms {
+                info |= 1 << 6;
+            }
+            if sequence.tdo_capture {
+                info |= 1 << 7;
+            }
+
+            buffer[position] = info;
+            buffer[position + 1..position + 1 + byte_count]
+                .copy_from_slice(&sequence.tdi.to_le_bytes()[..byte_count]);
+
+            position += 1 + byte_count;
+        }
+
+        Ok(position - offset)
+    }
+}
+
+/// The status of the command and the captured TDO data of all sequences,
+/// concatenated in the order of the sequences.
+pub struct SequenceResponse(pub(crate) Status, pub(crate) Vec<u8>);
+
+impl Response for SequenceResponse {
+    fn from_bytes(buffer: &[u8], offset: usize) -> Result<Self> {
+        Ok(SequenceResponse(
+            Status::from_byte(buffer[offset])?,
+            buffer[offset + 1..].to_vec(),
+        ))
+    }
+}
@@ -6,7 +6,9 @@ use crate::{
         debug_port::DPRegister,
         dp_access::{DPAccess, DebugPort},
     },
-    probe::{DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, Port, WireProtocol},
+    probe::{
+        DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, JTAGAccess, Port, WireProtocol,
+    },
 };
 
 use log::{debug, error, info};
@@ -18,6 +20,10 @@ use commands::{
         info::{Command, PacketCount, PacketSize},
         reset::{ResetRequest, ResetResponse},
     },
+    jtag::sequence::{
+        Sequence as JtagSequence, SequenceRequest as JtagSequenceRequest,
+        SequenceResponse as JtagSequenceResponse,
+    },
     swd,
     swj::{
         clock::{SWJClockRequest, SWJClockResponse},
@@ -107,6 +113,111 @@ impl DAPLink {
             })?;
         Ok(())
     }
+
+    /// Clocks a list of JTAG sequences and returns the captured TDO data.
+    fn send_jtag_sequences(
+        &self,
+        sequences: Vec<JtagSequence>,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        use commands::Error;
+
+        let capture_len = sequences
+            .iter()
+            .filter(|s| s.tdo_capture())
+            .map(|s| s.byte_count())
+            .sum();
+
+        let request = JtagSequenceRequest::new(sequences)?;
+
+        let data = commands::send_command::<JtagSequenceRequest, JtagSequenceResponse>(
+            &self.device,
+            request,
+        )
+        .and_then(|v| match v {
+            JtagSequenceResponse(Status::DAPOk, data) => Ok(data),
+            JtagSequenceResponse(Status::DAPError, _) => Err(Error::DAP),
+        })?;
+
+        Ok(data[..capture_len].to_vec())
+    }
+
+    /// Moves the TAP into Test-Logic-Reset, and from there into Run-Test/Idle.
+    fn jtag_reset(&self) -> Result<(), DebugProbeError> {
+        self.send_jtag_sequences(vec![
+            JtagSequence::new(6, true, false, 0)?,
+            JtagSequence::new(1, false, false, 0)?,
+        ])?;
+        Ok(())
+    }
+}
+
+impl JTAGAccess for DAPLink {
+    fn write_ir(&mut self, value: u32, len: u8) -> Result<(), DebugProbeError> {
+        if len == 0 || len > 32 {
+            return Err(commands::Error::TooMuchData.into());
+        }
+
+        let value = u64::from(value);
+        let mut sequences = vec![
+            // Run-Test/Idle -> Select-DR-Scan -> Select-IR-Scan
+            JtagSequence::new(2, true, false, 0)?,
+            // -> Capture-IR -> Shift-IR
+            JtagSequence::new(2, false, false, 0)?,
+        ];
+        if len > 1 {
+            sequences.push(JtagSequence::new(len - 1, false, false, value)?);
+        }
+        sequences.extend_from_slice(&[
+            // The last bit is shifted when leaving to Exit1-IR.
+            JtagSequence::new(1, true, false, value >> (len - 1))?,
+            // -> Update-IR -> Run-Test/Idle
+            JtagSequence::new(1, true, false, 0)?,
+            JtagSequence::new(1, false, false, 0)?,
+        ]);
+
+        self.send_jtag_sequences(sequences)?;
+
+        Ok(())
+    }
+
+    fn write_dr(&mut self, value: u64, len: u8, idle_cycles: u8) -> Result<u64, DebugProbeError> {
+        if len == 0 || len > 64 || idle_cycles > 63 {
+            return Err(commands::Error::TooMuchData.into());
+        }
+
+        let mut sequences = vec![
+            // Run-Test/Idle -> Select-DR-Scan
+            JtagSequence::new(1, true, false, 0)?,
+            // -> Capture-DR -> Shift-DR
+            JtagSequence::new(2, false, false, 0)?,
+        ];
+        if len > 1 {
+            sequences.push(JtagSequence::new(len - 1, false, true, value)?);
+        }
+        sequences.extend_from_slice(&[
+            // The last bit is shifted when leaving to Exit1-DR.
+            JtagSequence::new(1, true, true, value >> (len - 1))?,
+            // -> Update-DR -> Run-Test/Idle
+            JtagSequence::new(1, true, false, 0)?,
+            JtagSequence::new(idle_cycles + 1, false, false, 0)?,
+        ]);
+
+        let data = self.send_jtag_sequences(sequences)?;
+
+        // Reassemble the captured bits. The last bit was captured in its own sequence.
+        let mut buffer = [0u8; 8];
+        let head_bytes = data.len() - 1;
+        buffer[..head_bytes].copy_from_slice(&data[..head_bytes]);
+
+        let mut result = if len > 1 {
+            u64::from_le_bytes(buffer) & (!0u64 >> (64 - (u32::from(len) - 1)))
+        } else {
+            0
+        };
+        result |= u64::from(data[head_bytes] & 1) << (len - 1);
+
+        Ok(result)
+    }
 }
 
 impl<P: DebugPort, R: DPRegister<P>> DPAccess<P, R> for DAPLink {
@@ -188,6 +299,15 @@ impl DebugProbe for DAPLink {
 
         self.set_swj_clock(clock)?;
 
+        // In JTAG mode, the probe only provides raw access to the JTAG chain.
+        if result == WireProtocol::Jtag {
+            self.jtag_reset()?;
+
+            info!("Succesfully attached to system in JTAG mode");
+
+            return Ok(result);
+        }
+
         self.transfer_configure(ConfigureRequest {
             idle_cycles: 0,
             wait_retry: 80,
@@ -276,6 +396,10 @@ impl DebugProbe for DAPLink {
         })?;
         Ok(())
     }
+
+    fn get_jtag_access(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self)
+    }
 }
 
 impl DAPAccess for DAPLink {
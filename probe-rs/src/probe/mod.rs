@@ -1,40 +1,39 @@
 pub mod daplink;
 pub mod stlink;
 
+use crate::cores::riscv::{dtm::DtmState, Riscv, RiscvError};
 use crate::coresight::{
-    access_ports::{
-        custom_ap::{CtrlAP, ERASEALL, ERASEALLSTATUS, RESET},
-        generic_ap::{APClass, APType, GenericAP, IDR},
-        memory_ap::MemoryAP,
-        APRegister, AccessPortError,
-    },
-    ap_access::{get_ap_by_idr, APAccess, AccessPort},
+    access_ports::{generic_ap::GenericAP, memory_ap::MemoryAP, APRegister, AccessPortError},
+    ap_access::{APAccess, AccessPort},
     common::Register,
     memory::{adi_v5_memory_interface::ADIMemoryInterface, MI},
 };
 
 use log::debug;
 
-use colored::*;
 use std::error::Error;
 use std::fmt;
-use std::time::Instant;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum WireProtocol {
     Swd,
     Jtag,
 }
 
-const UNLOCK_TIMEOUT: u64 = 15;
-const CTRL_AP_IDR: IDR = IDR {
-    REVISION: 0,
-    DESIGNER: 0x0144,
-    CLASS: APClass::Undefined,
-    _RES0: 0,
-    VARIANT: 0,
-    TYPE: APType::JTAG_COM_AP,
-};
+impl std::str::FromStr for WireProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "swd" => Ok(WireProtocol::Swd),
+            "jtag" => Ok(WireProtocol::Jtag),
+            _ => Err(format!(
+                "'{}' is not a valid protocol. Choose from [swd, jtag].",
+                s
+            )),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum DebugProbeError {
@@ -56,13 +55,17 @@ pub enum DebugProbeError {
     ProbeCouldNotBeCreated,
     TargetPowerUpFailed,
     Timeout,
+    /// The access requires secure debug, which is not enabled on the target.
+    SecureDebugDisabled,
     AccessPortError(AccessPortError),
+    RiscvError(RiscvError),
 }
 
 impl Error for DebugProbeError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             DebugProbeError::AccessPortError(ref e) => Some(e),
+            DebugProbeError::RiscvError(ref e) => Some(e),
             _ => None,
         }
     }
@@ -133,14 +136,58 @@ pub struct MasterProbe {
     actual_probe: Box<dyn DebugProbe>,
     current_apsel: u8,
     current_apbanksel: u8,
+    /// The memory AP used for memory accesses through the `MI` implementation.
+    memory_ap: u8,
+    /// The base address of the System Control Space of the selected core.
+    scs_base: u32,
+    /// Memory accesses go through the RISC-V Debug Module instead of a memory AP.
+    debug_module_memory: bool,
+    /// The configuration of the RISC-V DTM, once it was read in this session.
+    pub(crate) riscv_dtm: Option<DtmState>,
 }
 
+/// The architectural base address of the System Control Space.
+const SCS_BASE: u32 = 0xE000_E000;
+const SCS_SIZE: u32 = 0x1000;
+
 impl MasterProbe {
     pub fn from_specific_probe(probe: Box<dyn DebugProbe>) -> Self {
         MasterProbe {
             actual_probe: probe,
             current_apbanksel: 0,
             current_apsel: 0,
+            memory_ap: 0,
+            scs_base: SCS_BASE,
+            debug_module_memory: false,
+            riscv_dtm: None,
+        }
+    }
+
+    /// Selects the memory AP and the System Control Space of the core which is debugged.
+    ///
+    /// All following memory accesses go through the given AP, and accesses to the
+    /// System Control Space at `0xE000_E000` are redirected to `scs_base`.
+    pub fn select_core_port(&mut self, ap: u8, scs_base: u32) {
+        debug!("Selecting core on AP {} with SCS at 0x{:08x}", ap, scs_base);
+        self.memory_ap = ap;
+        self.scs_base = scs_base;
+        self.debug_module_memory = false;
+    }
+
+    /// Routes all following memory accesses through the Debug Module of a RISC-V core.
+    ///
+    /// The hart has to be halted for memory accesses.
+    pub fn select_debug_module_memory(&mut self) {
+        debug!("Selecting memory access through the RISC-V debug module");
+        self.debug_module_memory = true;
+    }
+
+    /// Redirects accesses to the System Control Space to the one of the selected core.
+    fn translate_address(&self, address: u32) -> u32 {
+        if address >= SCS_BASE && address - SCS_BASE < SCS_SIZE {
+            self.scs_base + (address - SCS_BASE)
+        } else {
+            address
         }
     }
 
@@ -184,7 +231,7 @@ impl MasterProbe {
         Ok(())
     }
 
-    fn write_ap_register<AP, REGISTER>(
+    pub(crate) fn write_ap_register<AP, REGISTER>(
         &mut self,
         port: AP,
         register: REGISTER,
@@ -239,7 +286,7 @@ impl MasterProbe {
         Ok(())
     }
 
-    fn read_ap_register<AP, REGISTER>(
+    pub(crate) fn read_ap_register<AP, REGISTER>(
         &mut self,
         port: AP,
         _register: REGISTER,
@@ -294,6 +341,13 @@ impl MasterProbe {
         Ok(())
     }
 
+    /// Returns the raw JTAG access of the probe, if the probe supports it.
+    pub fn jtag_access(&mut self) -> Result<&mut dyn JTAGAccess, DebugProbeError> {
+        self.actual_probe
+            .get_jtag_access()
+            .ok_or(DebugProbeError::JTAGNotSupportedOnProbe)
+    }
+
     pub fn read_register_dp(&mut self, offset: u16) -> Result<u32, DebugProbeError> {
         self.actual_probe.read_register(Port::DebugPort, offset)
     }
@@ -302,59 +356,6 @@ impl MasterProbe {
         self.actual_probe
             .write_register(Port::DebugPort, offset, val)
     }
-
-    /// Tries to mass erase a locked nRF52 chip, this process may timeout, if it does, the chip
-    /// might be unlocked or not, it is advised to try again if flashing fails
-    pub fn nrf_recover(&mut self) -> Result<(), DebugProbeError> {
-        let ctrl_port = match get_ap_by_idr(self, |idr| idr == CTRL_AP_IDR) {
-            Some(port) => CtrlAP::from(port),
-            None => {
-                return Err(DebugProbeError::AccessPortError(
-                    AccessPortError::CtrlAPNotFound,
-                ));
-            }
-        };
-        log::info!("Starting mass erase...");
-        let mut erase_reg = ERASEALL::from(1);
-        let status_reg = ERASEALLSTATUS::from(0);
-        let mut reset_reg = RESET::from(1);
-
-        // Reset first
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        reset_reg.RESET = false;
-        self.write_ap_register(ctrl_port, reset_reg)?;
-
-        self.write_ap_register(ctrl_port, erase_reg)?;
-
-        // Prepare timeout
-        let now = Instant::now();
-        let status = self.read_ap_register(ctrl_port, status_reg)?;
-        log::info!("Erase status: {:?}", status.ERASEALLSTATUS);
-        let timeout = loop {
-            let status = self.read_ap_register(ctrl_port, status_reg)?;
-            if !status.ERASEALLSTATUS {
-                break false;
-            }
-            if now.elapsed().as_secs() >= UNLOCK_TIMEOUT {
-                break true;
-            }
-        };
-        reset_reg.RESET = true;
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        reset_reg.RESET = false;
-        self.write_ap_register(ctrl_port, reset_reg)?;
-        erase_reg.ERASEALL = false;
-        self.write_ap_register(ctrl_port, erase_reg)?;
-        if timeout {
-            log::error!(
-                "    {} Mass erase process timeout, the chip might still be locked.",
-                "Error".red().bold()
-            );
-        } else {
-            log::info!("Mass erase completed, chip unlocked");
-        }
-        Ok(())
-    }
 }
 
 impl<REGISTER> APAccess<MemoryAP, REGISTER> for MasterProbe
@@ -437,35 +438,79 @@ where
 
 impl MI for MasterProbe {
     fn read32(&mut self, address: u32) -> Result<u32, AccessPortError> {
-        ADIMemoryInterface::new(0).read32(self, address)
+        if self.debug_module_memory {
+            let mut data = [0u32];
+            self.read_block32(address, &mut data)?;
+            return Ok(data[0]);
+        }
+        let address = self.translate_address(address);
+        ADIMemoryInterface::new(self.memory_ap).read32(self, address)
     }
 
     fn read8(&mut self, address: u32) -> Result<u8, AccessPortError> {
-        ADIMemoryInterface::new(0).read8(self, address)
+        if self.debug_module_memory {
+            let mut data = [0u8];
+            self.read_block8(address, &mut data)?;
+            return Ok(data[0]);
+        }
+        let address = self.translate_address(address);
+        ADIMemoryInterface::new(self.memory_ap).read8(self, address)
     }
 
     fn read_block32(&mut self, address: u32, data: &mut [u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block32(self, address, data)
+        if self.debug_module_memory {
+            return Ok(Riscv::with_debug_module(self, |dm| {
+                dm.read_memory(address, data)
+            })?);
+        }
+        let address = self.translate_address(address);
+        ADIMemoryInterface::new(self.memory_ap).read_block32(self, address, data)
     }
 
     fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).read_block8(self, address, data)
+        if self.debug_module_memory {
+            return Ok(Riscv::with_debug_module(self, |dm| {
+                dm.read_memory8(address, data)
+            })?);
+        }
+        let address = self.translate_address(address);
+        ADIMemoryInterface::new(self.memory_ap).read_block8(self, address, data)
     }
 
     fn write32(&mut self, addr: u32, data: u32) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write32(self, addr, data)
+        if self.debug_module_memory {
+            return self.write_block32(addr, &[data]);
+        }
+        let addr = self.translate_address(addr);
+        ADIMemoryInterface::new(self.memory_ap).write32(self, addr, data)
     }
 
     fn write8(&mut self, addr: u32, data: u8) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write8(self, addr, data)
+        if self.debug_module_memory {
+            return self.write_block8(addr, &[data]);
+        }
+        let addr = self.translate_address(addr);
+        ADIMemoryInterface::new(self.memory_ap).write8(self, addr, data)
     }
 
     fn write_block32(&mut self, addr: u32, data: &[u32]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block32(self, addr, data)
+        if self.debug_module_memory {
+            return Ok(Riscv::with_debug_module(self, |dm| {
+                dm.write_memory(addr, data)
+            })?);
+        }
+        let addr = self.translate_address(addr);
+        ADIMemoryInterface::new(self.memory_ap).write_block32(self, addr, data)
     }
 
     fn write_block8(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessPortError> {
-        ADIMemoryInterface::new(0).write_block8(self, addr, data)
+        if self.debug_module_memory {
+            return Ok(Riscv::with_debug_module(self, |dm| {
+                dm.write_memory8(addr, data)
+            })?);
+        }
+        let addr = self.translate_address(addr);
+        ADIMemoryInterface::new(self.memory_ap).write_block8(self, addr, data)
     }
 }
 
@@ -485,6 +530,24 @@ pub trait DebugProbe: DAPAccess {
 
     /// Resets the target device.
     fn target_reset(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Returns raw access to the JTAG chain, if the probe supports it.
+    fn get_jtag_access(&mut self) -> Option<&mut dyn JTAGAccess> {
+        None
+    }
+}
+
+/// Raw access to a JTAG chain with a single TAP.
+///
+/// All operations start and end in the Run-Test/Idle state of the TAP.
+pub trait JTAGAccess {
+    /// Shifts the lowest `len` bits of `value` into the instruction register.
+    fn write_ir(&mut self, value: u32, len: u8) -> Result<(), DebugProbeError>;
+
+    /// Shifts the lowest `len` bits of `value` into the selected data register,
+    /// and returns the bits captured from TDO. Afterwards, the TAP stays in
+    /// Run-Test/Idle for `idle_cycles` clock cycles.
+    fn write_dr(&mut self, value: u64, len: u8, idle_cycles: u8) -> Result<u64, DebugProbeError>;
 }
 
 #[derive(Debug, Clone)]
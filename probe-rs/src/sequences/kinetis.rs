@@ -0,0 +1,114 @@
+//! The sequence of NXP Kinetis chips, which are unlocked through the MDM-AP.
+
+use super::{report_erase, DebugSequence, SequenceError};
+use crate::coresight::access_ports::custom_ap::{MdmAP, MDMCONTROL, MDMSTATUS};
+use crate::flash::FlashProgress;
+use crate::probe::MasterProbe;
+use crate::session::Session;
+
+use std::time::{Duration, Instant};
+
+/// The MDM-AP is always the second access port of a Kinetis chip.
+const MDM_AP_PORT: u8 = 1;
+const MASS_ERASE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+pub struct Kinetis;
+
+impl Kinetis {
+    /// Polls the MDM-AP until `done` returns true or the mass erase timeout elapsed.
+    fn wait<F>(probe: &mut MasterProbe, mut done: F) -> Result<(), SequenceError>
+    where
+        F: FnMut(&mut MasterProbe) -> Result<bool, SequenceError>,
+    {
+        let now = Instant::now();
+        while !done(probe)? {
+            if now.elapsed() >= MASS_ERASE_TIMEOUT {
+                return Err(SequenceError::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Erases the chip through the MDM-AP.
+    fn erase_all(probe: &mut MasterProbe) -> Result<(), SequenceError> {
+        let mdm_port = MdmAP::new(MDM_AP_PORT);
+
+        Self::wait(probe, |probe| {
+            Ok(probe
+                .read_ap_register(mdm_port, MDMSTATUS::default())?
+                .FLASH_READY)
+        })?;
+        let status = probe.read_ap_register(mdm_port, MDMSTATUS::default())?;
+        if !status.MASS_ERASE_ENABLE {
+            return Err(SequenceError::MassEraseDisabled);
+        }
+
+        log::info!("Starting mass erase...");
+        probe.write_ap_register(
+            mdm_port,
+            MDMCONTROL {
+                MASS_ERASE_IN_PROGRESS: true,
+                SYSTEM_RESET_REQUEST: true,
+            },
+        )?;
+
+        // The erase has started once it is acknowledged, and is done once the flag is cleared.
+        Self::wait(probe, |probe| {
+            Ok(probe
+                .read_ap_register(mdm_port, MDMSTATUS::default())?
+                .MASS_ERASE_ACK)
+        })?;
+        Self::wait(probe, |probe| {
+            Ok(!probe
+                .read_ap_register(mdm_port, MDMCONTROL::default())?
+                .MASS_ERASE_IN_PROGRESS)
+        })?;
+
+        probe.write_ap_register(mdm_port, MDMCONTROL::default())?;
+        log::info!("Mass erase completed, chip unlocked");
+
+        Ok(())
+    }
+}
+
+impl DebugSequence for Kinetis {
+    fn name(&self) -> &'static str {
+        "kinetis"
+    }
+
+    /// A secured Kinetis chip can only be unlocked by a mass erase.
+    fn unlock(&self, session: &mut Session) -> Result<(), SequenceError> {
+        let mdm_port = MdmAP::new(MDM_AP_PORT);
+        let status = session
+            .probe
+            .read_ap_register(mdm_port, MDMSTATUS::default())?;
+        if !status.SYSTEM_SECURITY {
+            log::info!("The chip is not secured.");
+            return Ok(());
+        }
+
+        self.mass_erase(session, &FlashProgress::new(|_| {}))
+    }
+
+    /// Erases the flash through the MDM-AP while the chip is held in reset.
+    fn mass_erase(
+        &self,
+        session: &mut Session,
+        progress: &FlashProgress,
+    ) -> Result<(), SequenceError> {
+        report_erase(progress, || Self::erase_all(&mut session.probe))
+    }
+
+    fn post_attach(&self, session: &mut Session) -> Result<(), SequenceError> {
+        let status = session
+            .probe
+            .read_ap_register(MdmAP::new(MDM_AP_PORT), MDMSTATUS::default())?;
+        if status.SYSTEM_SECURITY {
+            log::warn!("The chip is secured, it has to be unlocked with a mass erase first.");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Vendor specific sequences to unlock, mass erase, reset and attach to a chip.
+//!
+//! The sequence of a target is selected by the `debug_sequence` key of its chip family.
+
+pub mod kinetis;
+pub mod nrf;
+pub mod stm32;
+
+use crate::coresight::access_ports::AccessPortError;
+use crate::flash::{
+    config::ConfigError,
+    erase::{erase_all, EraseError},
+    FlashProgress,
+};
+use crate::probe::DebugProbeError;
+use crate::session::Session;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SequenceError {
+    /// The sequence of the target does not support the operation.
+    NotSupported {
+        sequence: &'static str,
+        operation: &'static str,
+    },
+    /// The mass erase did not finish in time. The chip might still be locked.
+    Timeout,
+    /// The chip does not permit a mass erase through the debug port.
+    MassEraseDisabled,
+    /// The chip is locked permanently and can not be unlocked anymore.
+    PermanentlyLocked,
+    DebugProbe(DebugProbeError),
+    Config(ConfigError),
+    Erase(EraseError),
+}
+
+impl Error for SequenceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use SequenceError::*;
+
+        match self {
+            DebugProbe(ref e) => Some(e),
+            Config(ref e) => Some(e),
+            Erase(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SequenceError::*;
+
+        match self {
+            NotSupported {
+                sequence,
+                operation,
+            } => write!(
+                f,
+                "The {} debug sequence does not support to {} the chip.",
+                sequence, operation
+            ),
+            Timeout => write!(
+                f,
+                "The mass erase did not finish in time, the chip might still be locked."
+            ),
+            MassEraseDisabled => write!(f, "The chip does not permit a mass erase."),
+            PermanentlyLocked => write!(f, "The chip is locked permanently."),
+            DebugProbe(ref e) => e.fmt(f),
+            Config(ref e) => e.fmt(f),
+            Erase(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<DebugProbeError> for SequenceError {
+    fn from(error: DebugProbeError) -> Self {
+        SequenceError::DebugProbe(error)
+    }
+}
+
+impl From<AccessPortError> for SequenceError {
+    fn from(error: AccessPortError) -> Self {
+        SequenceError::DebugProbe(error.into())
+    }
+}
+
+impl From<ConfigError> for SequenceError {
+    fn from(error: ConfigError) -> Self {
+        SequenceError::Config(error)
+    }
+}
+
+impl From<EraseError> for SequenceError {
+    fn from(error: EraseError) -> Self {
+        SequenceError::Erase(error)
+    }
+}
+
+/// The vendor specific steps which are needed to work with a chip.
+///
+/// All steps default to the generic behaviour, so a sequence only has to implement
+/// the steps which differ for its chips.
+pub trait DebugSequence: std::fmt::Debug + dyn_clone::DynClone {
+    /// The name of the sequence as used in the target description.
+    fn name(&self) -> &'static str;
+
+    /// Unlocks a chip whose debug access is locked. This usually erases the entire chip.
+    fn unlock(&self, _session: &mut Session) -> Result<(), SequenceError> {
+        Err(SequenceError::NotSupported {
+            sequence: self.name(),
+            operation: "unlock",
+        })
+    }
+
+    /// Erases the entire chip and reports the progress to `progress`.
+    ///
+    /// By default, the flash is erased with the flash algorithms of the target.
+    fn mass_erase(
+        &self,
+        session: &mut Session,
+        progress: &FlashProgress,
+    ) -> Result<(), SequenceError> {
+        erase_all(session, progress)?;
+
+        Ok(())
+    }
+
+    /// Runs before the target is reset.
+    fn pre_reset(&self, _session: &mut Session) -> Result<(), SequenceError> {
+        Ok(())
+    }
+
+    /// Runs once after the probe is attached to the target.
+    fn post_attach(&self, _session: &mut Session) -> Result<(), SequenceError> {
+        Ok(())
+    }
+}
+
+dyn_clone::clone_trait_object!(DebugSequence);
+
+/// Reports the start and the end of an erase which does not use the flash algorithms,
+/// and thus can't report single sectors.
+pub(crate) fn report_erase<F>(progress: &FlashProgress, erase: F) -> Result<(), SequenceError>
+where
+    F: FnOnce() -> Result<(), SequenceError>,
+{
+    progress.started_erasing();
+    let result = erase();
+    if result.is_ok() {
+        progress.finished_erasing();
+    } else {
+        progress.failed_erasing();
+    }
+    result
+}
+
+/// The sequence of chips which don't need any vendor specific steps.
+#[derive(Debug, Clone)]
+pub struct Generic;
+
+impl DebugSequence for Generic {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+}
+
+pub fn get_sequence(name: impl AsRef<str>) -> Option<Box<dyn DebugSequence>> {
+    let map: HashMap<&'static str, Box<dyn DebugSequence>> = hashmap! {
+        "generic" => Box::new(Generic) as _,
+        "nrf52" => Box::new(self::nrf::Nrf52) as _,
+        "stm32" => Box::new(self::stm32::Stm32) as _,
+        "kinetis" => Box::new(self::kinetis::Kinetis) as _,
+    };
+
+    map.get(&name.as_ref().to_ascii_lowercase()[..]).cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_sequence, report_erase, SequenceError};
+    use crate::config::{
+        registry::{Registry, SelectionStrategy},
+        validation::SEQUENCE_NAMES,
+    };
+    use crate::flash::{FlashProgress, ProgressEvent};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sequence_of_builtin_targets() {
+        assert_eq!(get_sequence("nRF52").unwrap().name(), "nrf52");
+        assert!(get_sequence("unknown").is_none());
+        for &name in SEQUENCE_NAMES {
+            assert_eq!(get_sequence(name).unwrap().name(), name);
+        }
+
+        let registry = Registry::from_builtin_families();
+        let target = |name: &str| {
+            registry
+                .get_target(SelectionStrategy::TargetIdentifier(name.into()))
+                .unwrap()
+        };
+
+        assert_eq!(target("nRF52832_xxAA").debug_sequence.name(), "nrf52");
+        assert_eq!(target("STM32F415ZGTx").debug_sequence.name(), "stm32");
+        assert_eq!(target("LPC55S69JET98").debug_sequence.name(), "generic");
+    }
+
+    #[test]
+    fn report_vendor_erase() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = events.clone();
+        let progress = FlashProgress::new(move |event| recorded.borrow_mut().push(event));
+
+        report_erase(&progress, || Ok(())).unwrap();
+        assert!(report_erase(&progress, || Err(SequenceError::Timeout)).is_err());
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                ProgressEvent::StartedErasing,
+                ProgressEvent::FinishedErasing,
+                ProgressEvent::StartedErasing,
+                ProgressEvent::FailedErasing,
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! The sequence of the nRF52 series, which is unlocked through Nordic's CTRL-AP.
+
+use super::{report_erase, DebugSequence, SequenceError};
+use crate::coresight::{
+    access_ports::{
+        custom_ap::{CtrlAP, APPROTECTSTATUS, ERASEALL, ERASEALLSTATUS, RESET},
+        generic_ap::{APClass, APType, IDR},
+        AccessPortError,
+    },
+    ap_access::get_ap_by_idr,
+};
+use crate::flash::FlashProgress;
+use crate::probe::{DebugProbeError, MasterProbe};
+use crate::session::Session;
+
+use std::time::{Duration, Instant};
+
+const UNLOCK_TIMEOUT: Duration = Duration::from_secs(15);
+const CTRL_AP_IDR: IDR = IDR {
+    REVISION: 0,
+    DESIGNER: 0x0144,
+    CLASS: APClass::Undefined,
+    _RES0: 0,
+    VARIANT: 0,
+    TYPE: APType::JTAG_COM_AP,
+};
+
+#[derive(Debug, Clone)]
+pub struct Nrf52;
+
+impl Nrf52 {
+    fn ctrl_ap(probe: &mut MasterProbe) -> Result<CtrlAP, DebugProbeError> {
+        get_ap_by_idr(probe, |idr| idr == CTRL_AP_IDR)
+            .map(CtrlAP::from)
+            .ok_or(DebugProbeError::AccessPortError(
+                AccessPortError::CtrlAPNotFound,
+            ))
+    }
+
+    /// Pulses the soft reset of the CTRL-AP.
+    fn reset(probe: &mut MasterProbe, ctrl_port: CtrlAP) -> Result<(), DebugProbeError> {
+        probe.write_ap_register(ctrl_port, RESET { RESET: true })?;
+        probe.write_ap_register(ctrl_port, RESET { RESET: false })
+    }
+
+    /// Erases the chip through the CTRL-AP.
+    fn erase_all(probe: &mut MasterProbe) -> Result<(), SequenceError> {
+        let ctrl_port = Self::ctrl_ap(probe)?;
+
+        log::info!("Starting mass erase...");
+        Self::reset(probe, ctrl_port)?;
+        probe.write_ap_register(ctrl_port, ERASEALL { ERASEALL: true })?;
+
+        let now = Instant::now();
+        let finished = loop {
+            let status = probe.read_ap_register(ctrl_port, ERASEALLSTATUS::default())?;
+            if !status.ERASEALLSTATUS {
+                break true;
+            }
+            if now.elapsed() >= UNLOCK_TIMEOUT {
+                break false;
+            }
+        };
+
+        Self::reset(probe, ctrl_port)?;
+        probe.write_ap_register(ctrl_port, ERASEALL { ERASEALL: false })?;
+
+        if !finished {
+            return Err(SequenceError::Timeout);
+        }
+        log::info!("Mass erase completed, chip unlocked");
+
+        Ok(())
+    }
+}
+
+impl DebugSequence for Nrf52 {
+    fn name(&self) -> &'static str {
+        "nrf52"
+    }
+
+    /// A locked nRF52 chip can only be unlocked by a mass erase.
+    fn unlock(&self, session: &mut Session) -> Result<(), SequenceError> {
+        self.mass_erase(session, &FlashProgress::new(|_| {}))
+    }
+
+    /// Erases the flash, the RAM and the UICR through the CTRL-AP,
+    /// which also works if the chip is locked.
+    fn mass_erase(
+        &self,
+        session: &mut Session,
+        progress: &FlashProgress,
+    ) -> Result<(), SequenceError> {
+        report_erase(progress, || Self::erase_all(&mut session.probe))
+    }
+
+    /// Warns if the chip is locked.
+    ///
+    /// Some probes, like the ST-Link, can't reach the CTRL-AP. As the check only gives advice,
+    /// attaching succeeds without it.
+    fn post_attach(&self, session: &mut Session) -> Result<(), SequenceError> {
+        let probe = &mut session.probe;
+        let status = Self::ctrl_ap(probe)
+            .and_then(|ctrl_port| probe.read_ap_register(ctrl_port, APPROTECTSTATUS::default()));
+
+        match status {
+            Ok(status) if !status.APPROTECTSTATUS => {
+                log::warn!("The chip is locked, it has to be unlocked with a mass erase first.")
+            }
+            Ok(_) => (),
+            Err(e) => log::debug!(
+                "Could not read the APPROTECT status from the CTRL-AP: {}",
+                e
+            ),
+        }
+
+        Ok(())
+    }
+}
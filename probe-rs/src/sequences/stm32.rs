@@ -0,0 +1,57 @@
+//! The sequence of STM32 chips, which are unlocked by a regression of their readout protection.
+
+use super::{DebugSequence, SequenceError};
+use crate::coresight::memory::MI;
+use crate::flash::config::{read_field, write_fields};
+use crate::session::Session;
+
+/// The name of the config field which holds the readout protection level.
+const RDP_FIELD: &str = "RDP";
+/// Level 0, the chip is not protected.
+const RDP_LEVEL_0: u32 = 0xAA;
+/// Level 2, the debug access is disabled permanently.
+const RDP_LEVEL_2: u32 = 0xCC;
+
+/// The debug configuration register of the DBGMCU.
+const DBGMCU_CR: u32 = 0xE004_2004;
+/// Keeps the debug access alive in sleep, stop and standby mode.
+const DBGMCU_CR_LOW_POWER: u32 = 0x7;
+
+#[derive(Debug, Clone)]
+pub struct Stm32;
+
+impl DebugSequence for Stm32 {
+    fn name(&self) -> &'static str {
+        "stm32"
+    }
+
+    /// Sets the readout protection back to level 0, which mass erases the flash.
+    ///
+    /// The level is changed through the `RDP` config field of the target.
+    fn unlock(&self, session: &mut Session) -> Result<(), SequenceError> {
+        let level = read_field(session, RDP_FIELD)?;
+        if level == RDP_LEVEL_0 {
+            log::info!("The readout protection is not active.");
+            return Ok(());
+        }
+        if level == RDP_LEVEL_2 {
+            return Err(SequenceError::PermanentlyLocked);
+        }
+
+        log::info!("Removing the readout protection, which erases the flash...");
+        // Going back to level 0 is the point of unlocking, so the write is forced.
+        write_fields(session, &[(RDP_FIELD, RDP_LEVEL_0)], true)?;
+        log::info!("Readout protection removed, the chip has to be reset.");
+
+        Ok(())
+    }
+
+    fn post_attach(&self, session: &mut Session) -> Result<(), SequenceError> {
+        let value = session.probe.read32(DBGMCU_CR)?;
+        session
+            .probe
+            .write32(DBGMCU_CR, value | DBGMCU_CR_LOW_POWER)?;
+
+        Ok(())
+    }
+}
@@ -1,14 +1,130 @@
-use crate::config::target::Target;
+use crate::config::target::{Target, TargetCore};
+use crate::flash::FlashProgress;
 use crate::probe::MasterProbe;
+use crate::sequences::SequenceError;
+use crate::target::Core;
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// The target has no core with the given index.
+    CoreNotFound(usize),
+}
+
+impl Error for SessionError {}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SessionError::*;
+
+        match self {
+            CoreNotFound(index) => write!(f, "The target has no core with index {}.", index),
+        }
+    }
+}
 
 pub struct Session {
     pub target: Target,
     pub probe: MasterProbe,
+    current_core: usize,
 }
 
 impl Session {
     /// Open a new session with a given debug target
-    pub fn new(target: Target, probe: MasterProbe) -> Self {
-        Self { target, probe }
+    ///
+    /// The first core of the target is selected. `attach` has to be called before the target is used.
+    pub fn new(target: Target, mut probe: MasterProbe) -> Self {
+        let main_core = target.main_core();
+        main_core
+            .core
+            .select_port(&mut probe, main_core.ap, main_core.scs_base);
+
+        Self {
+            target,
+            probe,
+            current_core: 0,
+        }
+    }
+
+    /// Returns all cores of the target.
+    pub fn cores(&self) -> &[TargetCore] {
+        &self.target.cores
+    }
+
+    /// Returns the index of the selected core.
+    pub fn current_core(&self) -> usize {
+        self.current_core
+    }
+
+    /// Selects the core which is addressed by all following core operations.
+    pub fn select_core(&mut self, index: usize) -> Result<(), SessionError> {
+        let core = self
+            .target
+            .cores
+            .get(index)
+            .ok_or(SessionError::CoreNotFound(index))?;
+
+        core.core
+            .select_port(&mut self.probe, core.ap, core.scs_base);
+        self.current_core = index;
+
+        Ok(())
+    }
+
+    /// Returns the selected core.
+    ///
+    /// The returned handle does not borrow the session, so it can be used together
+    /// with the session probe, e.g. `session.core().halt(&mut session.probe)`.
+    pub fn core(&self) -> Box<dyn Core> {
+        self.target.cores[self.current_core].core.clone()
+    }
+
+    /// Selects the main core on the probe, without changing the selected core of the session.
+    ///
+    /// Use `restore_core_selection` to select the core of the session again.
+    pub(crate) fn select_main_core_port(&mut self) {
+        let main_core = self.target.main_core();
+        main_core
+            .core
+            .select_port(&mut self.probe, main_core.ap, main_core.scs_base);
+    }
+
+    /// Selects the selected core of the session on the probe again.
+    pub(crate) fn restore_core_selection(&mut self) {
+        let core = &self.target.cores[self.current_core];
+        core.core
+            .select_port(&mut self.probe, core.ap, core.scs_base);
+    }
+
+    /// Runs the post-attach sequence of the target.
+    ///
+    /// This has to be called once after the session is opened. A locked chip has to be
+    /// unlocked with `unlock` first.
+    pub fn attach(&mut self) -> Result<(), SequenceError> {
+        let sequence = self.target.debug_sequence.clone();
+        sequence.post_attach(self)
+    }
+
+    /// Unlocks the chip with the sequence of the target. This usually erases the entire chip.
+    pub fn unlock(&mut self) -> Result<(), SequenceError> {
+        let sequence = self.target.debug_sequence.clone();
+        sequence.unlock(self)
+    }
+
+    /// Erases the entire chip with the sequence of the target and reports the progress to `progress`.
+    pub fn mass_erase(&mut self, progress: &FlashProgress) -> Result<(), SequenceError> {
+        let sequence = self.target.debug_sequence.clone();
+        sequence.mass_erase(self, progress)
+    }
+
+    /// Resets the target, after running the pre-reset sequence of the target.
+    pub fn reset(&mut self) -> Result<(), SequenceError> {
+        let sequence = self.target.debug_sequence.clone();
+        sequence.pre_reset(self)?;
+        self.probe.target_reset()?;
+
+        Ok(())
     }
 }
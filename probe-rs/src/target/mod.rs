@@ -0,0 +1,326 @@
+pub mod info;
+
+use serde::de::{Error, Unexpected};
+
+use crate::{
+    cores::get_core,
+    probe::{DebugProbeError, MasterProbe},
+};
+
+pub trait CoreRegister: Clone + From<u32> + Into<u32> + Sized + std::fmt::Debug {
+    const ADDRESS: u32;
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoreRegisterAddress(pub u8);
+
+impl From<CoreRegisterAddress> for u32 {
+    fn from(value: CoreRegisterAddress) -> Self {
+        u32::from(value.0)
+    }
+}
+
+impl From<u8> for CoreRegisterAddress {
+    fn from(value: u8) -> Self {
+        CoreRegisterAddress(value)
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Copy, Clone)]
+pub struct BasicRegisterAddresses {
+    pub R0: CoreRegisterAddress,
+    pub R1: CoreRegisterAddress,
+    pub R2: CoreRegisterAddress,
+    pub R3: CoreRegisterAddress,
+    pub R4: CoreRegisterAddress,
+    pub R9: CoreRegisterAddress,
+    pub PC: CoreRegisterAddress,
+    pub LR: CoreRegisterAddress,
+    pub SP: CoreRegisterAddress,
+    pub XPSR: CoreRegisterAddress,
+}
+
+/// The group a core register belongs to, used to present the register file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterGroup {
+    /// The general purpose registers R0-R12, SP, LR and PC.
+    General,
+    /// Status, stack pointer and mask registers such as XPSR, MSP or PRIMASK.
+    Special,
+    /// Registers of the floating point extension.
+    FloatingPoint,
+    /// Banked registers of the security extension.
+    Security,
+}
+
+impl RegisterGroup {
+    pub fn name(self) -> &'static str {
+        match self {
+            RegisterGroup::General => "general",
+            RegisterGroup::Special => "special",
+            RegisterGroup::FloatingPoint => "fp",
+            RegisterGroup::Security => "security",
+        }
+    }
+}
+
+/// Describes a single register of a core.
+///
+/// Some registers do not have their own selector in the debug interface, but are
+/// packed into a wider transfer register (e.g. CONTROL and PRIMASK on Cortex-M).
+/// For those, `bit_offset` and `bit_width` describe where the register is located
+/// inside the transferred value.
+#[derive(Debug, Copy, Clone)]
+pub struct RegisterDescription {
+    pub name: &'static str,
+    pub group: RegisterGroup,
+    pub address: CoreRegisterAddress,
+    pub bit_offset: u8,
+    pub bit_width: u8,
+}
+
+impl RegisterDescription {
+    /// A full 32 bit wide register.
+    pub const fn new(name: &'static str, group: RegisterGroup, address: u8) -> Self {
+        Self {
+            name,
+            group,
+            address: CoreRegisterAddress(address),
+            bit_offset: 0,
+            bit_width: 32,
+        }
+    }
+
+    /// A register which is packed into a part of the transferred value.
+    pub const fn packed(
+        name: &'static str,
+        group: RegisterGroup,
+        address: u8,
+        bit_offset: u8,
+        bit_width: u8,
+    ) -> Self {
+        Self {
+            name,
+            group,
+            address: CoreRegisterAddress(address),
+            bit_offset,
+            bit_width,
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        if self.bit_width >= 32 {
+            0xFFFF_FFFF
+        } else {
+            (1 << self.bit_width) - 1
+        }
+    }
+
+    /// Returns true if the register only occupies a part of the transferred value.
+    pub fn is_packed(&self) -> bool {
+        self.bit_offset != 0 || self.bit_width < 32
+    }
+
+    /// Extracts the register value from the raw transferred value.
+    pub fn extract(&self, raw: u32) -> u32 {
+        (raw >> self.bit_offset) & self.mask()
+    }
+
+    /// Replaces the register value inside of the raw transferred value.
+    pub fn insert(&self, raw: u32, value: u32) -> u32 {
+        let mask = self.mask() << self.bit_offset;
+
+        (raw & !mask) | ((value << self.bit_offset) & mask)
+    }
+}
+
+/// The security state of a core implementing the ARMv8-M security extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecurityState {
+    Secure,
+    NonSecure,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoreInformation {
+    pub pc: u32,
+}
+
+pub trait Core: std::fmt::Debug + dyn_clone::DynClone {
+    /// Wait until the core is halted. If the core does not halt on its own,
+    /// a [`DebugProbeError::Timeout`] error will be returned.
+    ///
+    /// [`DebugProbeError::Timeout`]: ../probe/debug_probe/enum.DebugProbeError.html#variant.Timeout
+    fn wait_for_core_halted(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Try to halt the core. This function ensures the core is actually halted, and
+    /// returns a [`DebugProbeError::Timeout`] otherwise.
+    ///
+    /// [`DebugProbeError::Timeout`]: ../probe/debug_probe/enum.DebugProbeError.html#variant.Timeout
+    fn halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    fn run(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Reset the core, and then continue to execute instructions. If the core
+    /// should be halted after reset, use the [`reset_and_halt`] function.
+    ///
+    /// [`reset_and_halt`]: trait.Core.html#tymethod.reset_and_halt
+    fn reset(&self, mi: &mut MasterProbe) -> Result<(), DebugProbeError>;
+
+    /// Reset the core, and then immediately halt. To continue execution after
+    /// reset, use the [`reset`] function.
+    ///
+    /// [`reset`]: trait.Core.html#tymethod.reset
+    fn reset_and_halt(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    /// Steps one instruction and then enters halted state again.
+    fn step(&self, mi: &mut MasterProbe) -> Result<CoreInformation, DebugProbeError>;
+
+    fn read_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+    ) -> Result<u32, DebugProbeError>;
+
+    fn write_core_reg(
+        &self,
+        mi: &mut MasterProbe,
+        addr: CoreRegisterAddress,
+        value: u32,
+    ) -> Result<(), DebugProbeError>;
+
+    fn get_available_breakpoint_units(&self, mi: &mut MasterProbe) -> Result<u32, DebugProbeError>;
+
+    fn enable_breakpoints(&self, mi: &mut MasterProbe, state: bool) -> Result<(), DebugProbeError>;
+
+    fn set_breakpoint(&self, mi: &mut MasterProbe, addr: u32) -> Result<(), DebugProbeError>;
+
+    fn read_block8(
+        &self,
+        mi: &mut MasterProbe,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<(), DebugProbeError>;
+
+    fn registers<'a>(&self) -> &'a BasicRegisterAddresses;
+
+    /// Returns the descriptions of all registers which are accessible on this core.
+    fn register_file(&self) -> &'static [RegisterDescription];
+
+    /// Selects this core on the probe, so the `MI` implementation of the probe
+    /// accesses the memory as seen by this core.
+    fn select_port(&self, mi: &mut MasterProbe, ap: u8, scs_base: u32) {
+        mi.select_core_port(ap, scs_base);
+    }
+
+    /// Reads the security state the halted core is executing in.
+    ///
+    /// Returns `None` for cores which do not implement a security extension.
+    /// The state is only read on request, halting and stepping do not touch it.
+    fn security_state(
+        &self,
+        _mi: &mut MasterProbe,
+    ) -> Result<Option<SecurityState>, DebugProbeError> {
+        Ok(None)
+    }
+
+    /// Looks up a register by its name. The comparison is case insensitive.
+    fn register_by_name(&self, name: &str) -> Option<&'static RegisterDescription> {
+        self.register_file()
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Reads the value of a register from the register file.
+    fn read_register(
+        &self,
+        mi: &mut MasterProbe,
+        register: &RegisterDescription,
+    ) -> Result<u32, DebugProbeError> {
+        let raw = self.read_core_reg(mi, register.address)?;
+
+        Ok(register.extract(raw))
+    }
+
+    /// Writes the value of a register from the register file.
+    ///
+    /// Packed registers are written with a read-modify-write cycle,
+    /// so the other registers sharing the same transfer register are preserved.
+    fn write_register(
+        &self,
+        mi: &mut MasterProbe,
+        register: &RegisterDescription,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let raw = if register.is_packed() {
+            let current = self.read_core_reg(mi, register.address)?;
+            register.insert(current, value)
+        } else {
+            value
+        };
+
+        self.write_core_reg(mi, register.address, raw)
+    }
+}
+
+dyn_clone::clone_trait_object!(Core);
+
+struct CoreVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CoreVisitor {
+    type Value = Box<dyn Core>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "an existing core name")
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        if let Some(core) = get_core(s) {
+            Ok(core)
+        } else {
+            Err(Error::invalid_value(
+                Unexpected::Other(&format!("Core {} does not exist.", s)),
+                &self,
+            ))
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Box<dyn Core> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(CoreVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RegisterDescription, RegisterGroup};
+
+    #[test]
+    fn packed_register_extract() {
+        let basepri = RegisterDescription::packed("BASEPRI", RegisterGroup::Special, 20, 8, 8);
+
+        assert_eq!(basepri.extract(0x0301_A001), 0xA0);
+    }
+
+    #[test]
+    fn packed_register_insert_preserves_other_fields() {
+        let primask = RegisterDescription::packed("PRIMASK", RegisterGroup::Special, 20, 0, 1);
+
+        assert_eq!(primask.insert(0x0301_A000, 0xFF), 0x0301_A001);
+        assert_eq!(primask.insert(0x0301_A001, 0), 0x0301_A000);
+    }
+
+    #[test]
+    fn full_register_is_not_packed() {
+        let r0 = RegisterDescription::new("R0", RegisterGroup::General, 0);
+
+        assert!(!r0.is_packed());
+        assert_eq!(r0.extract(0xDEAD_BEEF), 0xDEAD_BEEF);
+    }
+}
@@ -0,0 +1,62 @@
+mod pdsc;
+
+use pdsc::{load_flm, parse_families, PdscError};
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "target-gen",
+    about = "Generates probe-rs target descriptions from an unpacked CMSIS-Pack"
+)]
+struct Opt {
+    /// The directory of the unpacked CMSIS-Pack, containing the .pdsc file
+    #[structopt(parse(from_os_str))]
+    pack: PathBuf,
+
+    /// The directory to write the target YAML files to
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let opt = Opt::from_args();
+
+    if let Err(e) = run(&opt.pack, &opt.output) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Converts all pack descriptions in `pack_dir` into one YAML file per family.
+fn run(pack_dir: &Path, output_dir: &Path) -> Result<(), PdscError> {
+    fs::create_dir_all(output_dir)?;
+
+    for entry in fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("pdsc") {
+            continue;
+        }
+
+        println!("Reading {}", path.display());
+        let pdsc = fs::read_to_string(&path)?;
+
+        for family in parse_families(&pdsc, |flm| load_flm(pack_dir, flm))? {
+            let file_name = output_dir.join(format!("{}.yaml", family.name));
+            serde_yaml::to_writer(File::create(&file_name)?, &family)?;
+
+            println!(
+                "Wrote {} with {} variants to {}",
+                family.name,
+                family.variants.len(),
+                file_name.display()
+            );
+        }
+    }
+
+    Ok(())
+}
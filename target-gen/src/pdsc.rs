@@ -0,0 +1,614 @@
+//! Conversion of the device tree of a CMSIS-Pack description (`.pdsc`) into chip families.
+
+use probe_rs::config::{
+    chip::{Chip, CoreDescription, DEFAULT_SCS_BASE},
+    chip_family::ChipFamily,
+    flash_algorithm::RawFlashAlgorithm,
+    flm::FlmError,
+    memory::{FlashRegion, GenericRegion, MemoryRegion, RamRegion, SectorDescription},
+};
+use roxmltree::{Document, Node};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PdscError {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    Yaml(serde_yaml::Error),
+    /// The FLM file at the contained path could not be loaded.
+    Flm(String, FlmError),
+    /// The element is missing the attribute.
+    MissingAttribute(String, &'static str),
+    InvalidNumber(String),
+    /// The element with the contained start and size exceeds the 32 bit address space.
+    AddressOverflow(String, u32, u32),
+}
+
+impl Error for PdscError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use PdscError::*;
+
+        match self {
+            Io(ref e) => Some(e),
+            Xml(ref e) => Some(e),
+            Yaml(ref e) => Some(e),
+            Flm(_, ref e) => Some(e),
+            MissingAttribute(..) => None,
+            InvalidNumber(_) => None,
+            AddressOverflow(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for PdscError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use PdscError::*;
+
+        match self {
+            Io(ref e) => e.fmt(f),
+            Xml(ref e) => e.fmt(f),
+            Yaml(ref e) => e.fmt(f),
+            Flm(path, ref e) => write!(f, "{}: {}", path, e),
+            MissingAttribute(element, attribute) => write!(
+                f,
+                "The <{}> element has no {} attribute.",
+                element, attribute
+            ),
+            InvalidNumber(value) => write!(f, "'{}' is not a valid number.", value),
+            AddressOverflow(element, start, size) => write!(
+                f,
+                "The <{}> element at {:#010x} with size {:#x} exceeds the address space.",
+                element, start, size
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for PdscError {
+    fn from(error: std::io::Error) -> Self {
+        PdscError::Io(error)
+    }
+}
+
+impl From<roxmltree::Error> for PdscError {
+    fn from(error: roxmltree::Error) -> Self {
+        PdscError::Xml(error)
+    }
+}
+
+impl From<serde_yaml::Error> for PdscError {
+    fn from(error: serde_yaml::Error) -> Self {
+        PdscError::Yaml(error)
+    }
+}
+
+/// A memory as declared by a `<memory>` element.
+#[derive(Debug, Clone)]
+struct Memory {
+    name: String,
+    range: Range<u32>,
+    is_ram: bool,
+    is_boot_memory: bool,
+}
+
+/// A flash algorithm as declared by an `<algorithm>` element.
+#[derive(Debug, Clone)]
+struct Algorithm {
+    /// The path of the FLM file, relative to the pack.
+    path: String,
+    range: Range<u32>,
+    default: bool,
+}
+
+/// The properties of a device, which are inherited from its family and subfamily.
+#[derive(Debug, Clone, Default)]
+struct DeviceProperties {
+    core: Option<String>,
+    memories: Vec<Memory>,
+    algorithms: Vec<Algorithm>,
+}
+
+impl DeviceProperties {
+    /// Returns the properties of the `node`, which override the inherited ones.
+    fn extend(&self, node: Node) -> Result<Self, PdscError> {
+        let mut properties = self.clone();
+
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "processor" => {
+                    if let Some(core) = child.attribute("Dcore") {
+                        properties.core = Some(core.to_owned());
+                    }
+                }
+                "memory" => {
+                    let memory = parse_memory(child)?;
+                    properties.memories.retain(|m| m.name != memory.name);
+                    properties.memories.push(memory);
+                }
+                "algorithm" => {
+                    let algorithm = parse_algorithm(child)?;
+                    properties.algorithms.retain(|a| a.path != algorithm.path);
+                    properties.algorithms.push(algorithm);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(properties)
+    }
+}
+
+/// Parses a decimal or hexadecimal number.
+fn parse_number(value: &str) -> Result<u32, PdscError> {
+    let value = value.trim();
+    let result = if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+    result.map_err(|_| PdscError::InvalidNumber(value.to_owned()))
+}
+
+fn attribute<'a>(node: Node<'a, '_>, name: &'static str) -> Result<&'a str, PdscError> {
+    node.attribute(name)
+        .ok_or_else(|| PdscError::MissingAttribute(node.tag_name().name().to_owned(), name))
+}
+
+/// Parses the `start` and `size` attributes of the `node` into an address range.
+fn parse_range(node: Node) -> Result<Range<u32>, PdscError> {
+    let start = parse_number(attribute(node, "start")?)?;
+    let size = parse_number(attribute(node, "size")?)?;
+
+    match start.checked_add(size) {
+        Some(end) => Ok(start..end),
+        None => Err(PdscError::AddressOverflow(
+            node.tag_name().name().to_owned(),
+            start,
+            size,
+        )),
+    }
+}
+
+fn parse_memory(node: Node) -> Result<Memory, PdscError> {
+    // Older packs use the `id` attribute, newer ones the `name` and `access` attributes.
+    let (name, is_ram) = match node.attribute("id") {
+        Some(id) => (id, id.starts_with("IRAM")),
+        None => {
+            let access = node.attribute("access").unwrap_or("rx");
+            (attribute(node, "name")?, access.contains('w'))
+        }
+    };
+
+    Ok(Memory {
+        name: name.to_owned(),
+        range: parse_range(node)?,
+        is_ram,
+        is_boot_memory: node.attribute("startup") == Some("1"),
+    })
+}
+
+fn parse_algorithm(node: Node) -> Result<Algorithm, PdscError> {
+    Ok(Algorithm {
+        path: attribute(node, "name")?.replace('\\', "/"),
+        range: parse_range(node)?,
+        default: node.attribute("default") == Some("1"),
+    })
+}
+
+/// Maps the `Dcore` of a processor to the core type of probe-rs.
+fn core_type(core: &str) -> Option<&'static str> {
+    match core {
+        "Cortex-M0" | "Cortex-M0+" | "Cortex-M1" | "Cortex-M23" | "SC000" => Some("M0"),
+        "Cortex-M3" | "Cortex-M4" | "Cortex-M7" | "SC300" => Some("M4"),
+        "Cortex-M33" | "Cortex-M35P" => Some("M33"),
+        _ => None,
+    }
+}
+
+/// Returns the name of the algorithm, which is the lowercase name of its FLM file.
+fn algorithm_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_else(|| path.to_ascii_lowercase())
+}
+
+/// Returns the sectors of `region` which lie inside the memory `range`, relative to its start.
+///
+/// A memory might only cover a part of the flash which is programmed by an algorithm.
+/// Groups outside of the memory are left out, and a group which begins before the memory
+/// is moved to the start of the memory.
+fn memory_sectors(region: &FlashRegion, range: &Range<u32>) -> Vec<SectorDescription> {
+    let base = u64::from(region.range.start);
+    let start = u64::from(range.start);
+    let end = u64::from(range.end);
+
+    let mut sectors = vec![];
+    for (i, group) in region.sectors.iter().enumerate() {
+        let group_start = base + u64::from(group.offset);
+        let group_end = match region.sectors.get(i + 1) {
+            Some(next) => base + u64::from(next.offset),
+            None => u64::MAX,
+        };
+        if group_end <= start || group_start >= end {
+            continue;
+        }
+
+        let first = group_start.max(start);
+        if group.size > 0 && (first - group_start) % u64::from(group.size) != 0 {
+            log::warn!(
+                "The memory at {:#010x} does not start at a sector boundary.",
+                range.start
+            );
+        }
+        sectors.push(SectorDescription {
+            offset: (first - start) as u32,
+            size: group.size,
+        });
+    }
+
+    sectors
+}
+
+/// Converts the families of a pack description into chip families.
+///
+/// The FLM files referenced by the pack are loaded with `load_algorithm`.
+/// Devices with unsupported cores are skipped.
+pub fn parse_families<F>(pdsc: &str, mut load_algorithm: F) -> Result<Vec<ChipFamily>, PdscError>
+where
+    F: FnMut(&str) -> Result<(RawFlashAlgorithm, FlashRegion), PdscError>,
+{
+    let document = Document::parse(pdsc)?;
+    let mut algorithms: HashMap<String, (RawFlashAlgorithm, FlashRegion)> = HashMap::new();
+
+    let mut families = vec![];
+    for family_node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("family"))
+    {
+        let family_name = attribute(family_node, "Dfamily")?;
+        let family_properties = DeviceProperties::default().extend(family_node)?;
+
+        // Devices are either direct children of the family or of its subfamilies.
+        let mut devices = vec![];
+        for child in family_node.children() {
+            if child.has_tag_name("device") {
+                devices.push((child, family_properties.clone()));
+            } else if child.has_tag_name("subFamily") {
+                let properties = family_properties.extend(child)?;
+                for device in child.children().filter(|node| node.has_tag_name("device")) {
+                    devices.push((device, properties.clone()));
+                }
+            }
+        }
+
+        let mut chips = vec![];
+        for (device, properties) in devices {
+            let properties = properties.extend(device)?;
+
+            let variants = device
+                .children()
+                .filter(|node| node.has_tag_name("variant"))
+                .collect::<Vec<_>>();
+            if variants.is_empty() {
+                chips.push((attribute(device, "Dname")?, properties));
+            } else {
+                for variant in variants {
+                    chips.push((attribute(variant, "Dvariant")?, properties.extend(variant)?));
+                }
+            }
+        }
+
+        let mut family = ChipFamily {
+            name: family_name.to_owned(),
+            manufacturer: None,
+            variants: vec![],
+            flash_algorithms: vec![],
+            core: String::new(),
+            debug_sequence: None,
+            config_regions: vec![],
+        };
+
+        for (name, properties) in chips {
+            let core = match properties.core.as_ref().and_then(|core| core_type(core)) {
+                Some(core) => core,
+                None => {
+                    log::warn!(
+                        "Skipping {} because its core {:?} is not supported.",
+                        name,
+                        properties.core
+                    );
+                    continue;
+                }
+            };
+
+            let mut memory_map = vec![];
+            for memory in &properties.memories {
+                let range = memory.range.clone();
+
+                if memory.is_ram {
+                    memory_map.push(MemoryRegion::Ram(RamRegion {
+                        range,
+                        is_boot_memory: memory.is_boot_memory,
+                    }));
+                    continue;
+                }
+
+                // The flash layout is taken from the algorithm which programs the memory.
+                let algorithm = properties
+                    .algorithms
+                    .iter()
+                    .find(|a| a.range.contains(&memory.range.start));
+                let algorithm = match algorithm {
+                    Some(algorithm) => algorithm,
+                    None => {
+                        memory_map.push(MemoryRegion::Generic(GenericRegion { range }));
+                        continue;
+                    }
+                };
+
+                if !algorithms.contains_key(&algorithm.path) {
+                    let loaded = load_algorithm(&algorithm.path)?;
+                    algorithms.insert(algorithm.path.clone(), loaded);
+                }
+                let (raw, region) = &algorithms[&algorithm.path];
+
+                memory_map.push(MemoryRegion::Flash(FlashRegion {
+                    range,
+                    is_boot_memory: memory.is_boot_memory,
+                    sectors: memory_sectors(region, &memory.range),
+                    ..region.clone()
+                }));
+
+                if !family
+                    .flash_algorithms
+                    .iter()
+                    .any(|a| a.name == algorithm_name(&algorithm.path))
+                {
+                    family.flash_algorithms.push(RawFlashAlgorithm {
+                        name: algorithm_name(&algorithm.path),
+                        default: algorithm.default,
+                        flash_range: Some(algorithm.range.clone()),
+                        ..raw.clone()
+                    });
+                }
+            }
+
+            if !memory_map
+                .iter()
+                .any(|region| matches!(region, MemoryRegion::Flash(_)))
+            {
+                log::warn!(
+                    "Skipping {} because none of its memories has a flash algorithm.",
+                    name
+                );
+                continue;
+            }
+
+            // The first supported core is the one of the family.
+            let cores = if family.core.is_empty() || family.core == core {
+                family.core = core.to_owned();
+                vec![]
+            } else {
+                vec![CoreDescription {
+                    name: "main".to_owned(),
+                    core_type: core.to_owned(),
+                    ap: 0,
+                    scs_base: DEFAULT_SCS_BASE,
+                }]
+            };
+
+            family.variants.push(Chip {
+                name: name.to_owned(),
+                part: None,
+                memory_map,
+                cores,
+                flash_algorithm_ram: None,
+            });
+        }
+
+        if family.variants.is_empty() {
+            log::warn!(
+                "Skipping the family {} without supported devices.",
+                family_name
+            );
+        } else {
+            families.push(family);
+        }
+    }
+
+    Ok(families)
+}
+
+/// Loads an FLM file of the pack in `pack_dir`.
+pub fn load_flm(
+    pack_dir: &Path,
+    path: &str,
+) -> Result<(RawFlashAlgorithm, FlashRegion), PdscError> {
+    let data = std::fs::read(pack_dir.join(path))?;
+
+    RawFlashAlgorithm::from_flm(&algorithm_name(path), &data)
+        .map_err(|e| PdscError::Flm(path.to_owned(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{memory_sectors, parse_families, PdscError};
+    use probe_rs::config::{
+        chip_family::ChipFamily,
+        flash_algorithm::RawFlashAlgorithm,
+        memory::{FlashRegion, MemoryRegion, SectorDescription},
+        registry::{Registry, SelectionStrategy},
+    };
+
+    const PDSC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package schemaVersion="1.4">
+  <devices>
+    <family Dfamily="Test Series" Dvendor="Test:1">
+      <processor Dcore="Cortex-M4" DcoreVersion="r0p1"/>
+      <memory id="IRAM1" start="0x20000000" size="0x8000" default="1"/>
+      <subFamily DsubFamily="Test1">
+        <device Dname="Test1_64">
+          <memory id="IROM1" start="0x08000000" size="0x8000" startup="1" default="1"/>
+          <memory id="IROM2" start="0x08008000" size="0x8000"/>
+          <algorithm name="Flash\Test_64.FLM" start="0x08000000" size="0x10000" default="1"/>
+        </device>
+      </subFamily>
+      <device Dname="Test2">
+        <processor Dcore="Cortex-M0+"/>
+        <memory name="Flash" access="rx" start="0x00000000" size="0x8000" startup="1"/>
+        <memory name="Backup" access="rw" start="0x40024000" size="4096"/>
+        <memory name="Data" access="rx" start="0x08000000" size="0x10000"/>
+        <algorithm name="Flash\Test_64.FLM" start="0x08000000" size="0x10000"/>
+        <variant Dvariant="Test2_A"/>
+        <variant Dvariant="Test2_B"/>
+      </device>
+      <device Dname="Test3">
+        <processor Dcore="Cortex-A7"/>
+      </device>
+      <device Dname="Test4">
+        <memory name="Backup" access="rw" start="0x40024000" size="4096"/>
+      </device>
+    </family>
+  </devices>
+</package>"#;
+
+    fn load_algorithm(path: &str) -> Result<(RawFlashAlgorithm, FlashRegion), PdscError> {
+        assert_eq!(path, "Flash/Test_64.FLM");
+
+        let algorithm = RawFlashAlgorithm {
+            name: "test_64".to_owned(),
+            description: "Test 64kB".to_owned(),
+            instructions: vec![0xE7FE_BE00; 8],
+            pc_program_page: 0x9,
+            pc_erase_sector: 0x11,
+            data_section_offset: 0x20,
+            ..Default::default()
+        };
+        let region = FlashRegion {
+            range: 0x0800_0000..0x0801_0000,
+            is_boot_memory: false,
+            sectors: vec![
+                SectorDescription {
+                    offset: 0x0,
+                    size: 0x400,
+                },
+                SectorDescription {
+                    offset: 0x8000,
+                    size: 0x800,
+                },
+            ],
+            page_size: 0x100,
+            erased_byte_value: 0xFF,
+        };
+        Ok((algorithm, region))
+    }
+
+    #[test]
+    fn parse_devices() {
+        let families = parse_families(PDSC, load_algorithm).unwrap();
+
+        assert_eq!(families.len(), 1);
+        let family = &families[0];
+        assert_eq!(family.name, "Test Series");
+        assert_eq!(family.core, "M4");
+
+        let names = family
+            .variants
+            .iter()
+            .map(|v| &v.name[..])
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Test1_64", "Test2_A", "Test2_B"]);
+
+        // The RAM is inherited from the family, the flash layout comes from the algorithm.
+        match &family.variants[0].memory_map[..] {
+            [MemoryRegion::Ram(ram), MemoryRegion::Flash(flash), MemoryRegion::Flash(flash2)] => {
+                assert_eq!(ram.range, 0x2000_0000..0x2000_8000);
+                assert_eq!(flash.range, 0x0800_0000..0x0800_8000);
+                assert!(flash.is_boot_memory);
+                // The group at 0x8000 lies behind the end of the first memory.
+                assert_eq!(
+                    flash.sectors,
+                    vec![SectorDescription {
+                        offset: 0,
+                        size: 0x400
+                    }]
+                );
+
+                // The sectors of the second memory are relative to its start.
+                assert_eq!(flash2.range, 0x0800_8000..0x0801_0000);
+                assert_eq!(
+                    flash2.sectors,
+                    vec![SectorDescription {
+                        offset: 0,
+                        size: 0x800
+                    }]
+                );
+            }
+            memory_map => panic!("Unexpected memory map {:?}", memory_map),
+        }
+        assert!(family.variants[0].cores.is_empty());
+
+        // A memory without an algorithm can't be flashed.
+        match &family.variants[1].memory_map[..] {
+            [MemoryRegion::Ram(_), MemoryRegion::Generic(_), MemoryRegion::Ram(backup), MemoryRegion::Flash(data)] =>
+            {
+                assert_eq!(backup.range, 0x4002_4000..0x4002_5000);
+                assert_eq!(data.sectors.len(), 2);
+            }
+            memory_map => panic!("Unexpected memory map {:?}", memory_map),
+        }
+        assert_eq!(family.variants[1].cores[0].core_type, "M0");
+
+        assert_eq!(family.flash_algorithms.len(), 1);
+        assert_eq!(family.flash_algorithms[0].name, "test_64");
+        assert!(family.flash_algorithms[0].default);
+    }
+
+    #[test]
+    fn clip_sectors_to_memory() {
+        let (_, region) = load_algorithm("Flash/Test_64.FLM").unwrap();
+
+        // A memory which starts inside the first group and ends inside the second one.
+        let sectors = memory_sectors(&region, &(0x0800_4000..0x0800_C000));
+        assert_eq!(
+            sectors,
+            vec![
+                SectorDescription {
+                    offset: 0,
+                    size: 0x400
+                },
+                SectorDescription {
+                    offset: 0x4000,
+                    size: 0x800
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn load_generated_yaml() {
+        let families = parse_families(PDSC, load_algorithm).unwrap();
+        let yaml = serde_yaml::to_string(&families[0]).unwrap();
+
+        let family: ChipFamily = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(family.variants.len(), 3);
+
+        let path = std::env::temp_dir().join(format!("target-gen-{}.yaml", std::process::id()));
+        std::fs::write(&path, &yaml).unwrap();
+        let mut registry = Registry::from_builtin_families();
+        let result = registry.add_target_from_yaml(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let target = registry
+            .get_target(SelectionStrategy::TargetIdentifier("Test1_64".into()))
+            .unwrap();
+        assert_eq!(target.flash_algorithms.len(), 2);
+    }
+}